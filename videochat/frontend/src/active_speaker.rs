@@ -0,0 +1,333 @@
+use js_sys::Array;
+use wasm_bindgen::JsValue;
+use web_sys::{AnalyserNode, AudioContext, MediaStream, MediaStreamAudioSourceNode, MediaStreamTrack};
+
+use crate::device_check::meter_level;
+use crate::signaling::Roster;
+
+/// How often audio levels are sampled, in milliseconds.
+pub const POLL_MS: u32 = 100;
+/// Level (0..1, as on the microphone meter) above which someone counts as speaking.
+const SPEAKING_LEVEL: f64 = 0.35;
+/// How long someone else has to be the loudest before the spotlight moves to them.
+const SWITCH_AFTER_MS: f64 = 800.0;
+/// Pauses shorter than this don't interrupt someone taking over.
+const PAUSE_MS: f64 = 500.0;
+/// How long the spotlight stays on someone who went quiet.
+const RELEASE_AFTER_MS: f64 = 2000.0;
+
+/// The video tiles of the call view.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tile {
+    Local,
+    Remote,
+}
+
+/// Whose audio is heard.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Speaker {
+    /// Our own microphone.
+    Local,
+    /// Another participant, by client ID.
+    Client(String),
+}
+
+impl Speaker {
+    /// The tile showing this speaker's video.
+    pub fn tile(&self) -> Tile {
+        match self {
+            Speaker::Local => Tile::Local,
+            Speaker::Client(_) => Tile::Remote,
+        }
+    }
+}
+
+struct Source {
+    speaker: Speaker,
+    analyser: AnalyserNode,
+    // Kept so the audio graph stays connected until the speaker is dropped.
+    source: MediaStreamAudioSourceNode,
+    samples: Vec<f32>,
+}
+
+/// Works out who is speaking from the audio of each participant, using Web Audio analysers.
+pub struct SpeakerDetector {
+    context: AudioContext,
+    sources: Vec<Source>,
+    selector: SpeakerSelector,
+}
+
+impl SpeakerDetector {
+    pub fn new() -> Result<Self, JsValue> {
+        let context = AudioContext::new()?;
+        // Browsers may start the context suspended; joining was a user gesture, so this is allowed.
+        let _ = context.resume();
+        Ok(SpeakerDetector { context, sources: Vec::new(), selector: SpeakerSelector::default() })
+    }
+
+    /// Listens to `track` (an audio track) for `speaker`, replacing what was heard from them before.
+    pub fn watch(&mut self, speaker: Speaker, track: &MediaStreamTrack) -> Result<(), JsValue> {
+        let stream = MediaStream::new_with_tracks(&Array::of1(track))?;
+        let source = self.context.create_media_stream_source(&stream)?;
+        let analyser = self.context.create_analyser()?;
+        analyser.set_fft_size(512);
+        source.connect_with_audio_node(&analyser)?;
+        let samples = vec![0.0; analyser.fft_size() as usize];
+        self.sources.retain(|source| source.speaker != speaker);
+        self.sources.push(Source { speaker, analyser, source, samples });
+        Ok(())
+    }
+
+    /// Stops listening to the participants not in `present` (client IDs), e.g. after they left.
+    pub fn retain_clients(&mut self, present: &[&str]) {
+        let selector = &mut self.selector;
+        self.sources.retain(|source| match &source.speaker {
+            Speaker::Local => true,
+            Speaker::Client(id) if present.contains(&id.as_str()) => true,
+            gone => {
+                selector.forget(gone);
+                let _ = source.source.disconnect();
+                false
+            }
+        });
+    }
+
+    /// Samples everyone and returns who holds the spotlight now (`now` in milliseconds).
+    pub fn poll(&mut self, now: f64) -> Option<Speaker> {
+        let loudest = self
+            .sources
+            .iter_mut()
+            .map(|source| {
+                source.analyser.get_float_time_domain_data(&mut source.samples);
+                (&source.speaker, meter_level(&source.samples))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(speaker, level)| (speaker.clone(), level));
+        self.selector.update(loudest, now)
+    }
+
+    pub fn close(&self) {
+        let _ = self.context.close();
+    }
+}
+
+/// Remote audio tracks waiting for the roster to say whose media stream they arrived in.
+/// Generic over the track so the bookkeeping works without a browser.
+pub struct RemoteAudio<T> {
+    /// Tracks by the ID of their stream.
+    unassigned: Vec<(String, T)>,
+}
+
+impl<T> Default for RemoteAudio<T> {
+    fn default() -> Self {
+        RemoteAudio { unassigned: Vec::new() }
+    }
+}
+
+impl<T> RemoteAudio<T> {
+    /// A track arrived in `stream`: returns it with its speaker when `roster` knows who sends
+    /// the stream, otherwise keeps it until a roster does.
+    pub fn arrived(&mut self, stream: String, track: T, roster: &Roster) -> Option<(Speaker, T)> {
+        match roster.sender_of_stream(&stream) {
+            Some(sender) => Some((Speaker::Client(sender.id.clone()), track)),
+            None => {
+                self.unassigned.retain(|(waiting, _)| *waiting != stream);
+                self.unassigned.push((stream, track));
+                None
+            }
+        }
+    }
+
+    /// The waiting tracks whose sender `roster` names, with their speakers.
+    pub fn assign(&mut self, roster: &Roster) -> Vec<(Speaker, T)> {
+        let mut assigned = Vec::new();
+        let mut waiting = Vec::new();
+        for (stream, track) in self.unassigned.drain(..) {
+            match roster.sender_of_stream(&stream) {
+                Some(sender) => assigned.push((Speaker::Client(sender.id.clone()), track)),
+                None => waiting.push((stream, track)),
+            }
+        }
+        self.unassigned = waiting;
+        assigned
+    }
+}
+
+/// Picks the active speaker from the loudest participant, with hysteresis so the spotlight
+/// doesn't flicker between people talking over each other or pausing for breath.
+#[derive(Default)]
+struct SpeakerSelector {
+    current: Option<Speaker>,
+    /// When `current` was last heard.
+    last_heard: f64,
+    /// Someone else who is speaking, since when, and when they were last heard.
+    challenger: Option<(Speaker, f64, f64)>,
+}
+
+impl SpeakerSelector {
+    fn update(&mut self, loudest: Option<(Speaker, f64)>, now: f64) -> Option<Speaker> {
+        if let Some((_, _, heard)) = self.challenger {
+            if now - heard > PAUSE_MS {
+                self.challenger = None;
+            }
+        }
+        match loudest.filter(|(_, level)| *level >= SPEAKING_LEVEL) {
+            Some((speaker, _)) if self.current.as_ref() == Some(&speaker) => {
+                self.last_heard = now;
+            }
+            Some((speaker, _)) => {
+                let since = match &self.challenger {
+                    Some((challenger, since, _)) if *challenger == speaker => *since,
+                    _ => now,
+                };
+                self.challenger = Some((speaker.clone(), since, now));
+                // Nobody holds the spotlight: take it right away.
+                if self.current.is_none() || now - since >= SWITCH_AFTER_MS {
+                    self.current = Some(speaker);
+                    self.last_heard = now;
+                    self.challenger = None;
+                }
+            }
+            None => {
+                if now - self.last_heard > RELEASE_AFTER_MS {
+                    self.current = None;
+                }
+            }
+        }
+        self.current.clone()
+    }
+
+    /// Drops `speaker`, e.g. because they left, so the spotlight doesn't stay on them.
+    fn forget(&mut self, speaker: &Speaker) {
+        if self.current.as_ref() == Some(speaker) {
+            self.current = None;
+        }
+        if matches!(&self.challenger, Some((challenger, _, _)) if challenger == speaker) {
+            self.challenger = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUD: f64 = 0.8;
+    const QUIET: f64 = 0.1;
+
+    fn client(id: &str) -> Speaker {
+        Speaker::Client(id.to_string())
+    }
+
+    /// A roster where client `id` sends stream `stream`, for each pair.
+    fn roster(streams: &[(&str, &str)]) -> Roster {
+        let participants = streams
+            .iter()
+            .map(|(id, stream)| serde_json::json!({ "id": id, "name": null, "role": "participant", "stream": stream }))
+            .collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({ "locked": false, "participants": participants })).unwrap()
+    }
+
+    #[test]
+    fn tracks_of_known_streams_go_to_their_sender() {
+        let mut audio = RemoteAudio::default();
+        let roster = roster(&[("1", "cam-a"), ("2", "cam-b")]);
+        assert_eq!(audio.arrived("cam-b".to_string(), "track-b", &roster), Some((client("2"), "track-b")));
+        assert_eq!(audio.arrived("cam-a".to_string(), "track-a", &roster), Some((client("1"), "track-a")));
+        assert!(audio.assign(&roster).is_empty());
+    }
+
+    #[test]
+    fn tracks_wait_for_the_roster_naming_their_sender() {
+        let mut audio = RemoteAudio::default();
+        assert_eq!(audio.arrived("cam-a".to_string(), "track-a", &roster(&[])), None);
+        assert_eq!(audio.arrived("cam-b".to_string(), "track-b", &roster(&[])), None);
+        // A newer track in the same stream replaces the one waiting.
+        assert_eq!(audio.arrived("cam-b".to_string(), "track-b2", &roster(&[])), None);
+
+        assert_eq!(audio.assign(&roster(&[("2", "cam-b")])), vec![(client("2"), "track-b2")]);
+        assert_eq!(audio.assign(&roster(&[("1", "cam-a"), ("2", "cam-b")])), vec![(client("1"), "track-a")]);
+        assert!(audio.assign(&roster(&[("1", "cam-a")])).is_empty());
+    }
+
+    /// Feeds `speaker` at `level` every `POLL_MS` from `from` until `to` (exclusive)
+    /// and returns who held the spotlight at the last poll.
+    fn feed(selector: &mut SpeakerSelector, speaker: &Speaker, level: f64, from: f64, to: f64) -> Option<Speaker> {
+        let mut current = selector.current.clone();
+        let mut now = from;
+        while now < to {
+            current = selector.update(Some((speaker.clone(), level)), now);
+            now += f64::from(POLL_MS);
+        }
+        current
+    }
+
+    #[test]
+    fn first_speaker_takes_the_spotlight_right_away() {
+        let mut selector = SpeakerSelector::default();
+        assert_eq!(selector.update(Some((client("a"), LOUD)), 0.0), Some(client("a")));
+    }
+
+    #[test]
+    fn quiet_audio_doesnt_count_as_speaking() {
+        let mut selector = SpeakerSelector::default();
+        assert_eq!(selector.update(Some((client("a"), QUIET)), 0.0), None);
+        assert_eq!(selector.update(None, 100.0), None);
+    }
+
+    #[test]
+    fn someone_else_takes_over_after_the_switch_threshold() {
+        let mut selector = SpeakerSelector::default();
+        feed(&mut selector, &Speaker::Local, LOUD, 0.0, 1000.0);
+        // Still ours just before the threshold…
+        assert_eq!(feed(&mut selector, &client("a"), LOUD, 1000.0, 1000.0 + SWITCH_AFTER_MS), Some(Speaker::Local));
+        // …and theirs once they kept talking that long.
+        assert_eq!(selector.update(Some((client("a"), LOUD)), 1000.0 + SWITCH_AFTER_MS), Some(client("a")));
+    }
+
+    #[test]
+    fn short_pauses_dont_reset_a_takeover() {
+        let mut selector = SpeakerSelector::default();
+        feed(&mut selector, &Speaker::Local, LOUD, 0.0, 100.0);
+        feed(&mut selector, &client("a"), LOUD, 100.0, 500.0);
+        // A breath shorter than the pause limit.
+        selector.update(Some((client("a"), QUIET)), 500.0);
+        assert_eq!(selector.update(Some((client("a"), LOUD)), 850.0), Some(Speaker::Local));
+        // Counted from when they started, not from after the pause.
+        assert_eq!(selector.update(Some((client("a"), LOUD)), 100.0 + SWITCH_AFTER_MS), Some(client("a")));
+    }
+
+    #[test]
+    fn long_pauses_start_a_takeover_over() {
+        let mut selector = SpeakerSelector::default();
+        feed(&mut selector, &Speaker::Local, LOUD, 0.0, 100.0);
+        feed(&mut selector, &client("a"), LOUD, 100.0, 500.0);
+        let back = 400.0 + PAUSE_MS + f64::from(POLL_MS);
+        // Past the switch threshold since they first started, but the count started again.
+        assert_eq!(feed(&mut selector, &client("a"), LOUD, back, back + SWITCH_AFTER_MS), Some(Speaker::Local));
+        assert_eq!(selector.update(Some((client("a"), LOUD)), back + SWITCH_AFTER_MS), Some(client("a")));
+    }
+
+    #[test]
+    fn spotlight_is_released_after_silence() {
+        let mut selector = SpeakerSelector::default();
+        selector.update(Some((client("a"), LOUD)), 0.0);
+        assert_eq!(selector.update(None, RELEASE_AFTER_MS), Some(client("a")));
+        assert_eq!(selector.update(Some((client("a"), QUIET)), RELEASE_AFTER_MS + 1.0), None);
+    }
+
+    #[test]
+    fn forgetting_the_speaker_releases_the_spotlight() {
+        let mut selector = SpeakerSelector::default();
+        selector.update(Some((client("a"), LOUD)), 0.0);
+        selector.update(Some((client("b"), LOUD)), 100.0);
+        selector.forget(&client("a"));
+        assert_eq!(selector.current, None);
+        // Someone still talking takes over right away.
+        assert_eq!(selector.update(Some((client("b"), LOUD)), 200.0), Some(client("b")));
+
+        selector.update(Some((client("c"), LOUD)), 300.0);
+        selector.forget(&client("c"));
+        assert!(selector.challenger.is_none());
+    }
+}
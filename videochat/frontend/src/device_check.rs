@@ -0,0 +1,377 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use gloo_timers::callback::{Interval, Timeout};
+use gloo_timers::future::TimeoutFuture;
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AudioContext, MediaStream, MessageEvent, RtcDataChannelEvent, RtcDataChannelState, RtcIceGatheringState,
+    RtcPeerConnection, RtcPeerConnectionIceEvent, RtcSdpType, RtcSessionDescriptionInit,
+};
+use yew::prelude::*;
+
+use crate::ice_configuration;
+
+/// How often the microphone meter updates, in milliseconds.
+const METER_INTERVAL_MS: u32 = 100;
+/// Quietest level the meter shows, in dBFS.
+const METER_FLOOR_DB: f64 = -60.0;
+/// Pitch and length of the speaker test tone.
+const TONE_HZ: f32 = 440.0;
+const TONE_SECS: f64 = 1.0;
+/// How long the loopback test may take to connect, and how long it sends data.
+const CONNECT_TIMEOUT_MS: f64 = 10_000.0;
+const MEASURE_MS: f64 = 3_000.0;
+/// Size of the chunks sent over the loopback data channel, and how much may be
+/// queued before waiting for the channel to catch up.
+const CHUNK_BYTES: usize = 16 * 1024;
+const MAX_BUFFERED_BYTES: u32 = 1024 * 1024;
+
+#[derive(Properties, PartialEq)]
+pub struct DeviceCheckProps {
+    /// The camera/microphone preview, once the user allowed access.
+    pub stream: Option<MediaStream>,
+}
+
+/// Self-test shown before joining: microphone level, a speaker test tone and a
+/// loopback network test.
+#[function_component(DeviceCheck)]
+pub fn device_check(props: &DeviceCheckProps) -> Html {
+    html! {
+        <div style="margin: 10px 0; padding: 10px; border: 1px solid #ddd; max-width: 480px;">
+            <h3 style="margin-top: 0;">{ "Check your setup" }</h3>
+            if let Some(stream) = props.stream.clone().filter(|stream| stream.get_audio_tracks().length() > 0) {
+                <MicMeter {stream} />
+            } else {
+                <p>{ "Microphone: not in use" }</p>
+            }
+            <SpeakerTest />
+            <NetworkTest />
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct MicMeterProps {
+    pub stream: MediaStream,
+}
+
+/// Live microphone level from a Web Audio `AnalyserNode`.
+#[function_component(MicMeter)]
+pub fn mic_meter(props: &MicMeterProps) -> Html {
+    // 0 (silence) to 1 (full scale).
+    let level = use_state(|| 0.0);
+
+    {
+        let level = level.clone();
+        use_effect_with_deps(
+            move |stream: &MediaStream| {
+                let context = AudioContext::new().ok();
+                let mut interval = None;
+                if let Some(context) = &context {
+                    match (context.create_media_stream_source(stream), context.create_analyser()) {
+                        (Ok(source), Ok(analyser)) => {
+                            analyser.set_fft_size(1024);
+                            let _ = source.connect_with_audio_node(&analyser);
+                            let mut samples = vec![0.0f32; analyser.fft_size() as usize];
+                            interval = Some(Interval::new(METER_INTERVAL_MS, move || {
+                                analyser.get_float_time_domain_data(&mut samples);
+                                level.set(meter_level(&samples));
+                            }));
+                        }
+                        _ => web_sys::console::error_1(&"Couldn't analyse the microphone".into()),
+                    }
+                }
+                move || {
+                    drop(interval);
+                    if let Some(context) = context {
+                        let _ = context.close();
+                    }
+                }
+            },
+            props.stream.clone(),
+        );
+    }
+
+    let percent = (*level * 100.0).round();
+    let color = if *level > 0.05 { "#2e7d32" } else { "#9e9e9e" };
+    html! {
+        <div style="margin-bottom: 10px;">
+            { "Microphone " }
+            <span style="display: inline-block; width: 200px; height: 10px; background: #eee; vertical-align: middle;">
+                <span style={format!("display: block; height: 100%; width: {}%; background: {};", percent, color)} />
+            </span>
+            if *level <= 0.05 {
+                <span style="margin-left: 8px; color: #666;">{ "Say something to test it" }</span>
+            }
+        </div>
+    }
+}
+
+/// Maps the RMS of `samples` from `METER_FLOOR_DB`..0 dBFS onto 0..1.
+pub fn meter_level(samples: &[f32]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mean_square = samples.iter().map(|s| f64::from(*s) * f64::from(*s)).sum::<f64>() / samples.len() as f64;
+    let db = 10.0 * mean_square.max(1e-12).log10();
+    ((db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0.0, 1.0)
+}
+
+/// Plays a short tone so the user can tell whether their speakers work.
+#[function_component(SpeakerTest)]
+pub fn speaker_test() -> Html {
+    let playing = use_state(|| false);
+    let played = use_state(|| false);
+
+    let onclick = {
+        let playing = playing.clone();
+        let played = played.clone();
+        Callback::from(move |_| {
+            if let Err(e) = play_tone(playing.clone()) {
+                web_sys::console::error_1(&format!("Speaker test failed: {:?}", e).into());
+                return;
+            }
+            playing.set(true);
+            played.set(true);
+        })
+    };
+
+    html! {
+        <div style="margin-bottom: 10px;">
+            <button {onclick} disabled={*playing}>{ "Test speakers" }</button>
+            if *played && !*playing {
+                <span style="margin-left: 8px; color: #666;">{ "Didn't hear a tone? Check your volume and output device." }</span>
+            }
+        </div>
+    }
+}
+
+/// Plays `TONE_HZ` for `TONE_SECS`, then resets `playing`.
+fn play_tone(playing: UseStateHandle<bool>) -> Result<(), JsValue> {
+    let context = AudioContext::new()?;
+    let oscillator = context.create_oscillator()?;
+    let gain = context.create_gain()?;
+    oscillator.frequency().set_value(TONE_HZ);
+    // Quiet enough not to startle anyone wearing headphones.
+    gain.gain().set_value(0.2);
+    oscillator.connect_with_audio_node(&gain)?;
+    gain.connect_with_audio_node(&context.destination())?;
+    oscillator.start()?;
+    oscillator.stop_with_when(context.current_time() + TONE_SECS)?;
+    Timeout::new((TONE_SECS * 1000.0) as u32 + 200, move || {
+        let _ = context.close();
+        playing.set(false);
+    })
+    .forget();
+    Ok(())
+}
+
+/// What the loopback network test found.
+#[derive(Clone, PartialEq, Debug, Default)]
+struct NetworkReport {
+    /// Candidate types gathered: `host`, `srflx` (via STUN), `relay` (via TURN).
+    candidate_types: BTreeSet<String>,
+    connect_ms: f64,
+    /// Throughput over the loopback data channel.
+    kbps: f64,
+}
+
+#[derive(Clone, PartialEq)]
+enum NetworkTestState {
+    Idle,
+    Running,
+    Done(NetworkReport),
+    Failed(String),
+}
+
+/// Connects two local peer connections through ICE and pushes data between them,
+/// reporting whether STUN/TURN candidates were gathered and the bitrate achieved.
+#[function_component(NetworkTest)]
+pub fn network_test() -> Html {
+    let state = use_state(|| NetworkTestState::Idle);
+
+    let onclick = {
+        let state = state.clone();
+        Callback::from(move |_| {
+            state.set(NetworkTestState::Running);
+            let state = state.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                state.set(match run_loopback().await {
+                    Ok(report) => NetworkTestState::Done(report),
+                    Err(message) => NetworkTestState::Failed(message),
+                });
+            });
+        })
+    };
+
+    let result = match &*state {
+        NetworkTestState::Idle => html! {},
+        NetworkTestState::Running => html! { <p>{ "Testing your connection…" }</p> },
+        NetworkTestState::Failed(message) => html! { <p style="color: #b00020;">{ message.clone() }</p> },
+        NetworkTestState::Done(report) => {
+            let has = |kind: &str| report.candidate_types.contains(kind);
+            html! {
+                <ul>
+                    <li>{ format!("Connected in {:.0} ms, about {:.1} Mbit/s", report.connect_ms, report.kbps / 1000.0) }</li>
+                    <li>{ if has("srflx") { "STUN: reachable" } else { "STUN: no public address found; calls outside your network may fail" } }</li>
+                    <li>{ if has("relay") { "TURN: relay available" } else { "TURN: no relay candidates" } }</li>
+                </ul>
+            }
+        }
+    };
+
+    html! {
+        <div>
+            <button {onclick} disabled={*state == NetworkTestState::Running}>{ "Test network" }</button>
+            { result }
+        </div>
+    }
+}
+
+/// The loopback test. Both peer connections are closed when it ends.
+async fn run_loopback() -> Result<NetworkReport, String> {
+    let config = ice_configuration();
+    let sender = RtcPeerConnection::new_with_configuration(&config).map_err(describe)?;
+    let receiver = RtcPeerConnection::new_with_configuration(&config).map_err(describe)?;
+    let result = measure(&sender, &receiver).await;
+    for pc in [&sender, &receiver] {
+        pc.set_onicecandidate(None);
+        pc.set_ondatachannel(None);
+        pc.close();
+    }
+    result
+}
+
+async fn measure(sender: &RtcPeerConnection, receiver: &RtcPeerConnection) -> Result<NetworkReport, String> {
+    let candidate_types = Rc::new(RefCell::new(BTreeSet::new()));
+    // Trickle each side's candidates to the other, noting their types on the way.
+    let forward_candidates = |from: &RtcPeerConnection, to: &RtcPeerConnection| {
+        let to = to.clone();
+        let candidate_types = candidate_types.clone();
+        let on_candidate = Closure::<dyn FnMut(RtcPeerConnectionIceEvent)>::new(move |evt: RtcPeerConnectionIceEvent| {
+            if let Some(candidate) = evt.candidate() {
+                if let Some(kind) = candidate_type(&candidate.candidate()) {
+                    candidate_types.borrow_mut().insert(kind);
+                }
+                let _ = to.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&candidate));
+            }
+        });
+        from.set_onicecandidate(Some(on_candidate.as_ref().unchecked_ref()));
+        on_candidate
+    };
+    let _sender_candidates = forward_candidates(sender, receiver);
+    let _receiver_candidates = forward_candidates(receiver, sender);
+
+    // Count what arrives on the receiving end.
+    let received = Rc::new(Cell::new(0usize));
+    let on_data_channel = {
+        let received = received.clone();
+        Closure::<dyn FnMut(RtcDataChannelEvent)>::new(move |evt: RtcDataChannelEvent| {
+            let received = received.clone();
+            let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |msg: MessageEvent| {
+                received.set(received.get() + msg.data().as_string().map_or(0, |data| data.len()));
+            });
+            evt.channel().set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            // Lives as long as the channel, which closes with the test.
+            on_message.forget();
+        })
+    };
+    receiver.set_ondatachannel(Some(on_data_channel.as_ref().unchecked_ref()));
+
+    let started = js_sys::Date::now();
+    let channel = sender.create_data_channel("network-check");
+    negotiate(sender, receiver).await.map_err(describe)?;
+    while channel.ready_state() != RtcDataChannelState::Open {
+        if js_sys::Date::now() - started > CONNECT_TIMEOUT_MS {
+            return Err("Couldn't connect. A firewall may be blocking WebRTC.".to_string());
+        }
+        TimeoutFuture::new(50).await;
+    }
+    let connect_ms = js_sys::Date::now() - started;
+
+    // Keep the channel busy for a while and see how much gets through.
+    let chunk = "x".repeat(CHUNK_BYTES);
+    let measure_start = js_sys::Date::now();
+    while js_sys::Date::now() - measure_start < MEASURE_MS {
+        while channel.buffered_amount() < MAX_BUFFERED_BYTES {
+            if channel.send_with_str(&chunk).is_err() {
+                return Err("The test connection dropped.".to_string());
+            }
+        }
+        TimeoutFuture::new(10).await;
+    }
+    let elapsed_secs = (js_sys::Date::now() - measure_start) / 1000.0;
+    let kbps = received.get() as f64 * 8.0 / 1000.0 / elapsed_secs;
+
+    // Give STUN/TURN a moment to answer if they haven't yet.
+    let gather_start = js_sys::Date::now();
+    while sender.ice_gathering_state() != RtcIceGatheringState::Complete && js_sys::Date::now() - gather_start < 5_000.0 {
+        TimeoutFuture::new(100).await;
+    }
+    channel.close();
+
+    let candidate_types = candidate_types.borrow().clone();
+    Ok(NetworkReport { candidate_types, connect_ms, kbps })
+}
+
+/// Offer from `offerer`, answer from `answerer`, without a signaling server in between.
+async fn negotiate(offerer: &RtcPeerConnection, answerer: &RtcPeerConnection) -> Result<(), JsValue> {
+    let offer = JsFuture::from(offerer.create_offer()).await?;
+    let offer = description(RtcSdpType::Offer, &offer)?;
+    JsFuture::from(offerer.set_local_description(&offer)).await?;
+    JsFuture::from(answerer.set_remote_description(&offer)).await?;
+    let answer = JsFuture::from(answerer.create_answer()).await?;
+    let answer = description(RtcSdpType::Answer, &answer)?;
+    JsFuture::from(answerer.set_local_description(&answer)).await?;
+    JsFuture::from(offerer.set_remote_description(&answer)).await?;
+    Ok(())
+}
+
+fn description(kind: RtcSdpType, created: &JsValue) -> Result<RtcSessionDescriptionInit, JsValue> {
+    let sdp = Reflect::get(created, &JsValue::from_str("sdp"))?.as_string().ok_or(JsValue::NULL)?;
+    let description = RtcSessionDescriptionInit::new(kind);
+    description.set_sdp(&sdp);
+    Ok(description)
+}
+
+/// The `typ` of an ICE candidate line, e.g. `host`, `srflx` or `relay`.
+fn candidate_type(candidate: &str) -> Option<String> {
+    let mut parts = candidate.split_whitespace();
+    parts.by_ref().find(|part| *part == "typ")?;
+    parts.next().map(str::to_string)
+}
+
+fn describe(e: JsValue) -> String {
+    web_sys::console::error_1(&e);
+    "The network test failed to start.".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meter_spans_the_floor_to_full_scale() {
+        assert_eq!(meter_level(&[]), 0.0);
+        assert_eq!(meter_level(&[0.0; 128]), 0.0);
+        // A full-scale square wave is 0 dBFS.
+        assert_eq!(meter_level(&[1.0, -1.0, 1.0, -1.0]), 1.0);
+        // -30 dBFS is halfway up a 60 dB meter.
+        let level = meter_level(&[10f32.powf(-1.5); 16]);
+        assert!((level - 0.5).abs() < 1e-6, "{}", level);
+    }
+
+    #[test]
+    fn reads_the_candidate_type() {
+        let candidate = |typ: &str| format!("candidate:842163049 1 udp 1677729535 203.0.113.7 52432 typ {} raddr 0.0.0.0 rport 0", typ);
+        assert_eq!(candidate_type(&candidate("srflx")).as_deref(), Some("srflx"));
+        assert_eq!(candidate_type(&candidate("relay")).as_deref(), Some("relay"));
+        assert_eq!(candidate_type("candidate:1 1 udp 2122260223 192.168.1.2 54400 typ host").as_deref(), Some("host"));
+        assert_eq!(candidate_type("candidate:1 1 udp 2122260223 192.168.1.2 54400"), None);
+        assert_eq!(candidate_type(""), None);
+    }
+}
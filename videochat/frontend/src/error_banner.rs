@@ -0,0 +1,71 @@
+use wasm_bindgen_futures::JsFuture;
+use web_sys::window;
+use yew::prelude::*;
+
+use crate::errors::ReportedError;
+
+#[derive(Properties, PartialEq)]
+pub struct ErrorBannerProps {
+    /// The error to show, if any.
+    pub error: Option<ReportedError>,
+    /// Copied to the clipboard by "Copy diagnostics".
+    pub diagnostics: String,
+    pub on_dismiss: Callback<()>,
+    /// Extra buttons, e.g. to retry.
+    #[prop_or_default]
+    pub children: Children,
+}
+
+/// Banner at the top of the call explaining the latest error, with a button that
+/// copies diagnostics for a bug report.
+#[function_component(ErrorBanner)]
+pub fn error_banner(props: &ErrorBannerProps) -> Html {
+    let copied = use_state(|| false);
+
+    // A new error gets a fresh "Copy diagnostics" button.
+    {
+        let copied = copied.clone();
+        use_effect_with_deps(move |_| copied.set(false), props.error.as_ref().map(|reported| reported.at));
+    }
+
+    let Some(reported) = props.error.clone() else {
+        return html! {};
+    };
+
+    let on_copy = {
+        let diagnostics = props.diagnostics.clone();
+        let copied = copied.clone();
+        Callback::from(move |_| {
+            let Some(window) = window() else {
+                return;
+            };
+            let promise = window.navigator().clipboard().write_text(&diagnostics);
+            let copied = copied.clone();
+            let diagnostics = diagnostics.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match JsFuture::from(promise).await {
+                    Ok(_) => copied.set(true),
+                    // No clipboard access (e.g. plain http): leave it in the console instead.
+                    Err(_) => web_sys::console::log_1(&format!("Diagnostics:\n{}", diagnostics).into()),
+                }
+            });
+        })
+    };
+    let on_dismiss = {
+        let on_dismiss = props.on_dismiss.clone();
+        Callback::from(move |_| on_dismiss.emit(()))
+    };
+
+    html! {
+        <div role="alert" style="background: #fdecea; color: #611a15; border: 1px solid #f5c6cb; padding: 10px 12px; margin: 10px 0;">
+            <strong>{ reported.error.message() }</strong>
+            <div style="margin-top: 8px;">
+                { for props.children.iter() }
+                <button onclick={on_copy} style="margin-right: 8px;">
+                    { if *copied { "Copied" } else { "Copy diagnostics" } }
+                </button>
+                <button onclick={on_dismiss}>{ "Dismiss" }</button>
+            </div>
+        </div>
+    }
+}
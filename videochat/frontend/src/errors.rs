@@ -0,0 +1,227 @@
+use std::fmt;
+use std::rc::Rc;
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
+use web_sys::{window, RtcPeerConnection};
+use yew::Reducible;
+
+use crate::media::MediaError;
+
+/// Something that went wrong in a call, grouped by where it happened.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CallError {
+    /// The camera or microphone couldn't be opened.
+    Media(MediaError),
+    /// Talking to the signaling server failed or the connection dropped.
+    Signaling(String),
+    /// Creating or applying an offer or answer failed.
+    Negotiation(String),
+    /// The peer connection couldn't be set up or lost connectivity.
+    Ice(String),
+}
+
+impl CallError {
+    pub fn signaling(context: &str, e: &JsValue) -> Self {
+        CallError::Signaling(format!("{}: {}", context, js_error_text(e)))
+    }
+
+    pub fn negotiation(context: &str, e: &JsValue) -> Self {
+        CallError::Negotiation(format!("{}: {}", context, js_error_text(e)))
+    }
+
+    pub fn ice(context: &str, e: &JsValue) -> Self {
+        CallError::Ice(format!("{}: {}", context, js_error_text(e)))
+    }
+
+    /// What to tell the user.
+    pub fn message(&self) -> &'static str {
+        match self {
+            CallError::Media(e) => e.message(),
+            CallError::Signaling(_) => "Lost the connection to the server. Check your internet connection and rejoin.",
+            CallError::Negotiation(_) => "Couldn't set up the call with the other participant. Try starting the call again.",
+            CallError::Ice(_) => {
+                "Couldn't connect to the other participant. A firewall or VPN may be blocking the call; try another network."
+            }
+        }
+    }
+
+    /// Technical details, for diagnostics.
+    pub fn detail(&self) -> String {
+        match self {
+            CallError::Media(e) => format!("media: {:?}", e),
+            CallError::Signaling(detail) => format!("signaling: {}", detail),
+            CallError::Negotiation(detail) => format!("negotiation: {}", detail),
+            CallError::Ice(detail) => format!("ice: {}", detail),
+        }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl From<MediaError> for CallError {
+    fn from(e: MediaError) -> Self {
+        CallError::Media(e)
+    }
+}
+
+/// The `name: message` of a JavaScript error, or its debug form for anything else.
+pub fn js_error_text(e: &JsValue) -> String {
+    let field = |key: &str| Reflect::get(e, &JsValue::from_str(key)).ok().and_then(|value| value.as_string());
+    match (field("name"), field("message")) {
+        (Some(name), Some(message)) => format!("{}: {}", name, message),
+        (None, Some(message)) => message,
+        _ => e.as_string().unwrap_or_else(|| format!("{:?}", e)),
+    }
+}
+
+/// An error and when it happened (milliseconds since the epoch).
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReportedError {
+    pub at: f64,
+    pub error: CallError,
+}
+
+/// Errors of the current call. A reducer, so callbacks created long ago can still
+/// add to it without overwriting each other.
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct ErrorLog {
+    /// Everything reported, oldest first, for diagnostics.
+    pub history: Vec<ReportedError>,
+    /// How many of `history` the user has dismissed.
+    pub dismissed: usize,
+}
+
+impl ErrorLog {
+    /// The latest error the user hasn't dismissed yet.
+    pub fn current(&self) -> Option<&ReportedError> {
+        self.history.get(self.dismissed..)?.last()
+    }
+
+    /// Adds `error`, reported at `at`, dropping the oldest past `MAX_HISTORY`.
+    fn push(&mut self, at: f64, error: CallError) {
+        self.history.push(ReportedError { at, error });
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+            self.dismissed = self.dismissed.saturating_sub(1);
+        }
+    }
+}
+
+pub enum ErrorAction {
+    Report(CallError),
+    Dismiss,
+}
+
+/// Most errors kept for diagnostics.
+const MAX_HISTORY: usize = 50;
+
+impl Reducible for ErrorLog {
+    type Action = ErrorAction;
+
+    fn reduce(self: Rc<Self>, action: ErrorAction) -> Rc<Self> {
+        let mut log = (*self).clone();
+        match action {
+            ErrorAction::Report(error) => {
+                web_sys::console::error_1(&format!("Call error: {}", error.detail()).into());
+                log.push(js_sys::Date::now(), error);
+            }
+            ErrorAction::Dismiss => log.dismissed = log.history.len(),
+        }
+        log.into()
+    }
+}
+
+/// Text to paste into a bug report: browser, room, connection states and every error so far.
+pub fn diagnostics(room: &str, my_id: Option<&str>, pc: Option<&RtcPeerConnection>, errors: &ErrorLog) -> String {
+    let user_agent = window().and_then(|window| window.navigator().user_agent().ok()).unwrap_or_default();
+    let mut fields = vec![
+        ("time", iso_time(js_sys::Date::now())),
+        ("user agent", user_agent),
+        ("room", room.to_string()),
+        ("client id", my_id.unwrap_or("-").to_string()),
+    ];
+    if let Some(pc) = pc {
+        fields.push(("signaling state", format!("{:?}", pc.signaling_state())));
+        fields.push(("ice connection state", format!("{:?}", pc.ice_connection_state())));
+        fields.push(("ice gathering state", format!("{:?}", pc.ice_gathering_state())));
+    }
+    format_diagnostics(&fields, errors, iso_time)
+}
+
+fn iso_time(at: f64) -> String {
+    String::from(js_sys::Date::new(&JsValue::from_f64(at)).to_iso_string())
+}
+
+/// Lays out the diagnostics: one `name: value` line per field, then every error with
+/// the time `timestamp` gives for it.
+fn format_diagnostics(fields: &[(&str, String)], errors: &ErrorLog, timestamp: impl Fn(f64) -> String) -> String {
+    let mut lines: Vec<String> = fields.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+    lines.push("errors:".to_string());
+    for reported in &errors.history {
+        lines.push(format!("  {} {}", timestamp(reported.at), reported.error.detail()));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_of(errors: &[&str]) -> ErrorLog {
+        let mut log = ErrorLog::default();
+        for (i, detail) in errors.iter().enumerate() {
+            log.push(i as f64 * 1000.0, CallError::Signaling(detail.to_string()));
+        }
+        log
+    }
+
+    #[test]
+    fn details_name_where_it_went_wrong() {
+        assert_eq!(CallError::Signaling("socket closed".to_string()).detail(), "signaling: socket closed");
+        assert_eq!(CallError::Negotiation("bad sdp".to_string()).detail(), "negotiation: bad sdp");
+        assert_eq!(CallError::Ice("failed".to_string()).detail(), "ice: failed");
+        assert_eq!(CallError::Ice("failed".to_string()).to_string(), CallError::Ice(String::new()).message());
+    }
+
+    #[test]
+    fn current_is_the_latest_error_not_dismissed() {
+        let mut log = log_of(&["first", "second"]);
+        assert_eq!(log.current().map(|reported| reported.at), Some(1000.0));
+        log.dismissed = log.history.len();
+        assert_eq!(log.current(), None);
+        log.push(5000.0, CallError::Ice("third".to_string()));
+        assert_eq!(log.current().map(|reported| reported.error.detail()).as_deref(), Some("ice: third"));
+    }
+
+    #[test]
+    fn history_keeps_the_latest_errors() {
+        let details: Vec<String> = (0..MAX_HISTORY + 5).map(|i| i.to_string()).collect();
+        let mut log = log_of(&details.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(log.history.len(), MAX_HISTORY);
+        assert_eq!(log.history[0].error.detail(), "signaling: 5");
+
+        log.dismissed = 3;
+        log.push(0.0, CallError::Ice("more".to_string()));
+        assert_eq!(log.dismissed, 2);
+    }
+
+    #[test]
+    fn formats_fields_then_errors() {
+        let fields = [("room", "standup".to_string()), ("client id", "-".to_string())];
+        let text = format_diagnostics(&fields, &log_of(&["socket closed", "reconnect failed"]), |at| format!("t{}", at));
+        assert_eq!(
+            text,
+            "room: standup\nclient id: -\nerrors:\n  t0 signaling: socket closed\n  t1000 signaling: reconnect failed"
+        );
+    }
+
+    #[test]
+    fn formats_an_empty_log() {
+        let text = format_diagnostics(&[("room", "standup".to_string())], &ErrorLog::default(), |at| at.to_string());
+        assert_eq!(text, "room: standup\nerrors:");
+    }
+}
@@ -0,0 +1,232 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use js_sys::Array;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{window, HtmlInputElement, HtmlSelectElement, HtmlVideoElement, MediaDeviceInfo, MediaDeviceKind, MediaStream};
+use yew::prelude::*;
+
+use crate::device_check::DeviceCheck;
+use crate::media::{open_local_media, JoinMode, MediaError};
+use crate::stop_stream;
+
+/// How the user chose to join in the green room.
+#[derive(Clone, PartialEq, Debug)]
+pub struct JoinChoice {
+    /// Display name; empty for none.
+    pub name: String,
+    pub mode: JoinMode,
+    /// Room password; empty for none.
+    pub password: String,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct GreenRoomProps {
+    pub room: String,
+    /// Display name to start with, e.g. from the landing page.
+    pub name: String,
+    /// Room password to start with, e.g. from the invite link.
+    pub password: String,
+    /// Why the server refused the last password, shown next to the password field.
+    #[prop_or_default]
+    pub password_error: Option<String>,
+    /// Called with the user's choices when they join.
+    pub on_join: Callback<JoinChoice>,
+}
+
+/// What the device check found.
+#[derive(Clone, PartialEq, Default)]
+struct Devices {
+    cameras: Vec<String>,
+    microphones: Vec<String>,
+    speakers: Vec<String>,
+}
+
+/// Pre-join screen: shows the camera preview and the devices found, and lets the user
+/// set their display name and what to send before joining. Nothing is sent to the
+/// signaling server here.
+#[function_component(GreenRoom)]
+pub fn green_room(props: &GreenRoomProps) -> Html {
+    let video_ref = use_node_ref();
+    let name = use_state(|| props.name.clone());
+    let password = use_state(|| props.password.clone());
+    let devices = use_state(|| Option::<Devices>::None);
+    let mode = use_state(JoinMode::default);
+    let media_error = use_state(|| Option::<MediaError>::None);
+    // Bumped by "Try again" to reopen the preview.
+    let attempt = use_state(|| 0u32);
+    // The preview stream, stopped when leaving so the call can take the camera.
+    let preview = use_mut_ref(|| Option::<MediaStream>::None);
+    // The same stream, for the microphone meter.
+    let preview_stream = use_state(|| Option::<MediaStream>::None);
+
+    {
+        let video_ref = video_ref.clone();
+        let devices = devices.clone();
+        let media_error = media_error.clone();
+        let preview = preview.clone();
+        let preview_stream = preview_stream.clone();
+        use_effect_with_deps(
+            move |(mode, _attempt): &(JoinMode, u32)| {
+                let mode = *mode;
+                media_error.set(None);
+                preview_stream.set(None);
+                // Set on unmount or when the mode changes, so a stream that arrives late is stopped right away.
+                let left = Rc::new(Cell::new(false));
+                let left_for_async = left.clone();
+                let preview_for_async = preview.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match open_local_media(mode).await {
+                        Ok(None) => {
+                            if let Some(video) = video_ref.cast::<HtmlVideoElement>() {
+                                video.set_src_object(None);
+                            }
+                        }
+                        Ok(Some(stream)) => {
+                            if left_for_async.get() {
+                                stop_stream(&stream);
+                                return;
+                            }
+                            if let Some(video) = video_ref.cast::<HtmlVideoElement>() {
+                                video.set_src_object(Some(&stream));
+                                let _ = video.play();
+                            }
+                            *preview_for_async.borrow_mut() = Some(stream.clone());
+                            preview_stream.set(Some(stream));
+                        }
+                        Err(e) => {
+                            web_sys::console::error_1(&format!("Camera preview failed: {:?}", e).into());
+                            if !left_for_async.get() {
+                                media_error.set(Some(e));
+                            }
+                        }
+                    }
+                    // Device labels are only filled in once the user allowed access.
+                    if let Ok(found) = list_devices().await {
+                        devices.set(Some(found));
+                    }
+                });
+                move || {
+                    left.set(true);
+                    if let Some(stream) = preview.borrow_mut().take() {
+                        stop_stream(&stream);
+                    }
+                }
+            },
+            (*mode, *attempt),
+        );
+    }
+
+    let on_name_input = {
+        let name = name.clone();
+        Callback::from(move |e: InputEvent| name.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let on_password_input = {
+        let password = password.clone();
+        Callback::from(move |e: InputEvent| password.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let on_mode_change = {
+        let mode = mode.clone();
+        Callback::from(move |e: Event| {
+            if let Some(selected) = JoinMode::from_key(&e.target_unchecked_into::<HtmlSelectElement>().value()) {
+                mode.set(selected);
+            }
+        })
+    };
+    let on_retry = {
+        let attempt = attempt.clone();
+        Callback::from(move |_| attempt.set(*attempt + 1))
+    };
+    let on_join = {
+        let name = name.clone();
+        let mode = mode.clone();
+        let password = password.clone();
+        let on_join = props.on_join.clone();
+        Callback::from(move |_| {
+            on_join.emit(JoinChoice { name: name.trim().to_string(), mode: *mode, password: (*password).clone() })
+        })
+    };
+
+    let device_list = |label: &str, names: &[String]| {
+        html! {
+            <li>
+                { format!("{}: ", label) }
+                if names.is_empty() {
+                    <span style="color: #b00020;">{ "none found" }</span>
+                } else {
+                    { names.join(", ") }
+                }
+            </li>
+        }
+    };
+
+    html! {
+        <div>
+            <h1>{ "Rust Video Chat" }</h1>
+            <h2>{ format!("Ready to join {}?", props.room) }</h2>
+            <video ref={video_ref} autoplay=true playsinline=true muted=true
+                style="width: 480px; height: 360px; background: #000;" />
+            if let Some(error) = (*media_error).clone() {
+                <p style="color: #b00020;">
+                    { error.to_string() }
+                    <button onclick={on_retry} style="margin-left: 10px;">{ "Try again" }</button>
+                </p>
+                <p>{ "You can also join with less, or just watch and listen." }</p>
+            }
+            if let Some(found) = (*devices).clone() {
+                <ul>
+                    { device_list("Cameras", &found.cameras) }
+                    { device_list("Microphones", &found.microphones) }
+                    { device_list("Speakers", &found.speakers) }
+                </ul>
+            } else {
+                <p>{ "Checking your devices…" }</p>
+            }
+            <DeviceCheck stream={(*preview_stream).clone()} />
+            <p>
+                <label>
+                    { "Your name " }
+                    <input type="text" placeholder="Guest" maxlength="64" value={(*name).clone()} oninput={on_name_input} />
+                </label>
+            </p>
+            <p>
+                <label>
+                    { "Room password " }
+                    <input type="password" placeholder="if the room has one" value={(*password).clone()}
+                        oninput={on_password_input} />
+                </label>
+                if let Some(error) = props.password_error.clone() {
+                    <span style="color: #b00020; margin-left: 10px;">{ error }</span>
+                }
+            </p>
+            <p>
+                <label>
+                    { "Join with " }
+                    <select onchange={on_mode_change}>
+                        { for JoinMode::ALL.iter().map(|option| html! {
+                            <option value={option.key()} selected={*option == *mode}>{ option.label() }</option>
+                        }) }
+                    </select>
+                </label>
+            </p>
+            <button onclick={on_join} style="padding: 10px 20px;">{ "Join" }</button>
+        </div>
+    }
+}
+
+async fn list_devices() -> Result<Devices, JsValue> {
+    let media_devices = window().ok_or(JsValue::NULL)?.navigator().media_devices()?;
+    let infos: Array = JsFuture::from(media_devices.enumerate_devices()?).await?.dyn_into()?;
+    let mut devices = Devices::default();
+    for info in infos.iter().filter_map(|info| info.dyn_into::<MediaDeviceInfo>().ok()) {
+        let list = match info.kind() {
+            MediaDeviceKind::Videoinput => &mut devices.cameras,
+            MediaDeviceKind::Audioinput => &mut devices.microphones,
+            MediaDeviceKind::Audiooutput => &mut devices.speakers,
+            _ => continue,
+        };
+        let label = info.label();
+        list.push(if label.is_empty() { format!("device {}", list.len() + 1) } else { label });
+    }
+    Ok(devices)
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use web_sys::{window, HtmlInputElement};
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::router::Route;
+use crate::visitor_counter::VisitorCounter;
+
+/// Start page: pick a display name, then start a new room or join one by name or link.
+#[function_component(Landing)]
+pub fn landing() -> Html {
+    let navigator = use_navigator();
+    let name = use_state(String::new);
+    let room = use_state(String::new);
+    let error = use_state(|| Option::<String>::None);
+
+    // Goes to the green room of `id`, carrying the display name along.
+    let go_to_room = {
+        let name = name.clone();
+        move |id: String| {
+            let Some(navigator) = navigator.clone() else {
+                return;
+            };
+            let mut query = HashMap::new();
+            if !name.trim().is_empty() {
+                query.insert("name", name.trim().to_string());
+            }
+            let route = Route::Room { id };
+            if navigator.push_with_query(&route, &query).is_err() {
+                navigator.push(&route);
+            }
+        }
+    };
+
+    let on_name_input = {
+        let name = name.clone();
+        Callback::from(move |e: InputEvent| name.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let on_room_input = {
+        let room = room.clone();
+        Callback::from(move |e: InputEvent| room.set(e.target_unchecked_into::<HtmlInputElement>().value()))
+    };
+    let on_create = {
+        let go_to_room = go_to_room.clone();
+        let error = error.clone();
+        Callback::from(move |_| match new_room_id() {
+            Some(id) => go_to_room(id),
+            None => error.set(Some("Couldn't pick a room name. Enter one to join instead.".to_string())),
+        })
+    };
+    let on_join = {
+        let room = room.clone();
+        let error = error.clone();
+        Callback::from(move |_| match parse_room(&room) {
+            Some(id) => go_to_room(id),
+            None => error.set(Some("Enter a room name (letters, digits, - and _) or a room link.".to_string())),
+        })
+    };
+
+    html! {
+        <div>
+            <h1>{ "Rust Video Chat" }</h1>
+            <p>
+                <label>
+                    { "Your name " }
+                    <input type="text" placeholder="Guest" maxlength="64" value={(*name).clone()} oninput={on_name_input} />
+                </label>
+            </p>
+            <p>
+                <button onclick={on_create} style="padding: 10px 20px;">{ "Start a new room" }</button>
+            </p>
+            <p>
+                <input type="text" placeholder="Room name or link" value={(*room).clone()} oninput={on_room_input} />
+                <button onclick={on_join} style="margin-left: 10px;">{ "Join" }</button>
+            </p>
+            if let Some(message) = (*error).clone() {
+                <p style="color: #b00020;">{ message }</p>
+            }
+            <VisitorCounter />
+        </div>
+    }
+}
+
+/// A room name nobody is likely to guess: 64 bits from the browser's secure random
+/// number generator. `None` if it isn't available.
+fn new_room_id() -> Option<String> {
+    let mut bytes = [0u8; 8];
+    window()?.crypto().ok()?.get_random_values_with_u8_array(&mut bytes).ok()?;
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Takes a room name, or the name out of a `/room/{id}` link.
+fn parse_room(input: &str) -> Option<String> {
+    let input = input.trim();
+    let id = match input.find("/room/") {
+        Some(start) => input[start + "/room/".len()..].split(['/', '?', '#']).next().unwrap_or(""),
+        None => input,
+    };
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_a_room_name() {
+        assert_eq!(parse_room("standup").as_deref(), Some("standup"));
+        assert_eq!(parse_room("  team_sync-2  ").as_deref(), Some("team_sync-2"));
+    }
+
+    #[test]
+    fn takes_the_room_out_of_a_link() {
+        assert_eq!(parse_room("https://chat.example.com/room/standup").as_deref(), Some("standup"));
+        assert_eq!(parse_room("https://chat.example.com/room/standup?name=Ada").as_deref(), Some("standup"));
+        assert_eq!(parse_room("/room/standup/#top").as_deref(), Some("standup"));
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_room() {
+        assert_eq!(parse_room(""), None);
+        assert_eq!(parse_room("   "), None);
+        assert_eq!(parse_room("no spaces"), None);
+        assert_eq!(parse_room("https://chat.example.com/room/"), None);
+        assert_eq!(parse_room(&"a".repeat(65)), None);
+    }
+}
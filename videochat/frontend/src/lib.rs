@@ -1,34 +1,76 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    HtmlVideoElement, MediaStream, MediaStreamConstraints, window, WebSocket,
+    HtmlVideoElement, MediaStream, window, WebSocket,
     RtcPeerConnection, RtcConfiguration, RtcPeerConnectionIceEvent, RtcIceCandidateInit,
     MessageEvent, MediaStreamTrack, RtcSessionDescriptionInit, RtcSdpType, RtcTrackEvent,
-    RtcIceCandidate,
+    RtcIceCandidate, RtcIceConnectionState, CloseEvent,
 };
 use yew::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use serde_json;
 use js_sys::{Array, Reflect};
 
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use yew_router::prelude::*;
 mod visitor_counter;
+mod participants;
 mod signaling;
+mod router;
+mod landing;
+mod green_room;
+mod device_check;
+mod media;
+mod errors;
+mod error_banner;
+mod active_speaker;
+use participants::ParticipantList;
+use router::{switch, Route};
 use visitor_counter::VisitorCounter;
-use web_sys::MediaTrackConstraints;
-use signaling::{IceCandidateData, SignalMessage};
+use media::{add_receive_transceivers, open_local_media, receive_only, JoinMode, MediaError};
+use errors::{diagnostics, CallError, ErrorAction, ErrorLog};
+use error_banner::ErrorBanner;
+use active_speaker::{RemoteAudio, Speaker, SpeakerDetector, Tile, POLL_MS};
+use gloo_timers::callback::Interval;
+use signaling::{
+    create_websocket, encode, send_signal, signaling_url, IceCandidateData, ServerOrigin, ModeratorCommand, Roster, RosterEntry,
+    SignalMessage,
+};
 
 #[function_component(App)]
 fn app() -> Html {
+    html! {
+        <BrowserRouter>
+            <Switch<Route> render={switch} />
+        </BrowserRouter>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CallViewProps {
+    pub room: String,
+    /// Display name sent to the server; empty for none.
+    pub name: String,
+    /// What we send: camera, microphone, both or nothing.
+    pub mode: JoinMode,
+    /// Room password sent to the server; empty for none.
+    pub password: String,
+    /// Called with the server's message when it refuses the password.
+    pub on_password_refused: Callback<String>,
+}
+
+/// The call itself. Grabs the camera and connects to the signaling server when
+/// mounted, and hangs up when unmounted.
+#[function_component(CallView)]
+pub fn call_view(props: &CallViewProps) -> Html {
     // Reference to the local video element (displaying our own stream)
     let video_ref = use_node_ref();
     // Reference to the remote video element (displaying the remote stream)
     let remote_video_ref = use_node_ref();
     
     // Use a mutable reference to store the WebSocket so that closures can always access the latest instance.
-    // (Replaces a use_state approach which can capture stale values.)
-    let ws_ref = use_mut_ref(|| Option::<WebSocket>::None); // <<-- CHANGED
+    // A use_state would hand closures the value from when they were created.
+    let ws_ref = use_mut_ref(|| Option::<WebSocket>::None);
 
     // Store the RTCPeerConnection instance in a mutable reference
     let pc_ref = use_mut_ref::<Option<RtcPeerConnection>, _>(|| None);
@@ -36,15 +78,57 @@ fn app() -> Html {
     let ice_candidate_queue = Rc::new(RefCell::new(Vec::<String>::new()));
     // A state to ensure the call is initiated only once.
     let offer_sent = use_state(|| false);
-    let local_track_ids = Rc::new(RefCell::new(Vec::<String>::new()));  
+    // Error or notice from the signaling server (room full, restarting, ...), shown to the user.
+    let server_notice = use_state(|| Option::<String>::None);
+    let server_notice_for_view = server_notice.clone();
+    // Latest announcement from the server operators.
+    let announcement = use_state(|| Option::<String>::None);
+    let announcement_for_view = announcement.clone();
+    // Our own ID and the room roster, used for the participant tiles.
+    let my_id = use_state(|| Option::<String>::None);
+    let roster = use_state(Roster::default);
+    let my_id_for_view = my_id.clone();
+    let roster_for_view = roster.clone();
+    // Whether we are held in the lobby, and (for hosts) who else is.
+    let waiting = use_state(|| false);
+    let waiting_for_view = waiting.clone();
+    let lobby = use_state(Vec::<RosterEntry>::new);
+    let lobby_for_view = lobby.clone();
+    // The camera/microphone stream, kept so the microphone can be muted later.
+    let local_stream = use_mut_ref(|| Option::<MediaStream>::None);
+    let mic_muted = use_state(|| false);
+    let mic_muted_for_view = mic_muted.clone();
+    let local_track_ids = use_mut_ref(Vec::<String>::new);
+    // What went wrong so far; the latest error is shown until dismissed.
+    let errors = use_reducer(ErrorLog::default);
+    let report = {
+        let errors = errors.dispatcher();
+        Callback::from(move |e: CallError| errors.dispatch(ErrorAction::Report(e)))
+    };
+    // Set when leaving the call view, for work that finishes afterwards.
+    let hung_up = use_mut_ref(|| false);
+    // Who is speaking, judged from the audio of everyone in the call, and whose video is
+    // enlarged in the spotlight layout (the last one who spoke).
+    let speaker_detector = use_mut_ref(|| Option::<SpeakerDetector>::None);
+    let speaker_poll = use_mut_ref(|| Option::<Interval>::None);
+    let active_speaker = use_state_eq(|| Option::<Speaker>::None);
+    // The roster, for callbacks that outlive the render, and the remote audio tracks whose
+    // sender it didn't name yet.
+    let roster_now = use_mut_ref(Roster::default);
+    let remote_audio = use_mut_ref(RemoteAudio::<MediaStreamTrack>::default);
+    let spotlit = use_state_eq(|| Tile::Remote);
+    let active_speaker_for_view = active_speaker.clone();
+    let spotlit_for_view = spotlit.clone();
+    let spotlight = use_state(|| false);
     let offer_sent_for_button = offer_sent.clone();
 
     // Callback for when the "Start Call" button is pressed.
     let on_start_call = {
         let pc_ref = pc_ref.clone();
         // Capture the WebSocket mutable reference so that we always use the current value.
-        let ws_ref_clone = ws_ref.clone(); // <<-- CHANGED
+        let ws_ref_clone = ws_ref.clone();
         let offer_sent_for_call = offer_sent.clone();
+        let report = report.clone();
         
         Callback::from(move |_| {
             web_sys::console::log_1(&"Start Call button pressed".into());
@@ -55,93 +139,200 @@ fn app() -> Html {
                 if let Some(pc) = pc_ref.borrow().clone() {
                     let pc_clone = pc.clone();
                     // Get the current WebSocket instance from ws_ref.
-                    let ws_clone = (*ws_ref_clone.borrow()).clone(); // <<-- CHANGED
+                    let ws_clone = (*ws_ref_clone.borrow()).clone();
                     
-                    wasm_bindgen_futures::spawn_local(async move {
-                        // Create an SDP offer
-                        let offer_js = JsFuture::from(pc_clone.create_offer())
-                            .await
-                            .expect("Offer creation failed");
-
-                        // Extract the SDP string from the offer object.
-                        let sdp = Reflect::get(&offer_js, &JsValue::from_str("sdp"))
-                            .expect("No sdp field")
-                            .as_string()
-                            .expect("sdp field is not a string");
-
-                        web_sys::console::log_1(&format!("Created offer with sdp: {}", sdp).into());
-                        let offer = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-                        offer.set_sdp(&sdp);
-
-                        // Set the local description with the created offer.
-                        let set_ld = pc_clone.set_local_description(&offer);
-                        let _ = JsFuture::from(set_ld).await;
-
-                        // If the WebSocket is available, send the offer message.
-                        if let Some(ws) = ws_clone.as_ref() {
-                            let sdp = Reflect::get(&offer, &JsValue::from_str("sdp"))
-                                .unwrap()
-                                .as_string()
-                                .unwrap();
-                            let msg = SignalMessage::Offer(sdp);
-                            let msg_json = serde_json::to_string(&msg).unwrap();
-                            web_sys::console::log_1(&format!("Sending Offer message: {}", msg_json).into());
-                            ws.send_with_str(&msg_json).ok();
+                    send_offer(pc_clone, ws_clone, report.clone());
+                }
+            }
+        })
+    };
+
+    // Asks for the camera/microphone again after it failed. If the call already
+    // started, a new offer tells the others about the tracks.
+    let on_retry_media = {
+        let pc_ref = pc_ref.clone();
+        let ws_ref = ws_ref.clone();
+        let video_ref = video_ref.clone();
+        let local_stream = local_stream.clone();
+        let local_track_ids = local_track_ids.clone();
+        let hung_up = hung_up.clone();
+        let speaker_detector = speaker_detector.clone();
+        let errors = errors.dispatcher();
+        let report = report.clone();
+        let call_started = *offer_sent;
+        let mode = props.mode;
+        Callback::from(move |_| {
+            let Some(pc) = pc_ref.borrow().clone() else {
+                return;
+            };
+            let ws = ws_ref.borrow().clone();
+            let video_ref = video_ref.clone();
+            let local_stream = local_stream.clone();
+            let local_track_ids = local_track_ids.clone();
+            let hung_up = hung_up.clone();
+            let speaker_detector = speaker_detector.clone();
+            let errors = errors.clone();
+            let report = report.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let attached =
+                    attach_local_media(mode, &pc, &video_ref, &local_stream, &local_track_ids, &hung_up, &speaker_detector)
+                        .await;
+                match attached {
+                    Ok(()) => {
+                        errors.dispatch(ErrorAction::Dismiss);
+                        if call_started {
+                            send_offer(pc, ws, report);
                         }
-                    });
+                    }
+                    Err(e) => report.emit(e.into()),
+                }
+            });
+        })
+    };
+    let on_dismiss_error = {
+        let errors = errors.dispatcher();
+        Callback::from(move |_| errors.dispatch(ErrorAction::Dismiss))
+    };
+
+    // Sends a moderator command; the server ignores it unless we are a host.
+    let on_moderator_command = {
+        let ws_ref = ws_ref.clone();
+        let report = report.clone();
+        Callback::from(move |command: ModeratorCommand| {
+            if let Some(ws) = ws_ref.borrow().as_ref() {
+                if let Err(e) = send_signal(ws, &SignalMessage::Moderate(command)) {
+                    report.emit(e);
                 }
             }
         })
     };
 
+    // Mutes or unmutes our own microphone.
+    let on_toggle_mute = {
+        let local_stream = local_stream.clone();
+        let mic_muted = mic_muted.clone();
+        Callback::from(move |_| {
+            let muted = !*mic_muted;
+            if let Some(stream) = local_stream.borrow().as_ref() {
+                set_audio_enabled(stream, !muted);
+            }
+            mic_muted.set(muted);
+        })
+    };
+
+    // Back to the landing page; unmounting the call view hangs up.
+    let on_leave = {
+        let navigator = use_navigator();
+        Callback::from(move |_| {
+            if let Some(navigator) = &navigator {
+                navigator.push(&Route::Landing);
+            }
+        })
+    };
+
+    let on_toggle_spotlight = {
+        let spotlight = spotlight.clone();
+        Callback::from(move |_| spotlight.set(!*spotlight))
+    };
+
     {
         // Clone references for use in the effect.
         let video_ref_clone = video_ref.clone();
         let remote_video_ref_clone = remote_video_ref.clone();
         // Use ws_ref instead of a state so that we always access the current WebSocket instance.
-        let ws_ref_inner = ws_ref.clone(); // <<-- CHANGED
+        let ws_ref_inner = ws_ref.clone();
         let pc_ref_inner = pc_ref.clone();
+        let ws_ref_for_cleanup = ws_ref.clone();
+        let pc_ref_for_cleanup = pc_ref.clone();
+        let local_stream_for_cleanup = local_stream.clone();
+        let hung_up_for_cleanup = hung_up.clone();
+        let speaker_detector_for_cleanup = speaker_detector.clone();
+        let speaker_poll_for_cleanup = speaker_poll.clone();
+        let room = props.room.clone();
+        let name = props.name.clone();
+        let password = props.password.clone();
+        let on_password_refused = props.on_password_refused.clone();
+        let mode = props.mode;
     
         use_effect_with_deps(
             move |_| {
-                // Create the configuration for the RTCPeerConnection.
-                let config = {
-                    let config = RtcConfiguration::new();
-                    let ice_server = {
-                        let server = web_sys::RtcIceServer::new();
-                        server.set_urls(&JsValue::from_str("stun:stun.l.google.com:19302"));
-                        server.set_credential("");
-                        server.set_username("");
-                        server
-                    };
-                    let ice_servers = Array::new();
-                    ice_servers.push(&ice_server);
-                    config.set_ice_servers(&ice_servers);
-                    config.set_ice_transport_policy(web_sys::RtcIceTransportPolicy::All);
-                    config
+                // Hang up when leaving the call view: close the socket so the server drops
+                // us from the room, and give the camera and microphone back.
+                let cleanup = move || {
+                    *hung_up_for_cleanup.borrow_mut() = true;
+                    if let Some(ws) = ws_ref_for_cleanup.borrow_mut().take() {
+                        ws.set_onmessage(None);
+                        ws.set_onclose(None);
+                        let _ = ws.close();
+                    }
+                    if let Some(pc) = pc_ref_for_cleanup.borrow_mut().take() {
+                        pc.close();
+                    }
+                    if let Some(stream) = local_stream_for_cleanup.borrow_mut().take() {
+                        stop_stream(&stream);
+                    }
+                    speaker_poll_for_cleanup.borrow_mut().take();
+                    if let Some(detector) = speaker_detector_for_cleanup.borrow_mut().take() {
+                        detector.close();
+                    }
                 };
 
+                // Create the configuration for the RTCPeerConnection.
+                let config = ice_configuration();
+
                 // Create the RTCPeerConnection.
-                let pc = RtcPeerConnection::new_with_configuration(&config)
-                    .expect("Failed to create RTCPeerConnection");
+                let pc = match RtcPeerConnection::new_with_configuration(&config) {
+                    Ok(pc) => pc,
+                    Err(e) => {
+                        report.emit(CallError::ice("couldn't create the peer connection", &e));
+                        return cleanup;
+                    }
+                };
                 
-                // >> DEBUG: Log ICE connection state changes.
+                // Log ICE connection state changes, and tell the user when connecting failed.
                 {
                     let pc_clone = pc.clone();
+                    let report_for_ice = report.clone();
                     let on_ice_state_change = Closure::wrap(Box::new(move || {
-                        // Log the ICE connection state using the native method.
-                        // (Depending on your web-sys version, this may need adjustment.)
-                        web_sys::console::log_1(&format!("ICE connection state: {:?}", pc_clone.ice_connection_state()).into());
+                        let state = pc_clone.ice_connection_state();
+                        web_sys::console::log_1(&format!("ICE connection state: {:?}", state).into());
+                        if state == RtcIceConnectionState::Failed {
+                            report_for_ice.emit(CallError::Ice("ICE connection failed".to_string()));
+                        }
                     }) as Box<dyn FnMut()>);
                     pc.set_oniceconnectionstatechange(Some(on_ice_state_change.as_ref().unchecked_ref()));
                     on_ice_state_change.forget();
                 }
-                // << END DEBUG
+
+                // Follow who is speaking; tracks are added as they arrive.
+                match SpeakerDetector::new() {
+                    Ok(detector) => {
+                        *speaker_detector.borrow_mut() = Some(detector);
+                        let speaker_detector_for_poll = speaker_detector.clone();
+                        let active_speaker_for_poll = active_speaker.clone();
+                        let spotlit_for_poll = spotlit.clone();
+                        *speaker_poll.borrow_mut() = Some(Interval::new(POLL_MS, move || {
+                            let speaker = match speaker_detector_for_poll.borrow_mut().as_mut() {
+                                Some(detector) => detector.poll(js_sys::Date::now()),
+                                None => return,
+                            };
+                            if let Some(speaker) = &speaker {
+                                spotlit_for_poll.set(speaker.tile());
+                            }
+                            active_speaker_for_poll.set(speaker);
+                        }));
+                    }
+                    Err(e) => web_sys::console::error_1(&format!("Active speaker detection unavailable: {:?}", e).into()),
+                }
 
                 // Attach the ontrack handler to process remote media tracks.
                 {
                     let remote_video_clone = remote_video_ref_clone.clone();
                     let local_track_ids_for_ontrack = local_track_ids.clone();
+                    let report_for_track = report.clone();
+                    let speaker_detector_for_track = speaker_detector.clone();
+                    let roster_for_track = roster_now.clone();
+                    let remote_audio_for_track = remote_audio.clone();
                     let on_track = Closure::wrap(Box::new(move |evt: RtcTrackEvent| {
                         let incoming_id = evt.track().id();
                         if local_track_ids_for_ontrack.borrow().contains(&incoming_id) {
@@ -154,12 +345,28 @@ fn app() -> Html {
                         web_sys::console::log_1(&format!("Remote track id: {}", track.id()).into());
                         let streams = evt.streams();
                         web_sys::console::log_1(&format!("Number of streams in event: {}", streams.length()).into());
+                        // The roster names the sender of each stream, so everyone's audio is followed on its own.
+                        let stream_id = streams.get(0).dyn_into::<MediaStream>().ok().map(|stream| stream.id());
+                        if let (Some(detector), Some(stream_id)) = (speaker_detector_for_track.borrow_mut().as_mut(), stream_id) {
+                            if track.kind() == "audio" {
+                                match remote_audio_for_track.borrow_mut().arrived(stream_id, track.clone(), &roster_for_track.borrow()) {
+                                    Some((speaker, track)) => watch_remote_audio(detector, speaker, &track),
+                                    None => web_sys::console::log_1(&"Remote audio from a stream not on the roster yet".into()),
+                                }
+                            }
+                        }
 
                         // Use the provided stream if available; otherwise, create a new MediaStream.
                         let stream_val = streams.get(0);
                         if stream_val.is_undefined() || stream_val.is_null() {
                             web_sys::console::log_1(&"No stream provided with ontrack; creating new MediaStream".into());
-                            let new_stream = web_sys::MediaStream::new().expect("Failed to create MediaStream");
+                            let new_stream = match web_sys::MediaStream::new() {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    report_for_track.emit(MediaError::from_js(&e).into());
+                                    return;
+                                }
+                            };
                             new_stream.add_track(&evt.track());
                             if let Some(video) = remote_video_clone.cast::<HtmlVideoElement>() {
                                 video.set_src_object(Some(new_stream.unchecked_ref()));
@@ -182,10 +389,10 @@ fn app() -> Html {
                 // Attach the onicecandidate handler to send ICE candidates via the WebSocket.
                 {
                     let pc_for_ice = pc.clone();
-                    let local_track_ids_for_async = local_track_ids.clone();
                     // Use the latest WebSocket instance via ws_ref.
-                    let ws_ref_clone = ws_ref_inner.clone(); // <<-- CHANGED
+                    let ws_ref_clone = ws_ref_inner.clone();
                     let ice_candidate_queue_clone = ice_candidate_queue.clone();
+                    let report_for_candidate = report.clone();
                     let on_ice_candidate = Closure::wrap(Box::new(move |evt: RtcPeerConnectionIceEvent| {
                         if let Some(candidate) = evt.candidate() {
                             web_sys::console::log_1(&format!("Sending ICE candidate: {}", candidate.candidate()).into());
@@ -195,12 +402,18 @@ fn app() -> Html {
                                 sdp_m_line_index: candidate.sdp_m_line_index(),
                             };
                             let msg = SignalMessage::IceCandidate(data);
-                            let msg_json = serde_json::to_string(&msg).unwrap();
+                            let msg_json = match encode(&msg) {
+                                Ok(msg_json) => msg_json,
+                                Err(e) => {
+                                    report_for_candidate.emit(e);
+                                    return;
+                                }
+                            };
                             // Always retrieve the current WebSocket instance.
-                            if let Some(ws) = ws_ref_clone.borrow().as_ref() { // <<-- CHANGED here
+                            if let Some(ws) = ws_ref_clone.borrow().as_ref() {
                                 if ws.ready_state() == web_sys::WebSocket::OPEN {
                                     let _ = ws.send_with_str(&msg_json);
-                                    web_sys::console::log_1(&"WebSocket Ready!!!!".into());
+                                    web_sys::console::log_1(&"Sent ICE candidate".into());
                                 } else {
                                     ice_candidate_queue_clone.borrow_mut().push(msg_json);
                                     web_sys::console::log_1(&"WebSocket not ready yet; candidate queued.".into());
@@ -217,56 +430,36 @@ fn app() -> Html {
     
                 // Store the peer connection in our mutable reference.
                 *pc_ref_inner.borrow_mut() = Some(pc.clone());
-                if (*pc_ref_inner.borrow()).is_none() {
-                    web_sys::console::log_1(&"pc_ref_inner is empty".into());
-                } else {
-                    web_sys::console::log_1(&"pc_ref_inner is not empty".into());
-                }
-                web_sys::console::log_1(&"!!!! Just set pc_ref_inner to Some(pc) !!!!".into());
     
+                // Ask the others for the kinds of media we don't send ourselves.
+                add_receive_transceivers(&pc, !mode.sends_audio(), !mode.sends_video());
+
                 // Obtain the user media (camera stream) asynchronously and attach it to the local video element.
-                {
-                    let navigator = window().unwrap().navigator();
-                    let media_devices = navigator.media_devices().expect("no media devices available");
-    
-                    let constraints = MediaStreamConstraints::new();
-                    constraints.set_video(&JsValue::TRUE);
-                    // Echo cancellation maybe
-                    //constraints.set_audio(&JsValue::TRUE);  // BRUH BRUH BRUH 
-                    let mut audio_constraints = MediaTrackConstraints::new();
-                    audio_constraints.echo_cancellation(&JsValue::from(true));
-                    // For Chrome-based browsers
-                    audio_constraints.echo_cancellation(&JsValue::from(true));  
-                    constraints.set_audio(&audio_constraints.into());
-                    let promise = media_devices
-                        .get_user_media_with_constraints(&constraints)
-                        .expect("getUserMedia should work");
-    
+                // Viewers have none, and the server refuses their offers if they carry any.
+                if mode != JoinMode::ReceiveOnly {
                     let video_for_async = video_ref_clone.clone();
                     let pc_for_tracks = pc.clone();
                     let local_track_ids_for_async = local_track_ids.clone();
+                    let local_stream_for_async = local_stream.clone();
+                    let hung_up_for_async = hung_up.clone();
+                    let speaker_detector_for_async = speaker_detector.clone();
+                    let report_for_media = report.clone();
                     wasm_bindgen_futures::spawn_local(async move {
-                        let stream_js = JsFuture::from(promise).await.expect("failed to get media stream");
-                        let stream: MediaStream = stream_js.dyn_into().unwrap();
-    
-                        // DEBUG: Log that the local media stream was successfully acquired.
-                        web_sys::console::log_1(&"Got local media stream".into());
-    
-                        if let Some(video) = video_for_async.cast::<HtmlVideoElement>() {
-                            video.set_src_object(Some(stream.unchecked_ref()));
-                            let _ = video.play();
-                        }
-    
-                        // Add each track of the stream to the peer connection.
-                        let tracks = stream.get_tracks();
-                        for i in 0..tracks.length() {
-                            let track_val = tracks.get(i);
-                            if !track_val.is_undefined() {
-                                let track: MediaStreamTrack = track_val.dyn_into().unwrap();
-                                local_track_ids_for_async.borrow_mut().push(track.id());
-                                let empty_array = Array::new();
-                                let _ = pc_for_tracks.add_track(&track, &stream, &empty_array);
-                            }
+                        let attached = attach_local_media(
+                            mode,
+                            &pc_for_tracks,
+                            &video_for_async,
+                            &local_stream_for_async,
+                            &local_track_ids_for_async,
+                            &hung_up_for_async,
+                            &speaker_detector_for_async,
+                        )
+                        .await;
+                        if let Err(e) = attached {
+                            web_sys::console::error_1(&format!("Couldn't open camera/microphone: {:?}", e).into());
+                            // Still receive what the others send while the user sorts it out.
+                            add_receive_transceivers(&pc_for_tracks, mode.sends_audio(), mode.sends_video());
+                            report_for_media.emit(e.into());
                         }
                     });
                 }
@@ -274,19 +467,38 @@ fn app() -> Html {
                 // Create the WebSocket connection only after the peer connection is set.
                 {
                     // Use ws_ref instead of a state for storing the WebSocket.
-                    let ws_ref_for_state = ws_ref_inner.clone(); // <<-- CHANGED
+                    let ws_ref_for_state = ws_ref_inner.clone();
                     let offer_sent_for_msg = offer_sent.clone();
-                    let ws_host = window().unwrap().location().host().unwrap();
-                    let ws_url  = format!("wss://{}/ws", ws_host);
-    
-                    let ws = WebSocket::new(&ws_url).expect("WebSocket creation failed");
+                    let server_notice_for_msg = server_notice.clone();
+                    let announcement_for_msg = announcement.clone();
+                    let my_id_for_msg = my_id.clone();
+                    let roster_for_msg = roster.clone();
+                    let roster_now_for_msg = roster_now.clone();
+                    let remote_audio_for_msg = remote_audio.clone();
+                    let speaker_detector_for_msg = speaker_detector.clone();
+                    let waiting_for_msg = waiting.clone();
+                    let lobby_for_msg = lobby.clone();
+                    let local_stream_for_msg = local_stream.clone();
+                    let mic_muted_for_msg = mic_muted.clone();
+                    let report_for_msg = report.clone();
+                    // Receive-only clients join as viewers, so other viewers leave them alone.
+                    let on_password_refused_for_msg = on_password_refused.clone();
+                    let ws = match open_signaling(&room, &name, &password, mode == JoinMode::ReceiveOnly) {
+                        Ok(ws) => ws,
+                        Err(e) => {
+                            report.emit(e);
+                            return cleanup;
+                        }
+                    };
+                    // Set once the server said why it is closing the connection, which is then expected.
+                    let server_explained = Rc::new(Cell::new(false));
                     // Use ws_ref to ensure that the onopen handler uses the current WebSocket.
-                    let ws_ref_for_onopen = ws_ref_inner.clone(); // <<-- CHANGED
+                    let ws_ref_for_onopen = ws_ref_inner.clone();
                     let ice_candidate_queue_clone2 = ice_candidate_queue.clone();
                     let on_open = Closure::wrap(Box::new(move |_| {
                         web_sys::console::log_1(&"WebSocket connection opened!".into());
                         let mut queue = ice_candidate_queue_clone2.borrow_mut();
-                        if let Some(ws) = ws_ref_for_onopen.borrow().as_ref() { // <<-- CHANGED
+                        if let Some(ws) = ws_ref_for_onopen.borrow().as_ref() {
                             // Flush any queued ICE candidates now that the socket is open.
                             for candidate_json in queue.drain(..) {
                                 ws.send_with_str(&candidate_json).ok();
@@ -303,17 +515,27 @@ fn app() -> Html {
                     ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
                     on_error.forget();
     
-                    // Log when the WebSocket connection is closed.
-                    let on_close = Closure::wrap(Box::new(move |_| {
+                    // Tell the user when the server goes away. Hanging up removes this handler first.
+                    let server_explained_for_close = server_explained.clone();
+                    let report_for_close = report.clone();
+                    let on_close = Closure::wrap(Box::new(move |evt: CloseEvent| {
                         web_sys::console::log_1(&"WebSocket connection closed!".into());
-                    }) as Box<dyn FnMut(JsValue)>);
+                        if !server_explained_for_close.get() {
+                            report_for_close.emit(CallError::Signaling(format!(
+                                "connection closed (code {}, reason {:?})",
+                                evt.code(),
+                                evt.reason()
+                            )));
+                        }
+                    }) as Box<dyn FnMut(CloseEvent)>);
                     ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
                     on_close.forget();
                     
                     // Use ws_ref for subsequent message handling.
-                    let ws_for_closure = ws_ref_inner.clone(); // <<-- CHANGED
+                    let ws_for_closure = ws_ref_inner.clone();
                     let on_message = {
                         let pc_ref_for_msg = pc_ref_inner.clone();
+                        let server_explained_for_msg = server_explained.clone();
                         Closure::wrap(Box::new(move |evt: MessageEvent| {
                             if let Some(txt) = evt.data().as_string() {
                                 web_sys::console::log_1(&format!("Received raw message as text: {}", txt).into());
@@ -331,36 +553,12 @@ fn app() -> Html {
                                                     web_sys::console::log_1(&"Received Offer signal. Disabling Start Call on this client.".into());
                                                     offer_sent_for_msg.set(true);
     
-                                                    let offer_desc = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-                                                    offer_desc.set_sdp(&sdp_str);
-    
                                                     let pc_for_async = pc.clone();
                                                     let ws_clone = ws_for_closure.clone();
-    
+                                                    let report_for_answer = report_for_msg.clone();
                                                     wasm_bindgen_futures::spawn_local(async move {
-                                                        let set_rd_prom = pc_for_async.set_remote_description(&offer_desc);
-                                                        let _ = JsFuture::from(set_rd_prom).await;
-                                                    
-                                                        let ans_js = JsFuture::from(pc_for_async.create_answer())
-                                                            .await
-                                                            .expect("Error creating answer");
-                                                    
-                                                        let sdp = Reflect::get(&ans_js, &JsValue::from_str("sdp"))
-                                                            .expect("No sdp field in answer")
-                                                            .as_string()
-                                                            .expect("sdp field is not a string");
-                                                    
-                                                        let answer = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-                                                        answer.set_sdp(&sdp);
-                                                    
-                                                        let set_ld = pc_for_async.set_local_description(&answer);
-                                                        let _ = JsFuture::from(set_ld).await;
-                                                    
-                                                        let msg = SignalMessage::Answer(sdp);
-                                                        let msg_json = serde_json::to_string(&msg).unwrap();
-                                                    
-                                                        if let Some(ws) = ws_clone.borrow().as_ref() { // <<-- CHANGED
-                                                            let _ = ws.send_with_str(&msg_json);
+                                                        if let Err(e) = answer_offer(&pc_for_async, mode, &sdp_str, &ws_clone).await {
+                                                            report_for_answer.emit(e);
                                                         }
                                                     });
                                                 },
@@ -370,9 +568,12 @@ fn app() -> Html {
                                                     ans_desc.set_sdp(&sdp_str);
     
                                                     let pc_for_async = pc.clone();
+                                                    let report_for_answer = report_for_msg.clone();
                                                     wasm_bindgen_futures::spawn_local(async move {
                                                         let set_rd = pc_for_async.set_remote_description(&ans_desc);
-                                                        let _ = JsFuture::from(set_rd).await;
+                                                        if let Err(e) = JsFuture::from(set_rd).await {
+                                                            report_for_answer.emit(CallError::negotiation("couldn't apply the answer", &e));
+                                                        }
                                                     });
                                                 },
     
@@ -389,6 +590,80 @@ fn app() -> Html {
                                                         let _ = pc.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&candidate));
                                                     }
                                                 },
+
+                                                SignalMessage::Error(err) if err.is_password_refusal() => {
+                                                    web_sys::console::log_1(&format!("Room password refused: {}", err.code).into());
+                                                    server_explained_for_msg.set(true);
+                                                    // Back to the green room to enter the password again.
+                                                    on_password_refused_for_msg.emit(err.message);
+                                                },
+
+                                                SignalMessage::Error(err) => {
+                                                    web_sys::console::error_1(&format!("Signaling server error {}: {}", err.code, err.message).into());
+                                                    server_explained_for_msg.set(true);
+                                                    server_notice_for_msg.set(Some(err.message));
+                                                    waiting_for_msg.set(false);
+                                                },
+
+                                                SignalMessage::ServerShuttingDown(notice) => {
+                                                    web_sys::console::log_1(&format!("Signaling server is shutting down; reconnect in {} ms", notice.reconnect_after_ms).into());
+                                                    server_explained_for_msg.set(true);
+                                                    server_notice_for_msg.set(Some(format!(
+                                                        "The server is restarting. Please rejoin in {} seconds.",
+                                                        notice.reconnect_after_ms.div_ceil(1000)
+                                                    )));
+                                                },
+
+                                                SignalMessage::Announcement(announcement) => {
+                                                    web_sys::console::log_1(&format!("Announcement: {}", announcement.message).into());
+                                                    announcement_for_msg.set(Some(announcement.message));
+                                                },
+
+                                                SignalMessage::Welcome(me) => {
+                                                    web_sys::console::log_1(&format!("Joined as {} ({:?})", me.id, me.role).into());
+                                                    my_id_for_msg.set(Some(me.id));
+                                                    waiting_for_msg.set(false);
+                                                },
+
+                                                SignalMessage::Waiting(me) => {
+                                                    web_sys::console::log_1(&format!("Waiting in the lobby as {}", me.id).into());
+                                                    my_id_for_msg.set(Some(me.id));
+                                                    waiting_for_msg.set(true);
+                                                },
+
+                                                SignalMessage::Lobby(update) => {
+                                                    lobby_for_msg.set(update.waiting);
+                                                },
+
+                                                SignalMessage::Roster(new_roster) => {
+                                                    // Follow the audio that was waiting for its sender, and stop listening to whoever left.
+                                                    let assigned = remote_audio_for_msg.borrow_mut().assign(&new_roster);
+                                                    if let Some(detector) = speaker_detector_for_msg.borrow_mut().as_mut() {
+                                                        for (speaker, track) in assigned {
+                                                            watch_remote_audio(detector, speaker, &track);
+                                                        }
+                                                        let present: Vec<&str> = new_roster.participants.iter().map(|entry| entry.id.as_str()).collect();
+                                                        detector.retain_clients(&present);
+                                                    }
+                                                    *roster_now_for_msg.borrow_mut() = new_roster.clone();
+                                                    roster_for_msg.set(new_roster);
+                                                },
+
+                                                SignalMessage::MuteRequest(request) => {
+                                                    // Honour the host's request right away; the user can unmute again.
+                                                    if let Some(stream) = local_stream_for_msg.borrow().as_ref() {
+                                                        set_audio_enabled(stream, false);
+                                                    }
+                                                    mic_muted_for_msg.set(true);
+                                                    server_notice_for_msg.set(Some(format!(
+                                                        "{} muted your microphone.",
+                                                        request.by.unwrap_or_else(|| "The host".to_string())
+                                                    )));
+                                                },
+
+                                                SignalMessage::Moderate(_) => {
+                                                    web_sys::console::log_1(&"Ignoring moderator command relayed by the server".into());
+                                                },
                                             }
                                         } else {
                                             web_sys::console::log_1(&"Parsed SignalMessage OK, but 'pc_ref_for_msg' is None. Skipping signal.".into());
@@ -411,31 +686,84 @@ fn app() -> Html {
                     on_message.forget();
     
                     // Finally, store the WebSocket instance in our ws_ref mutable reference.
-                    ws_ref_for_state.borrow_mut().replace(ws); // <<-- CHANGED
+                    ws_ref_for_state.borrow_mut().replace(ws);
                 }
     
-                || ()
+                cleanup
             },
             video_ref.clone()
         );
     }
-    
+
+    let current_error = errors.current().cloned();
+    let error_diagnostics = match current_error {
+        Some(_) => diagnostics(&props.room, my_id_for_view.as_deref(), pc_ref.borrow().as_ref(), &errors),
+        None => String::new(),
+    };
+
+    // The speaker is ringed; in the spotlight layout their video is large and comes first.
+    let tile_style = |tile: Tile| {
+        let (width, height, order) = match (*spotlight, *spotlit_for_view == tile) {
+            (false, _) => (480, 360, 0),
+            (true, true) => (720, 540, 0),
+            (true, false) => (240, 180, 1),
+        };
+        let ring = if active_speaker_for_view.as_ref().map(Speaker::tile) == Some(tile) { " box-shadow: 0 0 0 4px #2e7d32;" } else { "" };
+        format!("width: {}px; height: {}px; order: {}; background: #000;{}", width, height, order, ring)
+    };
+    let speaking_id = match &*active_speaker_for_view {
+        Some(Speaker::Local) => (*my_id_for_view).clone(),
+        Some(Speaker::Client(id)) => Some(id.clone()),
+        None => None,
+    };
+
     html! {
         <div>
             <h1>{ "Rust Video Chat" }</h1>
-            <div style="display: flex; gap: 20px;">
+            <p>{ format!("Room: {}", props.room) }</p>
+            <div style="display: flex; gap: 20px; align-items: flex-start;">
                 <video ref={video_ref} autoplay=true playsinline=true muted=true
-                    style="width: 480px; height: 360px; background: #000;" />
+                    style={tile_style(Tile::Local)} />
                 <video ref={remote_video_ref} autoplay=true playsinline=true muted=false
-                    style="width: 480px; height: 360px; background: #000;" />
+                    style={tile_style(Tile::Remote)} />
             </div>
+            <ErrorBanner error={current_error.clone()} diagnostics={error_diagnostics} on_dismiss={on_dismiss_error}>
+                if matches!(current_error.as_ref().map(|reported| &reported.error), Some(CallError::Media(_))) {
+                    <button onclick={on_retry_media} style="margin-right: 8px;">{ "Try again" }</button>
+                }
+            </ErrorBanner>
+            if *waiting_for_view {
+                <p style="font-weight: bold;">{ "Waiting for the host to let you in…" }</p>
+            }
             <button 
                 onclick={on_start_call} 
-                disabled={*offer_sent_for_button}
+                disabled={*offer_sent_for_button || *waiting_for_view}
                 style="margin-top: 20px; padding: 10px 20px;"
             >
                 { "Start Call" }
             </button>
+            <button onclick={on_toggle_mute} disabled={!props.mode.sends_audio()} style="margin-top: 20px; margin-left: 10px; padding: 10px 20px;">
+                { if *mic_muted_for_view { "Unmute" } else { "Mute" } }
+            </button>
+            <button onclick={on_toggle_spotlight} style="margin-top: 20px; margin-left: 10px; padding: 10px 20px;">
+                { if *spotlight { "Grid" } else { "Spotlight" } }
+            </button>
+            <button onclick={on_leave} style="margin-top: 20px; margin-left: 10px; padding: 10px 20px;">
+                { "Leave" }
+            </button>
+            <ParticipantList
+                roster={(*roster_for_view).clone()}
+                lobby={(*lobby_for_view).clone()}
+                my_id={(*my_id_for_view).clone()}
+                speaking={speaking_id}
+                on_command={on_moderator_command}
+            />
+            if let Some(message) = (*announcement_for_view).clone() {
+                <p style="background: #fff3cd; padding: 8px 12px;">{ message }</p>
+            }
+            if let Some(message) = (*server_notice_for_view).clone() {
+                <p style="color: #b00020; font-weight: bold;">{ message }</p>
+            }
             <p>{ format!("Call initiated? {}", *offer_sent_for_button) }</p>
             <p>{ "Click 'Start Call' to initiate video chat" }</p>
             <VisitorCounter />
@@ -443,6 +771,181 @@ fn app() -> Html {
     }
 }
 
+/// Creates an offer and sends it to the room, reporting what goes wrong.
+fn send_offer(pc: RtcPeerConnection, ws: Option<WebSocket>, report: Callback<CallError>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(e) = create_offer(&pc, ws.as_ref()).await {
+            report.emit(e);
+        }
+    });
+}
+
+async fn create_offer(pc: &RtcPeerConnection, ws: Option<&WebSocket>) -> Result<(), CallError> {
+    // Create an SDP offer
+    let offer_js = JsFuture::from(pc.create_offer())
+        .await
+        .map_err(|e| CallError::negotiation("couldn't create an offer", &e))?;
+    let sdp = sdp_of(&offer_js)?;
+
+    web_sys::console::log_1(&format!("Created offer with sdp: {}", sdp).into());
+    let offer = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+    offer.set_sdp(&sdp);
+
+    // Set the local description with the created offer.
+    JsFuture::from(pc.set_local_description(&offer))
+        .await
+        .map_err(|e| CallError::negotiation("couldn't apply our offer", &e))?;
+
+    // If the WebSocket is available, send the offer message.
+    if let Some(ws) = ws {
+        web_sys::console::log_1(&"Sending Offer message".into());
+        send_signal(ws, &SignalMessage::Offer(sdp))?;
+    }
+    Ok(())
+}
+
+/// Answers an offer from the room.
+async fn answer_offer(
+    pc: &RtcPeerConnection,
+    mode: JoinMode,
+    offer_sdp: &str,
+    ws_ref: &RefCell<Option<WebSocket>>,
+) -> Result<(), CallError> {
+    let offer_desc = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+    offer_desc.set_sdp(offer_sdp);
+    JsFuture::from(pc.set_remote_description(&offer_desc))
+        .await
+        .map_err(|e| CallError::negotiation("couldn't apply the offer", &e))?;
+    if mode == JoinMode::ReceiveOnly {
+        receive_only(pc);
+    }
+
+    let ans_js = JsFuture::from(pc.create_answer())
+        .await
+        .map_err(|e| CallError::negotiation("couldn't create an answer", &e))?;
+    let sdp = sdp_of(&ans_js)?;
+
+    let answer = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+    answer.set_sdp(&sdp);
+    JsFuture::from(pc.set_local_description(&answer))
+        .await
+        .map_err(|e| CallError::negotiation("couldn't apply our answer", &e))?;
+
+    if let Some(ws) = ws_ref.borrow().as_ref() {
+        send_signal(ws, &SignalMessage::Answer(sdp))?;
+    }
+    Ok(())
+}
+
+/// The `sdp` of an offer or answer from `createOffer`/`createAnswer`.
+fn sdp_of(description: &JsValue) -> Result<String, CallError> {
+    Reflect::get(description, &JsValue::from_str("sdp"))
+        .ok()
+        .and_then(|sdp| sdp.as_string())
+        .ok_or_else(|| CallError::Negotiation("session description without sdp".to_string()))
+}
+
+/// Opens the signaling connection for `room`. The token comes from the page's query string.
+fn open_signaling(room: &str, name: &str, password: &str, viewer: bool) -> Result<WebSocket, CallError> {
+    let location = window().ok_or_else(|| CallError::Signaling("no window".to_string()))?.location();
+    let origin = ServerOrigin::of_page()?;
+    let ws_url = signaling_url(&origin, room, name, password, viewer, &location.search().unwrap_or_default())?;
+    create_websocket(&ws_url)
+}
+
+/// Opens the camera/microphone for `mode`, shows it in `video_ref`, adds its
+/// tracks to `pc` and has `speaker_detector` listen to the microphone. Does
+/// nothing for receive-only.
+async fn attach_local_media(
+    mode: JoinMode,
+    pc: &RtcPeerConnection,
+    video_ref: &NodeRef,
+    local_stream: &RefCell<Option<MediaStream>>,
+    local_track_ids: &RefCell<Vec<String>>,
+    hung_up: &RefCell<bool>,
+    speaker_detector: &RefCell<Option<SpeakerDetector>>,
+) -> Result<(), MediaError> {
+    let Some(stream) = open_local_media(mode).await? else {
+        return Ok(());
+    };
+    // The user left before the camera was ready.
+    if *hung_up.borrow() {
+        stop_stream(&stream);
+        return Ok(());
+    }
+    *local_stream.borrow_mut() = Some(stream.clone());
+
+    // DEBUG: Log that the local media stream was successfully acquired.
+    web_sys::console::log_1(&"Got local media stream".into());
+
+    if let Some(video) = video_ref.cast::<HtmlVideoElement>() {
+        video.set_src_object(Some(stream.unchecked_ref()));
+        let _ = video.play();
+    }
+
+    // Add each track of the stream to the peer connection.
+    let tracks = stream.get_tracks();
+    for i in 0..tracks.length() {
+        if let Ok(track) = tracks.get(i).dyn_into::<MediaStreamTrack>() {
+            local_track_ids.borrow_mut().push(track.id());
+            let empty_array = Array::new();
+            let _ = pc.add_track(&track, &stream, &empty_array);
+            if track.kind() == "audio" {
+                if let Some(detector) = speaker_detector.borrow_mut().as_mut() {
+                    if let Err(e) = detector.watch(Speaker::Local, &track) {
+                        web_sys::console::error_1(&format!("Can't follow our audio level: {:?}", e).into());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Has `detector` follow the audio level of `speaker`'s `track`.
+fn watch_remote_audio(detector: &mut SpeakerDetector, speaker: Speaker, track: &MediaStreamTrack) {
+    if let Err(e) = detector.watch(speaker, track) {
+        web_sys::console::error_1(&format!("Can't follow remote audio level: {:?}", e).into());
+    }
+}
+
+/// STUN servers and ICE policy for peer connections, shared by the call and the network check.
+pub fn ice_configuration() -> RtcConfiguration {
+    let config = RtcConfiguration::new();
+    let ice_server = {
+        let server = web_sys::RtcIceServer::new();
+        server.set_urls(&JsValue::from_str("stun:stun.l.google.com:19302"));
+        server.set_credential("");
+        server.set_username("");
+        server
+    };
+    let ice_servers = Array::new();
+    ice_servers.push(&ice_server);
+    config.set_ice_servers(&ice_servers);
+    config.set_ice_transport_policy(web_sys::RtcIceTransportPolicy::All);
+    config
+}
+
+/// Enables or disables every audio track of `stream`.
+fn set_audio_enabled(stream: &MediaStream, enabled: bool) {
+    let tracks = stream.get_audio_tracks();
+    for i in 0..tracks.length() {
+        if let Ok(track) = tracks.get(i).dyn_into::<MediaStreamTrack>() {
+            track.set_enabled(enabled);
+        }
+    }
+}
+
+/// Stops every track of `stream`, turning off the camera and microphone.
+pub fn stop_stream(stream: &MediaStream) {
+    let tracks = stream.get_tracks();
+    for i in 0..tracks.length() {
+        if let Ok(track) = tracks.get(i).dyn_into::<MediaStreamTrack>() {
+            track.stop();
+        }
+    }
+}
+
 #[wasm_bindgen(start)]
 pub fn run_app() {
     wasm_logger::init(wasm_logger::Config::default());
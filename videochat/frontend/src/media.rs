@@ -0,0 +1,206 @@
+use std::fmt;
+use js_sys::Reflect;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    window, MediaStream, MediaStreamConstraints, MediaTrackConstraints, RtcPeerConnection, RtcRtpTransceiver,
+    RtcRtpTransceiverDirection, RtcRtpTransceiverInit,
+};
+
+/// What the user sends when joining a call.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum JoinMode {
+    #[default]
+    AudioVideo,
+    AudioOnly,
+    VideoOnly,
+    /// Send nothing, only watch and listen.
+    ReceiveOnly,
+}
+
+impl JoinMode {
+    pub const ALL: [JoinMode; 4] = [JoinMode::AudioVideo, JoinMode::AudioOnly, JoinMode::VideoOnly, JoinMode::ReceiveOnly];
+
+    pub fn sends_audio(self) -> bool {
+        matches!(self, JoinMode::AudioVideo | JoinMode::AudioOnly)
+    }
+
+    pub fn sends_video(self) -> bool {
+        matches!(self, JoinMode::AudioVideo | JoinMode::VideoOnly)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            JoinMode::AudioVideo => "Camera and microphone",
+            JoinMode::AudioOnly => "Microphone only",
+            JoinMode::VideoOnly => "Camera only",
+            JoinMode::ReceiveOnly => "Watch and listen only",
+        }
+    }
+
+    /// Short name used as the value of the join mode picker.
+    pub fn key(self) -> &'static str {
+        match self {
+            JoinMode::AudioVideo => "audio-video",
+            JoinMode::AudioOnly => "audio",
+            JoinMode::VideoOnly => "video",
+            JoinMode::ReceiveOnly => "receive",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<JoinMode> {
+        JoinMode::ALL.into_iter().find(|mode| mode.key() == key)
+    }
+}
+
+/// Why the camera or microphone couldn't be opened, from the `DOMException` name
+/// `getUserMedia` rejects with.
+#[derive(Clone, PartialEq, Debug)]
+pub enum MediaError {
+    /// The user or the browser's settings denied access.
+    NotAllowed,
+    /// No camera or microphone is connected.
+    NotFound,
+    /// The device exists but another app or a hardware problem keeps it busy.
+    NotReadable,
+    /// The device can't satisfy the requested settings.
+    Overconstrained,
+    /// No `navigator.mediaDevices`, usually because the page isn't served over HTTPS.
+    Unsupported,
+    Other(String),
+}
+
+impl MediaError {
+    pub fn from_js(e: &JsValue) -> Self {
+        match Reflect::get(e, &JsValue::from_str("name")).ok().and_then(|name| name.as_string()) {
+            Some(name) if !name.is_empty() => Self::from_name(&name),
+            _ => MediaError::Other(format!("{:?}", e)),
+        }
+    }
+
+    /// Maps the name of the `DOMException`.
+    fn from_name(name: &str) -> Self {
+        match name {
+            // The second names are what older browsers use.
+            "NotAllowedError" | "PermissionDeniedError" | "SecurityError" => MediaError::NotAllowed,
+            "NotFoundError" | "DevicesNotFoundError" => MediaError::NotFound,
+            "NotReadableError" | "TrackStartError" | "AbortError" => MediaError::NotReadable,
+            "OverconstrainedError" | "ConstraintNotSatisfiedError" => MediaError::Overconstrained,
+            _ => MediaError::Other(name.to_string()),
+        }
+    }
+
+    /// What happened and what the user can do about it.
+    pub fn message(&self) -> &'static str {
+        match self {
+            MediaError::NotAllowed => {
+                "Access to your camera or microphone was blocked. Allow it in your browser's address bar or site settings, then try again."
+            }
+            MediaError::NotFound => {
+                "No camera or microphone was found. Connect one and try again, or join with what you have."
+            }
+            MediaError::NotReadable => {
+                "Your camera or microphone is in use by another app or isn't working. Close other apps using it and try again."
+            }
+            MediaError::Overconstrained => "Your camera or microphone doesn't support the requested settings.",
+            MediaError::Unsupported => {
+                "This browser can't use your camera or microphone here. Make sure the page is opened over https."
+            }
+            MediaError::Other(_) => "We couldn't open your camera or microphone.",
+        }
+    }
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::Other(name) => write!(f, "{} ({})", self.message(), name),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+/// Opens the camera and/or microphone for `mode`. Returns `None` for receive-only.
+pub async fn open_local_media(mode: JoinMode) -> Result<Option<MediaStream>, MediaError> {
+    if mode == JoinMode::ReceiveOnly {
+        return Ok(None);
+    }
+    let media_devices = window()
+        .and_then(|window| window.navigator().media_devices().ok())
+        .ok_or(MediaError::Unsupported)?;
+    let constraints = MediaStreamConstraints::new();
+    constraints.set_video(&JsValue::from_bool(mode.sends_video()));
+    if mode.sends_audio() {
+        let audio_constraints = MediaTrackConstraints::new();
+        audio_constraints.set_echo_cancellation(&JsValue::TRUE);
+        constraints.set_audio(&audio_constraints.into());
+    } else {
+        constraints.set_audio(&JsValue::FALSE);
+    }
+    let promise = media_devices.get_user_media_with_constraints(&constraints).map_err(|e| MediaError::from_js(&e))?;
+    let stream = JsFuture::from(promise).await.map_err(|e| MediaError::from_js(&e))?;
+    stream.dyn_into().map(Some).map_err(|e| MediaError::Other(format!("{:?}", e)))
+}
+
+/// Adds a receive-only transceiver for audio and/or video, so an offer from us
+/// still asks the other side for kinds of media we don't send.
+pub fn add_receive_transceivers(pc: &RtcPeerConnection, audio: bool, video: bool) {
+    let init = RtcRtpTransceiverInit::new();
+    init.set_direction(RtcRtpTransceiverDirection::Recvonly);
+    for (kind, wanted) in [("audio", audio), ("video", video)] {
+        if wanted {
+            pc.add_transceiver_with_str_and_init(kind, &init);
+        }
+    }
+}
+
+/// Makes every transceiver of `pc` receive only, e.g. those created for an offer
+/// from the room, so the answer doesn't offer to send anything.
+pub fn receive_only(pc: &RtcPeerConnection) {
+    for transceiver in pc.get_transceivers().iter() {
+        if let Ok(transceiver) = transceiver.dyn_into::<RtcRtpTransceiver>() {
+            transceiver.set_direction(RtcRtpTransceiverDirection::Recvonly);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_get_user_media_errors() {
+        assert_eq!(MediaError::from_name("NotAllowedError"), MediaError::NotAllowed);
+        assert_eq!(MediaError::from_name("PermissionDeniedError"), MediaError::NotAllowed);
+        assert_eq!(MediaError::from_name("NotFoundError"), MediaError::NotFound);
+        assert_eq!(MediaError::from_name("DevicesNotFoundError"), MediaError::NotFound);
+        assert_eq!(MediaError::from_name("NotReadableError"), MediaError::NotReadable);
+        assert_eq!(MediaError::from_name("TrackStartError"), MediaError::NotReadable);
+        assert_eq!(MediaError::from_name("OverconstrainedError"), MediaError::Overconstrained);
+        assert_eq!(MediaError::from_name("TypeError"), MediaError::Other("TypeError".to_string()));
+    }
+
+    #[test]
+    fn tells_the_user_what_to_do() {
+        assert!(MediaError::from_name("NotAllowedError").to_string().contains("Allow it in your browser"));
+        assert!(MediaError::from_name("NotFoundError").to_string().contains("Connect one"));
+        assert!(MediaError::from_name("NotReadableError").to_string().contains("in use by another app"));
+        assert!(MediaError::from_name("OverconstrainedError").to_string().contains("requested settings"));
+        // Unknown errors keep their name for bug reports.
+        assert_eq!(
+            MediaError::from_name("TypeError").to_string(),
+            "We couldn't open your camera or microphone. (TypeError)"
+        );
+    }
+
+    #[test]
+    fn join_modes_round_trip_through_their_keys() {
+        for mode in JoinMode::ALL {
+            assert_eq!(JoinMode::from_key(mode.key()), Some(mode));
+        }
+        assert_eq!(JoinMode::from_key("audio"), Some(JoinMode::AudioOnly));
+        assert_eq!(JoinMode::from_key("receive"), Some(JoinMode::ReceiveOnly));
+        assert_eq!(JoinMode::from_key("everything"), None);
+        assert_eq!(JoinMode::from_key(""), None);
+    }
+}
@@ -0,0 +1,119 @@
+use yew::prelude::*;
+
+use crate::signaling::{ModeratorCommand, Role, Roster, RosterEntry};
+
+#[derive(Properties, PartialEq)]
+pub struct ParticipantListProps {
+    pub roster: Roster,
+    /// Clients waiting in the lobby; only sent to hosts.
+    pub lobby: Vec<RosterEntry>,
+    /// Our own client ID, once the server sent it.
+    pub my_id: Option<String>,
+    /// Client ID of whoever is speaking, if known.
+    #[prop_or_default]
+    pub speaking: Option<String>,
+    /// Sends a moderator command to the server.
+    pub on_command: Callback<ModeratorCommand>,
+}
+
+/// One tile per participant. Hosts get mute, kick and promote buttons on every
+/// other participant's tile, a toggle to lock the room and the lobby with
+/// admit/deny buttons.
+#[function_component(ParticipantList)]
+pub fn participant_list(props: &ParticipantListProps) -> Html {
+    let is_host = props
+        .roster
+        .participants
+        .iter()
+        .any(|entry| Some(&entry.id) == props.my_id.as_ref() && entry.role == Role::Host);
+
+    let lock_toggle = if is_host {
+        let locked = props.roster.locked;
+        let on_command = props.on_command.clone();
+        let onclick = Callback::from(move |_| on_command.emit(ModeratorCommand::LockRoom { locked: !locked }));
+        html! {
+            <button {onclick} style="margin-bottom: 10px;">
+                { if locked { "Unlock room" } else { "Lock room" } }
+            </button>
+        }
+    } else {
+        html! {}
+    };
+
+    let lobby = if is_host && !props.lobby.is_empty() {
+        let rows = props.lobby.iter().map(|entry| {
+            let command = |make: fn(String) -> ModeratorCommand| {
+                let on_command = props.on_command.clone();
+                let target = entry.id.clone();
+                Callback::from(move |_| on_command.emit(make(target.clone())))
+            };
+            html! {
+                <li key={entry.id.clone()} style="margin-bottom: 6px;">
+                    { entry.name.clone().unwrap_or_else(|| "Guest".to_string()) }
+                    <button onclick={command(|target| ModeratorCommand::Admit { target })} style="margin-left: 10px;">{ "Admit" }</button>
+                    <button onclick={command(|target| ModeratorCommand::Deny { target })}>{ "Deny" }</button>
+                </li>
+            }
+        });
+        html! {
+            <div style="margin-bottom: 10px;">
+                <h4>{ format!("Waiting in the lobby ({})", props.lobby.len()) }</h4>
+                <ul style="list-style: none; padding: 0;">{ for rows }</ul>
+            </div>
+        }
+    } else {
+        html! {}
+    };
+
+    let tiles = props.roster.participants.iter().map(|entry| {
+        let is_me = Some(&entry.id) == props.my_id.as_ref();
+        let border = if Some(&entry.id) == props.speaking.as_ref() { "2px solid #2e7d32" } else { "1px solid #ccc" };
+        let name = entry.name.clone().unwrap_or_else(|| "Guest".to_string());
+        let label = match (is_me, entry.role) {
+            (true, Role::Host) => format!("{} (you, host)", name),
+            (true, Role::Participant) => format!("{} (you)", name),
+            (false, Role::Host) => format!("{} (host)", name),
+            (false, Role::Participant) => name,
+        };
+        let controls = if is_host && !is_me {
+            let command = |make: fn(String) -> ModeratorCommand| {
+                let on_command = props.on_command.clone();
+                let target = entry.id.clone();
+                Callback::from(move |_| on_command.emit(make(target.clone())))
+            };
+            html! {
+                <div style="margin-top: 6px;">
+                    <button onclick={command(|target| ModeratorCommand::MuteRequest { target })}>{ "Mute" }</button>
+                    <button onclick={command(|target| ModeratorCommand::Kick { target })}>{ "Kick" }</button>
+                    if entry.role != Role::Host {
+                        <button onclick={command(|target| ModeratorCommand::Promote { target })}>{ "Make host" }</button>
+                    }
+                </div>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+            <div key={entry.id.clone()} style={format!("border: {}; border-radius: 6px; padding: 8px 12px; min-width: 140px;", border)}>
+                <div>{ label }</div>
+                if entry.viewer {
+                    <div style="color: #666; font-size: 0.9em;">{ "Watching" }</div>
+                }
+                { controls }
+            </div>
+        }
+    });
+
+    html! {
+        <div style="margin-top: 20px;">
+            if props.roster.locked {
+                <p>{ "This room is locked; nobody else can join." }</p>
+            }
+            { lock_toggle }
+            { lobby }
+            <div style="display: flex; flex-wrap: wrap; gap: 10px; justify-content: center;">
+                { for tiles }
+            </div>
+        </div>
+    }
+}
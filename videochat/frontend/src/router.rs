@@ -0,0 +1,95 @@
+use web_sys::window;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::green_room::{GreenRoom, JoinChoice};
+use crate::landing::Landing;
+use crate::signaling::query_param;
+use crate::CallView;
+
+#[derive(Routable, Clone, PartialEq, Debug)]
+pub enum Route {
+    /// Create or join a room.
+    #[at("/")]
+    Landing,
+    /// The green room, then the call once the user joins.
+    #[at("/room/:id")]
+    Room { id: String },
+    #[not_found]
+    #[at("/404")]
+    NotFound,
+}
+
+pub fn switch(route: Route) -> Html {
+    match route {
+        // Links from before rooms had their own path put the room in the query string.
+        Route::Landing => match page_query_param("room") {
+            Some(id) => html! { <RoomPage key={id.as_str()} id={id.clone()} /> },
+            None => html! { <Landing /> },
+        },
+        Route::Room { id } => html! { <RoomPage key={id.as_str()} id={id.clone()} /> },
+        Route::NotFound => html! {
+            <div>
+                <h1>{ "Rust Video Chat" }</h1>
+                <p>{ "There is nothing here." }</p>
+                <Link<Route> to={Route::Landing}>{ "Start or join a call" }</Link<Route>>
+            </div>
+        },
+    }
+}
+
+/// Value of `key` in the page's query string.
+pub fn page_query_param(key: &str) -> Option<String> {
+    let search = window()?.location().search().ok()?;
+    query_param(&search, key)
+}
+
+#[derive(Properties, PartialEq)]
+pub struct RoomPageProps {
+    pub id: String,
+}
+
+/// Shows the green room until the user joins, then the call. The call view opens the
+/// signaling socket when it mounts, so nobody shows up in the room just by opening a link.
+#[function_component(RoomPage)]
+pub fn room_page(props: &RoomPageProps) -> Html {
+    // What the user picked in the green room, once they joined.
+    let joined_as = use_state(|| Option::<JoinChoice>::None);
+    // A join the server refused for the password, and why; the user gets to try again.
+    let refused = use_state(|| Option::<(JoinChoice, String)>::None);
+
+    match (*joined_as).clone() {
+        Some(choice) => {
+            let on_password_refused = {
+                let joined_as = joined_as.clone();
+                let refused = refused.clone();
+                let choice = choice.clone();
+                Callback::from(move |message: String| {
+                    refused.set(Some((choice.clone(), message)));
+                    joined_as.set(None);
+                })
+            };
+            let JoinChoice { name, mode, password } = choice;
+            html! { <CallView room={props.id.clone()} {name} {mode} {password} {on_password_refused} /> }
+        }
+        None => {
+            let on_join = {
+                let joined_as = joined_as.clone();
+                Callback::from(move |joined: JoinChoice| joined_as.set(Some(joined)))
+            };
+            let name = match refused.as_ref() {
+                Some((choice, _)) => choice.name.clone(),
+                None => page_query_param("name").unwrap_or_default(),
+            };
+            html! {
+                <GreenRoom
+                    room={props.id.clone()}
+                    name={name}
+                    password={page_query_param("password").unwrap_or_default()}
+                    password_error={refused.as_ref().map(|(_, message)| message.clone())}
+                    {on_join}
+                />
+            }
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Serialize, Deserialize};
-use web_sys::WebSocket;
+use web_sys::{window, UrlSearchParams, WebSocket};
+
+use crate::errors::CallError;
 
 #[derive(Serialize, Deserialize)]
 pub struct IceCandidateData {
@@ -8,15 +10,245 @@ pub struct IceCandidateData {
     pub sdp_m_line_index: Option<u16>,
 }
 
+/// Error sent by the signaling server, e.g. when the room is full.
+#[derive(Serialize, Deserialize)]
+pub struct ServerError {
+    pub code: String,
+    pub message: String,
+}
+
+impl ServerError {
+    /// Whether the server refused the join for a missing or wrong room password.
+    pub fn is_password_refusal(&self) -> bool {
+        self.code == "password-required" || self.code == "wrong-password"
+    }
+}
+
+/// Sent by the signaling server before it restarts.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownNotice {
+    pub reconnect_after_ms: u64,
+}
+
+/// Message from the server operators.
+#[derive(Serialize, Deserialize)]
+pub struct Announcement {
+    pub message: String,
+}
+
+/// What a participant may do in the room.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Host,
+    Participant,
+}
+
+/// A participant as listed by the server.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RosterEntry {
+    pub id: String,
+    pub name: Option<String>,
+    pub role: Role,
+    /// Watches and listens only, sending no media.
+    #[serde(default)]
+    pub viewer: bool,
+    /// ID of the media stream their tracks arrive in, once they offered or answered with media.
+    #[serde(default)]
+    pub stream: Option<String>,
+}
+
+/// Everyone in the room, sent by the server whenever it changes.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default, Debug)]
+pub struct Roster {
+    pub locked: bool,
+    pub participants: Vec<RosterEntry>,
+}
+
+impl Roster {
+    /// Who sends the media stream `stream`, i.e. whose tracks arrive in it.
+    pub fn sender_of_stream(&self, stream: &str) -> Option<&RosterEntry> {
+        self.participants.iter().find(|entry| entry.stream.as_deref() == Some(stream))
+    }
+}
+
+/// Clients waiting in the lobby, sent to hosts.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default, Debug)]
+pub struct LobbyUpdate {
+    pub waiting: Vec<RosterEntry>,
+}
+
+/// A host asks us to mute our microphone.
+#[derive(Serialize, Deserialize)]
+pub struct MuteRequest {
+    pub by: Option<String>,
+}
+
+/// Commands hosts send to the server, which checks the sender's role before acting.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ModeratorCommand {
+    MuteRequest { target: String },
+    Kick { target: String },
+    LockRoom { locked: bool },
+    Promote { target: String },
+    Admit { target: String },
+    Deny { target: String },
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum SignalMessage {
     Offer(String),
     Answer(String),
     IceCandidate(IceCandidateData),
+    Error(ServerError),
+    #[serde(rename = "server-shutting-down")]
+    ServerShuttingDown(ShutdownNotice),
+    Announcement(Announcement),
+    Welcome(RosterEntry),
+    Roster(Roster),
+    MuteRequest(MuteRequest),
+    Waiting(RosterEntry),
+    Lobby(LobbyUpdate),
+    Moderate(ModeratorCommand),
+}
+
+/// Where the page was loaded from, which is also where the server's endpoints are.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ServerOrigin {
+    /// Whether the page came over https, so sockets have to be secure too.
+    pub secure: bool,
+    /// As in `location.host`.
+    pub host: String,
+}
+
+impl ServerOrigin {
+    /// `protocol` as in `location.protocol`, e.g. `"https:"`.
+    pub fn new(protocol: &str, host: &str) -> Self {
+        ServerOrigin { secure: protocol == "https:", host: host.to_string() }
+    }
+
+    /// The origin of the current page.
+    pub fn of_page() -> Result<Self, CallError> {
+        let location = window().ok_or_else(|| CallError::Signaling("no window".to_string()))?.location();
+        let protocol = location.protocol().map_err(|e| CallError::signaling("couldn't read the page's protocol", &e))?;
+        let host = location.host().map_err(|e| CallError::signaling("couldn't read the page's host", &e))?;
+        Ok(Self::new(&protocol, &host))
+    }
+
+    /// `http(s)://` URL of `path` on the server.
+    pub fn http_url(&self, path: &str) -> String {
+        format!("{}://{}{}", if self.secure { "https" } else { "http" }, self.host, path)
+    }
+
+    /// `ws(s)://` URL of `path` on the server.
+    pub fn ws_url(&self, path: &str) -> String {
+        format!("{}://{}{}", if self.secure { "wss" } else { "ws" }, self.host, path)
+    }
 }
 
 /// Helper to create a WebSocket from a URL.
-pub fn create_websocket(url: &str) -> WebSocket {
-    WebSocket::new(url).expect("Failed to create WebSocket")
+pub fn create_websocket(url: &str) -> Result<WebSocket, CallError> {
+    WebSocket::new(url).map_err(|e| CallError::signaling("couldn't open the signaling connection", &e))
+}
+
+/// Serializes `msg` for the signaling server.
+pub fn encode(msg: &SignalMessage) -> Result<String, CallError> {
+    serde_json::to_string(msg).map_err(|e| CallError::Signaling(format!("couldn't encode message: {}", e)))
+}
+
+/// Sends `msg` to the signaling server.
+pub fn send_signal(ws: &WebSocket, msg: &SignalMessage) -> Result<(), CallError> {
+    ws.send_with_str(&encode(msg)?).map_err(|e| CallError::signaling("couldn't send to the server", &e))
+}
+
+/// Value of `key` in a query string such as `location.search`; `None` when missing or empty.
+pub fn query_param(search: &str, key: &str) -> Option<String> {
+    UrlSearchParams::new_with_str(search).ok()?.get(key).filter(|value| !value.is_empty())
+}
+
+/// Signaling URL for joining `room` as `name` with `password`, as a viewer when `viewer` is
+/// set. The rest of the page's query string (`search`, e.g. a token) is passed on as it is.
+pub fn signaling_url(
+    origin: &ServerOrigin,
+    room: &str,
+    name: &str,
+    password: &str,
+    viewer: bool,
+    search: &str,
+) -> Result<String, CallError> {
+    let params = UrlSearchParams::new_with_str(search)
+        .or_else(|_| UrlSearchParams::new())
+        .map_err(|e| CallError::signaling("couldn't build the signaling URL", &e))?;
+    params.set("room", room);
+    if name.is_empty() {
+        params.delete("name");
+    } else {
+        params.set("name", name);
+    }
+    if password.is_empty() {
+        params.delete("password");
+    } else {
+        params.set("password", password);
+    }
+    if viewer {
+        params.set("viewer", "true");
+    } else {
+        params.delete("viewer");
+    }
+    Ok(origin.ws_url(&format!("/ws?{}", String::from(params.to_string()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secure_page_uses_secure_schemes() {
+        let origin = ServerOrigin::new("https:", "chat.example.com");
+        assert_eq!(origin.http_url("/visitors/events"), "https://chat.example.com/visitors/events");
+        assert_eq!(origin.ws_url("/visitors"), "wss://chat.example.com/visitors");
+    }
+
+    #[test]
+    fn reads_the_shutdown_notice() {
+        let msg = serde_json::from_str(r#"{"type":"server-shutting-down","data":{"reconnectAfterMs":5000}}"#);
+        assert!(matches!(msg, Ok(SignalMessage::ServerShuttingDown(ShutdownNotice { reconnect_after_ms: 5000 }))));
+    }
+
+    #[test]
+    fn plain_page_uses_plain_schemes() {
+        let origin = ServerOrigin::new("http:", "localhost:8080");
+        assert_eq!(origin.http_url("/visitors/events"), "http://localhost:8080/visitors/events");
+        assert_eq!(origin.ws_url("/ws?room=a"), "ws://localhost:8080/ws?room=a");
+    }
+
+    #[test]
+    fn tells_password_refusals_from_other_errors() {
+        let refusal = |json: &str| match serde_json::from_str(json) {
+            Ok(SignalMessage::Error(err)) => err.is_password_refusal(),
+            _ => panic!("not an error: {}", json),
+        };
+        assert!(refusal(r#"{"type":"Error","data":{"code":"wrong-password","message":"Wrong room password."}}"#));
+        assert!(refusal(r#"{"type":"Error","data":{"code":"password-required","message":"Password?"}}"#));
+        assert!(!refusal(r#"{"type":"Error","data":{"code":"room-full","message":"This room is full."}}"#));
+    }
+
+    #[test]
+    fn finds_the_sender_of_each_stream() {
+        let roster: Roster = serde_json::from_str(
+            r#"{"locked":false,"participants":[
+                {"id":"1","name":"Ann","role":"host","stream":"cam-a"},
+                {"id":"2","name":null,"role":"participant","stream":"cam-b"},
+                {"id":"3","name":null,"role":"participant","viewer":true}
+            ]}"#,
+        )
+        .unwrap();
+        let sender = |stream: &str| roster.sender_of_stream(stream).map(|entry| entry.id.as_str());
+        assert_eq!(sender("cam-a"), Some("1"));
+        assert_eq!(sender("cam-b"), Some("2"));
+        assert_eq!(sender("cam-c"), None);
+    }
 }
@@ -1,61 +1,275 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use gloo_timers::callback::Timeout;
+use serde::Deserialize;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::window;
+use web_sys::{CloseEvent, Event, EventSource, MessageEvent, WebSocket};
 use yew::prelude::*;
 
-#[function_component(VisitorCounter)]
-pub fn visitor_counter() -> Html {
-    // use_effect to inject the visitor counter script once when the component mounts
-    use_effect(|| {
-        if let Some(document) = window().and_then(|w| w.document()) {
-            // Create a new <script> element.
-            if let Ok(script) = document.create_element("script") {
-                script.set_text_content(Some(
-r#"(function () {
-    const wsUrl = `wss://${window.location.host}/visitors`;
-    const countElem = document.getElementById("visitor-count");
-    if (!countElem) {
-      console.error("No element with id 'visitor-count' found.");
-      return;
-    }
-    const ws = new WebSocket(wsUrl);
-    ws.onopen = function () {
-      console.log("Connected to visitor counter.");
-    };
-    ws.onmessage = function (event) {
-      try {
-        const data = JSON.parse(event.data);
-        if (data.visitorCount !== undefined) {
-          countElem.textContent = data.visitorCount;
-        }
-      } catch (err) {
-        console.error("Error parsing visitor count message:", err);
-      }
-    };
-    ws.onclose = function () {
-      console.log("Disconnected from visitor counter.");
-    };
-    window.addEventListener("beforeunload", function () {
-      ws.close();
-    });
-})();"#
-                ));
-                // Append the script to the body.
-                if let Some(body) = document.body() {
-                    let _ = body.append_child(&script);
+use crate::signaling::ServerOrigin;
+
+/// First reconnect delay; doubled after every failed attempt.
+const INITIAL_RETRY_MS: u32 = 1_000;
+/// Upper bound for the reconnect delay.
+const MAX_RETRY_MS: u32 = 30_000;
+/// How long a new event stream may stay silent before we assume a proxy is
+/// buffering it; the server sends the stats right away.
+const SSE_FIRST_MESSAGE_MS: u32 = 10_000;
+
+/// Numbers pushed by the server on `/visitors`.
+#[derive(Deserialize, Clone, PartialEq, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VisitorStats {
+    pub visitor_count: usize,
+    #[serde(default)]
+    pub unique_visitors_today: usize,
+    #[serde(default)]
+    pub peak_visitors: usize,
+    #[serde(default)]
+    pub calls_started: u64,
+    #[serde(default)]
+    pub active_calls: usize,
+    #[serde(default)]
+    pub people_in_calls: usize,
+}
+
+/// Message pushed by the server on `/visitors`: the stats, or a shutdown notice.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VisitorUpdate {
+    #[serde(flatten)]
+    stats: Option<VisitorStats>,
+    #[serde(default)]
+    server_shutting_down: bool,
+    reconnect_after_ms: Option<u64>,
+}
+
+/// The visitor feed of a mounted counter. Listens on `/visitors/events` (server-sent
+/// events) and falls back to the `/visitors` WebSocket when the event stream never
+/// delivers anything, e.g. behind a proxy that buffers it. Reconnects with backoff
+/// when the connection drops, until [`VisitorFeed::stop`] is called on unmount.
+struct VisitorFeed {
+    /// Server to connect to.
+    origin: ServerOrigin,
+    /// Whether to try server-sent events before the WebSocket.
+    use_sse: bool,
+    /// Whether the current connection delivered a message yet.
+    received: bool,
+    events: Option<EventSource>,
+    socket: Option<WebSocket>,
+    on_message: Option<Closure<dyn FnMut(MessageEvent)>>,
+    on_close: Option<Closure<dyn FnMut(CloseEvent)>>,
+    on_error: Option<Closure<dyn FnMut(Event)>>,
+    /// Gives up on the event stream if it delivers nothing in time.
+    sse_deadline: Option<Timeout>,
+    retry: Option<Timeout>,
+    /// Delay before the next reconnect attempt.
+    retry_ms: u32,
+    stopped: bool,
+    on_stats: Callback<VisitorStats>,
+}
+
+impl VisitorFeed {
+    fn start(origin: ServerOrigin, on_stats: Callback<VisitorStats>) -> Rc<RefCell<Self>> {
+        let feed = Rc::new(RefCell::new(VisitorFeed {
+            origin,
+            use_sse: true,
+            received: false,
+            events: None,
+            socket: None,
+            on_message: None,
+            on_close: None,
+            on_error: None,
+            sse_deadline: None,
+            retry: None,
+            retry_ms: INITIAL_RETRY_MS,
+            stopped: false,
+            on_stats,
+        }));
+        Self::connect(&feed);
+        feed
+    }
+
+    fn connect(feed: &Rc<RefCell<Self>>) {
+        let mut this = feed.borrow_mut();
+        if this.stopped {
+            return;
+        }
+        this.retry = None;
+        this.received = false;
+
+        let weak = Rc::downgrade(feed);
+        let on_message = Closure::wrap(Box::new(move |evt: MessageEvent| {
+            let Some(text) = evt.data().as_string() else {
+                return;
+            };
+            match serde_json::from_str::<VisitorUpdate>(&text) {
+                Ok(update) => Self::handle_update(&weak, update),
+                Err(e) => web_sys::console::error_1(&format!("Error parsing visitor count message: {}", e).into()),
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+
+        if this.use_sse {
+            match EventSource::new(&this.origin.http_url("/visitors/events")) {
+                Ok(events) => {
+                    events.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+                    let weak = Rc::downgrade(feed);
+                    let on_error = Closure::wrap(Box::new(move |_: Event| {
+                        if let Some(feed) = weak.upgrade() {
+                            Self::handle_sse_error(&feed);
+                        }
+                    }) as Box<dyn FnMut(Event)>);
+                    events.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+                    this.events = Some(events);
+                    this.on_message = Some(on_message);
+                    this.on_error = Some(on_error);
+                    let weak = Rc::downgrade(feed);
+                    this.sse_deadline = Some(Timeout::new(SSE_FIRST_MESSAGE_MS, move || {
+                        if let Some(feed) = weak.upgrade() {
+                            Self::handle_sse_error(&feed);
+                        }
+                    }));
+                    return;
+                }
+                Err(e) => {
+                    web_sys::console::log_1(&format!("No server-sent events, using the WebSocket: {:?}", e).into());
+                    this.use_sse = false;
                 }
             }
         }
-        || ()
-    });
+
+        let socket = match WebSocket::new(&this.origin.ws_url("/visitors")) {
+            Ok(socket) => socket,
+            Err(e) => {
+                web_sys::console::error_1(&format!("Failed to open visitor counter socket: {:?}", e).into());
+                drop(this);
+                Self::schedule_reconnect(feed);
+                return;
+            }
+        };
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let weak = Rc::downgrade(feed);
+        let on_close = Closure::wrap(Box::new(move |_: CloseEvent| {
+            web_sys::console::log_1(&"Disconnected from visitor counter.".into());
+            if let Some(feed) = weak.upgrade() {
+                Self::schedule_reconnect(&feed);
+            }
+        }) as Box<dyn FnMut(CloseEvent)>);
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        this.socket = Some(socket);
+        this.on_message = Some(on_message);
+        this.on_close = Some(on_close);
+    }
+
+    fn handle_update(weak: &Weak<RefCell<Self>>, update: VisitorUpdate) {
+        let Some(feed) = weak.upgrade() else {
+            return;
+        };
+        if let Some(stats) = update.stats {
+            let mut this = feed.borrow_mut();
+            // A message means the connection works, so start over with short delays.
+            this.retry_ms = INITIAL_RETRY_MS;
+            this.received = true;
+            this.sse_deadline = None;
+            this.on_stats.emit(stats);
+        }
+        if update.server_shutting_down {
+            // Wait as long as the server asked before knocking again.
+            let delay = update.reconnect_after_ms.map(|ms| ms.min(MAX_RETRY_MS as u64) as u32);
+            feed.borrow_mut().retry_ms = delay.unwrap_or(INITIAL_RETRY_MS);
+        }
+    }
+
+    /// The event stream failed. If it never delivered anything, something between us
+    /// and the server doesn't pass it through, so switch to the WebSocket for good.
+    /// Otherwise reconnect with our own backoff rather than the browser's.
+    fn handle_sse_error(feed: &Rc<RefCell<Self>>) {
+        {
+            let mut this = feed.borrow_mut();
+            if !this.received && this.use_sse {
+                web_sys::console::log_1(&"Visitor event stream failed, falling back to the WebSocket.".into());
+                this.use_sse = false;
+            } else {
+                web_sys::console::log_1(&"Disconnected from visitor counter.".into());
+            }
+        }
+        Self::schedule_reconnect(feed);
+    }
+
+    /// Forgets the current socket and tries again after the backoff delay.
+    fn schedule_reconnect(feed: &Rc<RefCell<Self>>) {
+        let mut this = feed.borrow_mut();
+        if this.stopped || this.retry.is_some() {
+            return;
+        }
+        this.detach_socket();
+        let delay = this.retry_ms;
+        this.retry_ms = (this.retry_ms * 2).min(MAX_RETRY_MS);
+        let weak = Rc::downgrade(feed);
+        this.retry = Some(Timeout::new(delay, move || {
+            if let Some(feed) = weak.upgrade() {
+                Self::connect(&feed);
+            }
+        }));
+    }
+
+    /// Unhooks the handlers from the socket or event stream and closes it. The closures
+    /// themselves stay alive, since this may run inside one of them; `connect` replaces them.
+    fn detach_socket(&mut self) {
+        self.sse_deadline = None;
+        if let Some(events) = self.events.take() {
+            events.set_onmessage(None);
+            events.set_onerror(None);
+            events.close();
+        }
+        if let Some(socket) = self.socket.take() {
+            socket.set_onmessage(None);
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+    }
+
+    /// Closes the socket for good.
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.retry = None;
+        self.detach_socket();
+        self.on_message = None;
+        self.on_close = None;
+        self.on_error = None;
+    }
+}
+
+/// Shows how many people currently have the site open and how many are in calls.
+#[function_component(VisitorCounter)]
+pub fn visitor_counter() -> Html {
+    let stats = use_state(VisitorStats::default);
+
+    {
+        let stats = stats.clone();
+        use_effect_with_deps(
+            move |_| {
+                let origin = ServerOrigin::of_page().unwrap_or_else(|_| ServerOrigin::new("https:", ""));
+                let on_stats = Callback::from(move |update: VisitorStats| stats.set(update));
+                let feed = VisitorFeed::start(origin, on_stats);
+                move || feed.borrow_mut().stop()
+            },
+            (),
+        );
+    }
 
     html! {
-        html! {
-            <div id="visitor-counter" style="margin-top: 20px; font-size: 18px; color: #000;">
-                { "This site has currently " }
-                <span id="visitor-count" style="color: #000;">{ "0" }</span>
-                { " visitors" }
+        <div id="visitor-counter" style="margin-top: 20px; font-size: 18px; color: #000;">
+            { "This site has currently " }
+            <span id="visitor-count" style="color: #000;">{ stats.visitor_count }</span>
+            { " visitors" }
+            <div style="font-size: 14px; margin-top: 6px;">
+                { format!("{} people in calls right now", stats.people_in_calls) }
+                { format!(" · {} visitors today · peak {} · {} calls so far", stats.unique_visitors_today, stats.peak_visitors, stats.calls_started) }
             </div>
-        }
+        </div>
     }
 }
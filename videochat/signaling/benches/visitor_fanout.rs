@@ -0,0 +1,80 @@
+//! Fan-out of a visitor stats update to thousands of visitors, and the cost of a
+//! visitor joining and leaving, for the broadcast hub against the `Vec` of
+//! per-visitor `mpsc` senders it replaced.
+//!
+//! Run with `cargo bench -p signaling --bench visitor_fanout`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio::sync::mpsc;
+use warp::ws::Message;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/visitors.rs"]
+mod visitors;
+
+use visitors::VisitorHub;
+
+const VISITORS: [usize; 3] = [1_000, 5_000, 10_000];
+
+fn update() -> Message {
+    Message::text(r#"{"visitorCount":10000,"uniqueVisitorsToday":12000,"peakVisitors":10000,"peakAt":0,"callsStarted":42,"activeCalls":3,"peopleInCalls":9}"#)
+}
+
+fn fan_out(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let mut group = c.benchmark_group("fan_out");
+    for n in VISITORS {
+        group.bench_with_input(BenchmarkId::new("broadcast_hub", n), &n, |b, &n| {
+            let hub = VisitorHub::new(16);
+            let mut handles: Vec<_> = (0..n).map(|_| hub.join().0).collect();
+            b.iter(|| {
+                hub.send(update());
+                runtime.block_on(async {
+                    for handle in handles.iter_mut() {
+                        handle.recv().await.unwrap();
+                    }
+                });
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("mpsc_vec", n), &n, |b, &n| {
+            let (mut senders, mut receivers): (Vec<_>, Vec<_>) = (0..n).map(|_| mpsc::channel::<Message>(16)).unzip();
+            b.iter(|| {
+                let msg = update();
+                senders.retain(|tx| tx.try_send(msg.clone()).is_ok());
+                for rx in receivers.iter_mut() {
+                    rx.try_recv().unwrap();
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+fn join_leave(c: &mut Criterion) {
+    let mut group = c.benchmark_group("join_leave");
+    for n in VISITORS {
+        group.bench_with_input(BenchmarkId::new("broadcast_hub", n), &n, |b, &n| {
+            let hub = VisitorHub::new(16);
+            let _others: Vec<_> = (0..n).map(|_| hub.join().0).collect();
+            b.iter(|| {
+                let (handle, count) = hub.join();
+                drop(handle);
+                assert_eq!(count, n + 1);
+                assert_eq!(hub.count(), n);
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("mpsc_vec", n), &n, |b, &n| {
+            let (mut senders, _receivers): (Vec<_>, Vec<_>) = (0..n).map(|_| mpsc::channel::<Message>(16)).unzip();
+            b.iter(|| {
+                let (tx, rx) = mpsc::channel::<Message>(16);
+                senders.push(tx.clone());
+                senders.retain(|sender| !sender.same_channel(&tx));
+                drop(rx);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fan_out, join_leave);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+-- Rooms created through the API, which outlive their participants.
+CREATE TABLE rooms (
+    name TEXT PRIMARY KEY NOT NULL,
+    -- Argon2 PHC string; NULL for rooms without a password.
+    password_hash TEXT,
+    token_required INTEGER NOT NULL DEFAULT 0,
+    lobby INTEGER NOT NULL DEFAULT 0,
+    locked INTEGER NOT NULL DEFAULT 0,
+    owner TEXT,
+    created_at INTEGER NOT NULL
+);
+
+-- Meetings scheduled in a room.
+CREATE TABLE meetings (
+    id TEXT PRIMARY KEY NOT NULL,
+    room TEXT NOT NULL,
+    title TEXT NOT NULL,
+    starts_at INTEGER NOT NULL,
+    duration_mins INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX meetings_starts_at ON meetings (starts_at);
+
+-- One row per call: from the first offer or answer until the room empties.
+CREATE TABLE calls (
+    id TEXT PRIMARY KEY NOT NULL,
+    room TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER
+);
+CREATE INDEX calls_started_at ON calls (started_at);
+CREATE INDEX calls_room ON calls (room, started_at);
+
+-- Everyone who was in a call and when.
+CREATE TABLE call_participants (
+    call_id TEXT NOT NULL REFERENCES calls (id) ON DELETE CASCADE,
+    client_id TEXT NOT NULL,
+    name TEXT,
+    joined_at INTEGER NOT NULL,
+    left_at INTEGER,
+    PRIMARY KEY (call_id, client_id)
+);
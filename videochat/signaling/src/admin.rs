@@ -0,0 +1,433 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::SharedAuth;
+use crate::errors::ApiError;
+use crate::protocol::ServerMessage;
+use crate::rooms::{in_join_order, ClientId, Participant, Role, Room, Rooms};
+use crate::storage::{self, SharedStore};
+
+/// Largest JSON body accepted by the admin API.
+const MAX_BODY_BYTES: u64 = 16 * 1024;
+/// Longest announcement that is relayed to clients.
+const MAX_ANNOUNCEMENT_CHARS: usize = 1000;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ParticipantInfo {
+    /// Client IDs don't fit into a JavaScript number, so they are sent as strings.
+    pub id: String,
+    pub name: Option<String>,
+    pub role: Role,
+    pub viewer: bool,
+    pub ip: Option<String>,
+    pub connected_since: u64,
+    pub messages_sent: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomInfo {
+    pub name: String,
+    pub locked: bool,
+    pub lobby: bool,
+    pub persistent: bool,
+    pub owner: Option<String>,
+    pub password_protected: bool,
+    pub token_required: bool,
+    pub participants: Vec<ParticipantInfo>,
+    /// Clients waiting in the lobby.
+    pub waiting: Vec<ParticipantInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LockRequest {
+    pub locked: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnnouncementRequest {
+    pub message: String,
+    /// Only announce in this room; all rooms when missing.
+    pub room: Option<String>,
+}
+
+impl ParticipantInfo {
+    fn new(participant: &Participant) -> Self {
+        ParticipantInfo {
+            id: participant.id.to_string(),
+            name: participant.name.clone(),
+            role: participant.role,
+            viewer: participant.viewer,
+            ip: participant.ip.map(|ip| ip.to_string()),
+            connected_since: participant.connected_at,
+            messages_sent: participant.messages_sent,
+        }
+    }
+}
+
+impl RoomInfo {
+    fn new(name: &str, room: &Room) -> Self {
+        RoomInfo {
+            name: name.to_string(),
+            locked: room.locked,
+            lobby: room.lobby,
+            persistent: room.persistent,
+            owner: room.owner.clone(),
+            password_protected: room.password_hash.is_some(),
+            token_required: room.token_required,
+            participants: in_join_order(&room.participants).into_iter().map(ParticipantInfo::new).collect(),
+            waiting: in_join_order(&room.waiting).into_iter().map(ParticipantInfo::new).collect(),
+        }
+    }
+}
+
+/// Admin API under `/admin`, all endpoints need the API key:
+///
+/// * `GET /admin/rooms` lists rooms and their participants.
+/// * `GET /admin/rooms/{room}` shows a single room.
+/// * `DELETE /admin/rooms/{room}` disconnects everyone in the room and removes it,
+///   from the store as well.
+/// * `PUT /admin/rooms/{room}/lock` with `{"locked": bool}` locks or unlocks a room.
+///   Stored rooms stay locked across restarts.
+/// * `DELETE /admin/rooms/{room}/participants/{id}` kicks a participant.
+/// * `POST /admin/announcements` with `{"message": ..., "room": ...}` sends an
+///   announcement to one room or, without `room`, to every signaling client.
+pub fn routes(rooms: Rooms, auth: SharedAuth, store: SharedStore) -> BoxedFilter<(Response,)> {
+    let rooms_filter = warp::any().map(move || rooms.clone());
+    let store_filter = warp::any().map(move || store.clone());
+    // Every admin endpoint checks the API key before doing anything else.
+    let authorized = warp::header::optional::<String>("authorization")
+        .and(warp::any().map(move || auth.clone()))
+        .and_then(|authorization: Option<String>, auth: SharedAuth| async move {
+            auth.check_api_key(authorization.as_deref()).map_err(|e| {
+                eprintln!("Rejected admin request: {}", e);
+                warp::reject::custom(e)
+            })
+        })
+        .untuple_one();
+    let admin = warp::path("admin").and(authorized);
+
+    let list_rooms = admin
+        .clone()
+        .and(warp::path!("rooms"))
+        .and(warp::get())
+        .and(rooms_filter.clone())
+        .map(list_rooms);
+
+    let get_room = admin
+        .clone()
+        .and(warp::path!("rooms" / String))
+        .and(warp::get())
+        .and(rooms_filter.clone())
+        .and_then(get_room);
+
+    let close_room = admin
+        .clone()
+        .and(warp::path!("rooms" / String))
+        .and(warp::delete())
+        .and(rooms_filter.clone())
+        .and(store_filter.clone())
+        .and_then(close_room);
+
+    let lock_room = admin
+        .clone()
+        .and(warp::path!("rooms" / String / "lock"))
+        .and(warp::put())
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(rooms_filter.clone())
+        .and(store_filter)
+        .and_then(lock_room);
+
+    let kick = admin
+        .clone()
+        .and(warp::path!("rooms" / String / "participants" / ClientId))
+        .and(warp::delete())
+        .and(rooms_filter.clone())
+        .and_then(kick_participant);
+
+    let announce = admin
+        .and(warp::path!("announcements"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(rooms_filter)
+        .and_then(announce);
+
+    list_rooms
+        .or(get_room)
+        .unify()
+        .or(close_room)
+        .unify()
+        .or(lock_room)
+        .unify()
+        .or(kick)
+        .unify()
+        .or(announce)
+        .unify()
+        .boxed()
+}
+
+fn room_not_found() -> Rejection {
+    warp::reject::custom(ApiError::new(StatusCode::NOT_FOUND, "room not found"))
+}
+
+fn list_rooms(rooms: Rooms) -> Response {
+    let mut list: Vec<RoomInfo> = rooms.iter().map(|entry| RoomInfo::new(entry.key(), entry.value())).collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    warp::reply::json(&json!({ "rooms": list })).into_response()
+}
+
+async fn get_room(name: String, rooms: Rooms) -> Result<Response, Rejection> {
+    let room = rooms.get(&name).ok_or_else(room_not_found)?;
+    Ok(warp::reply::json(&RoomInfo::new(&name, &room)).into_response())
+}
+
+async fn close_room(name: String, rooms: Rooms, store: SharedStore) -> Result<Response, Rejection> {
+    let (_, mut room) = rooms.remove(&name).ok_or_else(room_not_found)?;
+    for participant in room.members() {
+        participant.disconnect("room-closed", "This room was closed by an administrator.");
+    }
+    println!("Admin closed room {} with {} clients", name, room.members().count());
+    if let Some(call) = room.end_call() {
+        storage::spawn_update(store.save_call(call));
+    }
+    if room.persistent {
+        if let Err(e) = store.delete_room(name.clone()).await {
+            eprintln!("Failed to delete stored room {}: {}", name, e);
+            return Err(warp::reject::custom(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "room closed but still stored; it comes back after a restart",
+            )));
+        }
+    }
+    Ok(warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT).into_response())
+}
+
+async fn lock_room(name: String, request: LockRequest, rooms: Rooms, store: SharedStore) -> Result<Response, Rejection> {
+    // The room can't be held across the store update, so the reply is built up front.
+    let (persistent, info) = {
+        let mut room = rooms.get_mut(&name).ok_or_else(room_not_found)?;
+        room.locked = request.locked;
+        room.send_roster();
+        (room.persistent, RoomInfo::new(&name, &room))
+    };
+    println!("Admin {} room {}", if request.locked { "locked" } else { "unlocked" }, name);
+    if persistent {
+        if let Err(e) = store.set_room_locked(name.clone(), request.locked).await {
+            eprintln!("Failed to store lock state of room {}: {}", name, e);
+            return Err(warp::reject::custom(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "failed to store the room")));
+        }
+    }
+    Ok(warp::reply::json(&info).into_response())
+}
+
+async fn kick_participant(name: String, client_id: ClientId, rooms: Rooms) -> Result<Response, Rejection> {
+    let mut room = rooms.get_mut(&name).ok_or_else(room_not_found)?;
+    let participant = room
+        .remove_participant(client_id)
+        .ok_or_else(|| warp::reject::custom(ApiError::new(StatusCode::NOT_FOUND, "participant not found")))?;
+    participant.disconnect("kicked", "You were removed from the room by an administrator.");
+    room.ensure_host();
+    room.send_roster();
+    println!("Admin kicked client {} from room {}", client_id, name);
+    Ok(warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT).into_response())
+}
+
+async fn announce(request: AnnouncementRequest, rooms: Rooms) -> Result<Response, Rejection> {
+    let message = request.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_ANNOUNCEMENT_CHARS {
+        return Err(warp::reject::custom(ApiError::bad_request(format!(
+            "announcement must be 1 to {} characters",
+            MAX_ANNOUNCEMENT_CHARS
+        ))));
+    }
+
+    let msg = ServerMessage::announcement(message).to_message();
+    let send_to = |room: &Room| {
+        room.participants.values().filter(|participant| participant.tx.try_send(msg.clone()).is_ok()).count()
+    };
+    let recipients: usize = match &request.room {
+        Some(name) => send_to(rooms.get(name).ok_or_else(room_not_found)?.value()),
+        None => rooms.iter().map(|entry| send_to(entry.value())).sum(),
+    };
+    println!("Admin announcement to {:?} delivered to {} clients", request.room, recipients);
+    Ok(warp::reply::json(&json!({ "recipients": recipients })).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::sync::mpsc::Receiver;
+    use warp::hyper::body::Bytes;
+    use warp::ws::Message;
+
+    use super::*;
+    use crate::auth::Auth;
+    use crate::storage::{MemoryStore, RoomRecord, Store};
+
+    type TestReply = warp::http::Response<Bytes>;
+
+    fn record(name: &str) -> RoomRecord {
+        RoomRecord {
+            name: name.to_string(),
+            password_hash: None,
+            token_required: false,
+            lobby: false,
+            locked: false,
+            owner: Some("ops".to_string()),
+            created_at: 0,
+        }
+    }
+
+    /// Rooms `retro` (stored, with client 1) and `standup` (with clients 2 and 3).
+    struct Fixture {
+        rooms: Rooms,
+        store: Arc<MemoryStore>,
+        api: BoxedFilter<(Response,)>,
+        clients: Vec<Receiver<Message>>,
+    }
+
+    async fn fixture(api_key: Option<&str>) -> Fixture {
+        let rooms = Rooms::default();
+        let store = Arc::new(MemoryStore::default());
+        store.save_room(record("retro")).await.unwrap();
+        let mut clients = Vec::new();
+        for (name, ids) in [("retro", &[1][..]), ("standup", &[2, 3][..])] {
+            let mut room = if name == "retro" { Room::from_record(record(name)) } else { Room::default() };
+            for &id in ids {
+                let (participant, rx) = Participant::connected(id, if id == 2 { Role::Host } else { Role::Participant });
+                room.add_participant(participant);
+                clients.push(rx);
+            }
+            rooms.insert(name.to_string(), room);
+        }
+        let auth = Arc::new(Auth::new(None, api_key.map(str::to_string)));
+        let api = routes(rooms.clone(), auth, store.clone())
+            .recover(crate::errors::handle_rejection)
+            .map(Reply::into_response)
+            .boxed();
+        Fixture { rooms, store, api, clients }
+    }
+
+    async fn request(api: &BoxedFilter<(Response,)>, method: &str, path: &str, body: Option<serde_json::Value>) -> TestReply {
+        let mut request = warp::test::request().method(method).path(path).header("authorization", "Bearer key");
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        request.reply(api).await
+    }
+
+    fn json(reply: &TestReply) -> serde_json::Value {
+        serde_json::from_slice(reply.body()).unwrap()
+    }
+
+    /// Texts queued for a client so far.
+    fn received(rx: &mut Receiver<Message>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok()).filter_map(|msg| msg.to_str().ok().map(str::to_string)).collect()
+    }
+
+    #[tokio::test]
+    async fn needs_the_api_key() {
+        let keyed = fixture(Some("key")).await;
+        let missing = warp::test::request().path("/admin/rooms").reply(&keyed.api).await;
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let wrong = warp::test::request()
+            .path("/admin/rooms")
+            .header("authorization", "Bearer nope")
+            .reply(&keyed.api)
+            .await;
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+        // Without a configured key the admin API is closed.
+        let unconfigured = fixture(None).await;
+        assert_eq!(request(&unconfigured.api, "GET", "/admin/rooms", None).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(request(&unconfigured.api, "DELETE", "/admin/rooms/retro", None).await.status(), StatusCode::FORBIDDEN);
+        assert!(unconfigured.rooms.contains_key("retro"));
+    }
+
+    #[tokio::test]
+    async fn lists_and_shows_rooms() {
+        let fixture = fixture(Some("key")).await;
+        let list = request(&fixture.api, "GET", "/admin/rooms", None).await;
+        assert_eq!(list.status(), StatusCode::OK);
+        let list = json(&list);
+        let names: Vec<&str> = list["rooms"].as_array().unwrap().iter().map(|room| room["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["retro", "standup"]);
+
+        let room = json(&request(&fixture.api, "GET", "/admin/rooms/standup", None).await);
+        let ids: Vec<&str> = room["participants"].as_array().unwrap().iter().map(|p| p["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["2", "3"]);
+        assert_eq!(room["persistent"], false);
+
+        assert_eq!(request(&fixture.api, "GET", "/admin/rooms/nope", None).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn kicks_a_participant() {
+        let mut fixture = fixture(Some("key")).await;
+        let reply = request(&fixture.api, "DELETE", "/admin/rooms/standup/participants/2", None).await;
+        assert_eq!(reply.status(), StatusCode::NO_CONTENT);
+        assert!(received(&mut fixture.clients[1]).iter().any(|text| text.contains("\"kicked\"")));
+        let room = fixture.rooms.get("standup").unwrap();
+        assert!(!room.participants.contains_key(&2));
+        // The host left, so the other participant took over.
+        assert_eq!(room.participants[&3].role, Role::Host);
+        drop(room);
+
+        let again = request(&fixture.api, "DELETE", "/admin/rooms/standup/participants/2", None).await;
+        assert_eq!(again.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn closing_a_room_removes_it_from_the_store() {
+        let mut fixture = fixture(Some("key")).await;
+        assert_eq!(request(&fixture.api, "DELETE", "/admin/rooms/retro", None).await.status(), StatusCode::NO_CONTENT);
+        assert!(!fixture.rooms.contains_key("retro"));
+        assert!(fixture.store.rooms().await.unwrap().is_empty());
+        assert!(received(&mut fixture.clients[0]).iter().any(|text| text.contains("\"room-closed\"")));
+
+        assert_eq!(request(&fixture.api, "DELETE", "/admin/rooms/retro", None).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn locks_are_stored_for_stored_rooms() {
+        let mut fixture = fixture(Some("key")).await;
+        let reply = request(&fixture.api, "PUT", "/admin/rooms/retro/lock", Some(serde_json::json!({ "locked": true }))).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(json(&reply)["locked"], true);
+        assert!(fixture.rooms.get("retro").unwrap().locked);
+        assert!(fixture.store.rooms().await.unwrap()[0].locked);
+        // Everyone in the room sees the lock in the roster.
+        assert!(received(&mut fixture.clients[0]).iter().any(|text| text.contains("\"locked\":true")));
+
+        let body = Some(serde_json::json!({ "locked": false }));
+        assert_eq!(request(&fixture.api, "PUT", "/admin/rooms/retro/lock", body).await.status(), StatusCode::OK);
+        assert!(!fixture.store.rooms().await.unwrap()[0].locked);
+    }
+
+    #[tokio::test]
+    async fn announces_to_one_room_or_all() {
+        let mut fixture = fixture(Some("key")).await;
+        let body = serde_json::json!({ "message": "Maintenance at noon", "room": "standup" });
+        let reply = request(&fixture.api, "POST", "/admin/announcements", Some(body)).await;
+        assert_eq!(json(&reply)["recipients"], 2);
+        assert!(received(&mut fixture.clients[0]).is_empty());
+        assert!(received(&mut fixture.clients[1]).iter().any(|text| text.contains("Maintenance at noon")));
+
+        let body = serde_json::json!({ "message": "Restarting soon" });
+        let reply = request(&fixture.api, "POST", "/admin/announcements", Some(body)).await;
+        assert_eq!(json(&reply)["recipients"], 3);
+        for client in &mut fixture.clients {
+            assert!(received(client).iter().any(|text| text.contains("Restarting soon")));
+        }
+
+        let blank = serde_json::json!({ "message": "   " });
+        assert_eq!(request(&fixture.api, "POST", "/admin/announcements", Some(blank)).await.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::unix_now;
+use crate::rooms::Rooms;
+use crate::time::SECS_PER_DAY;
+
+/// How often changed statistics are written to disk.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Counters that survive restarts, stored as JSON in the stats file.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PersistedStats {
+    /// Days since the Unix epoch (UTC) the unique visitor set belongs to.
+    day: u64,
+    /// Random salt of the day for hashing IPs. Never written to the stats file, so the
+    /// hashes in it can't be checked against guessed IPs; after a restart a new salt is
+    /// picked, and visitors seen earlier that day may be counted once more.
+    #[serde(skip)]
+    salt: u64,
+    unique_today: HashSet<u64>,
+    peak_visitors: usize,
+    /// Unix time the peak was reached.
+    peak_at: u64,
+    calls_started: u64,
+}
+
+/// Aggregate numbers pushed to every visitor on `/visitors`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VisitorStats {
+    pub visitor_count: usize,
+    pub unique_visitors_today: usize,
+    pub peak_visitors: usize,
+    pub peak_at: u64,
+    pub calls_started: u64,
+    /// Rooms with a call going on and the people in them.
+    pub active_calls: usize,
+    pub people_in_calls: usize,
+}
+
+/// Occupancy of a single room, only reported on the authenticated stats endpoint
+/// since room names are all that protects unlisted rooms.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomOccupancy {
+    pub room: String,
+    pub participants: usize,
+    pub waiting: usize,
+    pub in_call: bool,
+}
+
+/// Visitor and call statistics, optionally persisted to `path`.
+pub struct Analytics {
+    stats: Mutex<PersistedStats>,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+pub type SharedAnalytics = Arc<Analytics>;
+
+impl Analytics {
+    /// Loads the statistics from `path`, starting from zero when it doesn't exist yet.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let stats = match &path {
+            Some(path) => match std::fs::read(path) {
+                Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                    eprintln!("Ignoring unreadable stats file {}: {}", path.display(), e);
+                    PersistedStats::default()
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => PersistedStats::default(),
+                Err(e) => {
+                    eprintln!("Failed to read stats file {}: {}", path.display(), e);
+                    PersistedStats::default()
+                }
+            },
+            None => PersistedStats::default(),
+        };
+        let stats = PersistedStats { salt: rand::random(), ..stats };
+        println!(
+            "Visitor stats: peak {} visitors, {} calls started so far",
+            stats.peak_visitors, stats.calls_started
+        );
+        Analytics { stats: Mutex::new(stats), path, dirty: AtomicBool::new(false) }
+    }
+
+    /// Records a new visitor connection while `concurrent` visitors are connected.
+    pub fn record_visit(&self, ip: Option<IpAddr>, concurrent: usize) {
+        self.record_visit_at(ip, concurrent, unix_now());
+    }
+
+    fn record_visit_at(&self, ip: Option<IpAddr>, concurrent: usize, now: u64) {
+        let mut stats = self.stats.lock().unwrap();
+        roll_over(&mut stats, now / SECS_PER_DAY);
+        if let Some(ip) = ip {
+            let mut hasher = DefaultHasher::new();
+            stats.salt.hash(&mut hasher);
+            ip.hash(&mut hasher);
+            stats.unique_today.insert(hasher.finish());
+        }
+        if concurrent > stats.peak_visitors {
+            stats.peak_visitors = concurrent;
+            stats.peak_at = now;
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn record_call_started(&self) {
+        self.stats.lock().unwrap().calls_started += 1;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Current numbers for `visitors` open visitor sockets.
+    pub fn snapshot(&self, visitors: usize, rooms: &Rooms) -> VisitorStats {
+        let (active_calls, people_in_calls) = rooms
+            .iter()
+            .filter(|room| room.call.is_some())
+            .fold((0, 0), |(calls, people), room| (calls + 1, people + room.participants.len()));
+        let mut stats = self.stats.lock().unwrap();
+        roll_over(&mut stats, unix_now() / SECS_PER_DAY);
+        VisitorStats {
+            visitor_count: visitors,
+            unique_visitors_today: stats.unique_today.len(),
+            peak_visitors: stats.peak_visitors,
+            peak_at: stats.peak_at,
+            calls_started: stats.calls_started,
+            active_calls,
+            people_in_calls,
+        }
+    }
+
+    /// Writes the statistics to the stats file if they changed since the last save.
+    pub async fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let json = serde_json::to_vec(&*self.stats.lock().unwrap()).expect("stats always serialize");
+        // Write to a temporary file first so a crash never leaves a truncated file behind.
+        let tmp = path.with_extension("tmp");
+        let result = match tokio::fs::write(&tmp, json).await {
+            Ok(()) => tokio::fs::rename(&tmp, path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to save stats to {}: {}", path.display(), e);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Occupancy of every room, sorted by name.
+pub fn room_occupancy(rooms: &Rooms) -> Vec<RoomOccupancy> {
+    let mut occupancy: Vec<RoomOccupancy> = rooms
+        .iter()
+        .map(|entry| RoomOccupancy {
+            room: entry.key().clone(),
+            participants: entry.participants.len(),
+            waiting: entry.waiting.len(),
+            in_call: entry.call.is_some(),
+        })
+        .collect();
+    occupancy.sort_by(|a, b| a.room.cmp(&b.room));
+    occupancy
+}
+
+/// Starts a new day: forget yesterday's visitors and pick a fresh salt.
+fn roll_over(stats: &mut PersistedStats, today: u64) {
+    if stats.day != today {
+        stats.day = today;
+        stats.salt = rand::random();
+        stats.unique_today.clear();
+    }
+}
+
+/// Saves the statistics periodically.
+pub fn spawn_persistence(analytics: SharedAnalytics) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            analytics.save().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([192, 0, 2, last]))
+    }
+
+    fn today() -> u64 {
+        unix_now() / SECS_PER_DAY * SECS_PER_DAY
+    }
+
+    fn unique(analytics: &Analytics) -> usize {
+        analytics.stats.lock().unwrap().unique_today.len()
+    }
+
+    #[test]
+    fn counts_each_ip_once_a_day() {
+        let analytics = Analytics::load(None);
+        analytics.record_visit_at(ip(1), 1, today());
+        analytics.record_visit_at(ip(1), 1, today() + 60);
+        analytics.record_visit_at(ip(2), 2, today() + 120);
+        analytics.record_visit_at(None, 3, today() + 180);
+        assert_eq!(unique(&analytics), 2);
+    }
+
+    #[test]
+    fn keeps_the_peak_and_when_it_was_reached() {
+        let analytics = Analytics::load(None);
+        analytics.record_visit_at(ip(1), 1, today());
+        analytics.record_visit_at(ip(2), 3, today() + 60);
+        analytics.record_visit_at(ip(3), 2, today() + 120);
+        let stats = analytics.snapshot(2, &Rooms::default());
+        assert_eq!((stats.peak_visitors, stats.peak_at), (3, today() + 60));
+        assert_eq!(stats.visitor_count, 2);
+    }
+
+    #[test]
+    fn a_new_day_forgets_the_visitors_and_the_salt() {
+        let analytics = Analytics::load(None);
+        let yesterday = today() - SECS_PER_DAY;
+        analytics.record_visit_at(ip(1), 1, yesterday);
+        analytics.record_visit_at(ip(2), 1, yesterday);
+        let salt = analytics.stats.lock().unwrap().salt;
+
+        analytics.record_visit_at(ip(1), 1, today());
+        assert_eq!(unique(&analytics), 1);
+        assert_ne!(analytics.stats.lock().unwrap().salt, salt);
+        // The peak isn't per day.
+        assert_eq!(analytics.snapshot(0, &Rooms::default()).peak_at, yesterday);
+    }
+
+    #[tokio::test]
+    async fn survives_a_restart_without_storing_the_salt() {
+        let path = std::env::temp_dir().join(format!("visitor-stats-{:016x}.json", rand::random::<u64>()));
+        let analytics = Analytics::load(Some(path.clone()));
+        analytics.record_visit(ip(1), 4);
+        analytics.record_visit(ip(2), 2);
+        analytics.record_call_started();
+        analytics.save().await;
+
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert!(json.get("salt").is_none());
+
+        let reloaded = Analytics::load(Some(path.clone()));
+        let stats = reloaded.snapshot(0, &Rooms::default());
+        assert_eq!(stats.unique_visitors_today, 2);
+        assert_eq!(stats.peak_visitors, 4);
+        assert_eq!(stats.calls_started, 1);
+        assert_ne!(reloaded.stats.lock().unwrap().salt, analytics.stats.lock().unwrap().salt);
+        std::fs::remove_file(path).unwrap();
+    }
+}
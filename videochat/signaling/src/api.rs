@@ -0,0 +1,203 @@
+use dashmap::mapref::entry::Entry;
+use serde::Deserialize;
+use serde_json::json;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::analytics::{room_occupancy, SharedAnalytics};
+use crate::auth::{hash_password, unix_now, SharedAuth};
+use crate::errors::ApiError;
+use crate::limits::{Limits, SharedLimits};
+use crate::rooms::{is_valid_room_name, Role, Room, Rooms};
+use crate::storage::{RoomRecord, SharedStore, Store};
+use crate::visitors::Visitors;
+
+/// Largest JSON body accepted by the API.
+const MAX_BODY_BYTES: u64 = 16 * 1024;
+/// Lifetime of a join token when the caller doesn't ask for one.
+const DEFAULT_TOKEN_TTL_SECS: u64 = 60 * 60;
+/// Upper bound on requested token lifetimes.
+const MAX_TOKEN_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Deserialize, Debug)]
+pub struct CreateRoomRequest {
+    pub name: String,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub token_required: bool,
+    /// Hold joiners in a lobby until a host admits them.
+    #[serde(default)]
+    pub lobby: bool,
+    /// Who the room belongs to, e.g. a user ID in the caller's system. Only stored
+    /// and shown to admins.
+    pub owner: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MintTokenRequest {
+    pub room: String,
+    #[serde(default)]
+    pub role: Role,
+    pub name: Option<String>,
+    pub ttl_secs: Option<u64>,
+}
+
+/// HTTP API under `/api`:
+///
+/// * `POST /api/rooms` creates a room protected by a password and/or join tokens.
+///   The room is stored and comes back after a restart. Needs the API key when one
+///   is configured.
+/// * `POST /api/tokens` mints a join token. Always needs the API key, since this is
+///   meant to be called by a trusted backend.
+/// * `GET /api/stats` returns the visitor and call numbers also pushed on `/visitors`.
+/// * `GET /api/stats/rooms` adds the occupancy of every room. Always needs the API key,
+///   since it lists room names.
+pub fn routes(
+    rooms: Rooms,
+    visitors: Visitors,
+    auth: SharedAuth,
+    limits: SharedLimits,
+    analytics: SharedAnalytics,
+    store: SharedStore,
+) -> BoxedFilter<(Response,)> {
+    let rooms_filter = warp::any().map(move || rooms.clone());
+    let visitors_filter = warp::any().map(move || visitors.clone());
+    let analytics_filter = warp::any().map(move || analytics.clone());
+    let limits_filter = warp::any().map(move || limits.clone());
+    let auth_filter = warp::any().map(move || auth.clone());
+    let store_filter = warp::any().map(move || store.clone());
+
+    let create_room = warp::path!("api" / "rooms")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(rooms_filter.clone())
+        .and(auth_filter.clone())
+        .and(limits_filter)
+        .and(store_filter)
+        .and_then(create_room);
+
+    let mint_token = warp::path!("api" / "tokens")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and_then(mint_token);
+
+    let stats = warp::path!("api" / "stats")
+        .and(warp::get())
+        .and(visitors_filter)
+        .and(rooms_filter.clone())
+        .and(analytics_filter)
+        .map(|visitors: Visitors, rooms: Rooms, analytics: SharedAnalytics| {
+            warp::reply::json(&analytics.snapshot(visitors.count(), &rooms)).into_response()
+        });
+
+    let room_stats = warp::path!("api" / "stats" / "rooms")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(rooms_filter)
+        .and(auth_filter)
+        .and_then(room_stats);
+
+    create_room.or(mint_token).unify().or(stats).unify().or(room_stats).unify().boxed()
+}
+
+async fn create_room(
+    authorization: Option<String>,
+    request: CreateRoomRequest,
+    rooms: Rooms,
+    auth: SharedAuth,
+    limits: SharedLimits,
+    store: SharedStore,
+) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    if !is_valid_room_name(&request.name) {
+        return Err(ApiError::bad_request("invalid room name").into());
+    }
+
+    let record = RoomRecord {
+        name: request.name.clone(),
+        password_hash: hash_room_password(request.password).await?,
+        token_required: request.token_required,
+        lobby: request.lobby,
+        locked: false,
+        owner: request.owner,
+        created_at: unix_now(),
+    };
+    add_stored_room(&rooms, &limits, store.as_ref(), record).await?;
+    println!(
+        "Created room {} (token required: {}, lobby: {})",
+        request.name, request.token_required, request.lobby
+    );
+
+    let body = json!({ "room": request.name, "tokenRequired": request.token_required, "lobby": request.lobby });
+    Ok(warp::reply::with_status(warp::reply::json(&body), StatusCode::CREATED).into_response())
+}
+
+/// Hashes a room password on the blocking thread pool. Missing and empty passwords
+/// mean the room has none.
+pub async fn hash_room_password(password: Option<String>) -> Result<Option<String>, ApiError> {
+    match password {
+        Some(password) if !password.is_empty() => tokio::task::spawn_blocking(move || hash_password(&password))
+            .await
+            .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Opens a room created through the API and stores it so it survives restarts.
+/// Fails if the name is taken or the room limit is reached.
+pub async fn add_stored_room(
+    rooms: &Rooms,
+    limits: &Limits,
+    store: &dyn Store,
+    record: RoomRecord,
+) -> Result<(), ApiError> {
+    // Counted before taking the room's entry, which locks its shard.
+    let room_count = rooms.len();
+    match rooms.entry(record.name.clone()) {
+        Entry::Occupied(_) => return Err(ApiError::new(StatusCode::CONFLICT, "room already exists")),
+        Entry::Vacant(_) if !limits.can_create_room(room_count) => {
+            return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "room limit reached"));
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(Room::from_record(record.clone()));
+        }
+    }
+    // The room is taken in the map first so a concurrent request for the same name
+    // gets a conflict; undo that if the room can't be stored.
+    let name = record.name.clone();
+    if let Err(e) = store.save_room(record).await {
+        eprintln!("Failed to store room {}: {}", name, e);
+        rooms.remove(&name);
+        return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "failed to store the room"));
+    }
+    Ok(())
+}
+
+async fn mint_token(
+    authorization: Option<String>,
+    request: MintTokenRequest,
+    auth: SharedAuth,
+) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    if !is_valid_room_name(&request.room) {
+        return Err(ApiError::bad_request("invalid room name").into());
+    }
+
+    let ttl_secs = request.ttl_secs.unwrap_or(DEFAULT_TOKEN_TTL_SECS).min(MAX_TOKEN_TTL_SECS);
+    let (token, expires_at) = auth.mint(&request.room, request.role, request.name, ttl_secs)?;
+    let body = json!({ "token": token, "room": request.room, "expiresAt": expires_at });
+    Ok(warp::reply::json(&body).into_response())
+}
+
+async fn room_stats(authorization: Option<String>, rooms: Rooms, auth: SharedAuth) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    Ok(warp::reply::json(&json!({ "rooms": room_occupancy(&rooms) })).into_response())
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+
+use crate::errors::ApiError;
+use crate::protocol::ServerMessage;
+use crate::rooms::{is_valid_room_name, Role, Rooms, DEFAULT_ROOM};
+
+/// Claims carried by a join token. Tokens are HS256 JWTs signed with `--token-secret`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub room: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Expiry as seconds since the Unix epoch.
+    pub exp: u64,
+}
+
+/// Secrets used to sign join tokens and to protect the HTTP API.
+pub struct Auth {
+    token_keys: Option<(EncodingKey, DecodingKey)>,
+    api_key: Option<String>,
+}
+
+pub type SharedAuth = Arc<Auth>;
+
+impl Auth {
+    pub fn new(token_secret: Option<&str>, api_key: Option<String>) -> Self {
+        let token_keys = token_secret.map(|secret| {
+            (EncodingKey::from_secret(secret.as_bytes()), DecodingKey::from_secret(secret.as_bytes()))
+        });
+        if token_keys.is_none() {
+            println!("No token secret configured; join tokens are disabled");
+        }
+        Auth { token_keys, api_key }
+    }
+
+    /// Signs a token for `room` valid for `ttl_secs` seconds. Returns the token and its expiry.
+    pub fn mint(&self, room: &str, role: Role, name: Option<String>, ttl_secs: u64) -> Result<(String, u64), ApiError> {
+        let (encoding_key, _) = self.token_keys.as_ref().ok_or_else(tokens_disabled)?;
+        let claims = Claims { room: room.to_string(), role, name, exp: unix_now() + ttl_secs };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, encoding_key)
+            .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to sign token: {}", e)))?;
+        Ok((token, claims.exp))
+    }
+
+    /// Checks the signature and expiry of a join token.
+    pub fn verify(&self, token: &str) -> Result<Claims, ApiError> {
+        let (_, decoding_key) = self.token_keys.as_ref().ok_or_else(tokens_disabled)?;
+        jsonwebtoken::decode::<Claims>(token, decoding_key, &Validation::new(Algorithm::HS256))
+            .map(|data| data.claims)
+            .map_err(|e| ApiError::unauthorized(format!("invalid join token: {}", e)))
+    }
+
+    /// Validates an `Authorization: Bearer <api key>` header.
+    /// Without a configured key the check always fails with `403`, so privileged endpoints stay closed.
+    pub fn check_api_key(&self, authorization: Option<&str>) -> Result<(), ApiError> {
+        let expected = self.api_key.as_deref().ok_or_else(|| ApiError::forbidden("API key is not configured"))?;
+        let presented = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::unauthorized("missing bearer token"))?;
+        if constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+            Ok(())
+        } else {
+            Err(ApiError::unauthorized("invalid API key"))
+        }
+    }
+}
+
+fn tokens_disabled() -> ApiError {
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "join tokens are not configured")
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Hashes a room password with Argon2 and a random salt.
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to hash password: {}", e)))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+/// Query parameters accepted on the `/ws` upgrade request.
+#[derive(Deserialize, Debug, Default)]
+pub struct JoinParams {
+    pub room: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    pub name: Option<String>,
+    /// `viewer=true` joins to watch and listen only.
+    #[serde(default)]
+    pub viewer: bool,
+}
+
+/// Who is being let into which room.
+#[derive(Debug, Clone)]
+pub struct Admission {
+    pub room: String,
+    pub name: Option<String>,
+    pub role: Role,
+    pub viewer: bool,
+}
+
+/// Why a client wasn't let into a room.
+#[derive(Debug)]
+pub enum Refusal {
+    /// Answered over HTTP instead of upgrading.
+    Http(ApiError),
+    /// A missing or wrong room password. Browsers can't see why an upgrade failed, so
+    /// the socket is opened and the client told with a typed error instead.
+    Password(ServerMessage),
+}
+
+impl Refusal {
+    /// Whether the client presented credentials that didn't match (counted per IP).
+    pub fn is_failed_join(&self) -> bool {
+        match self {
+            Refusal::Http(e) => e.status == StatusCode::UNAUTHORIZED || e.status == StatusCode::FORBIDDEN,
+            Refusal::Password(_) => true,
+        }
+    }
+}
+
+impl From<ApiError> for Refusal {
+    fn from(e: ApiError) -> Self {
+        Refusal::Http(e)
+    }
+}
+
+/// Decides whether a client may join the room it asked for.
+///
+/// A valid token for the room always admits. Otherwise the room must not require tokens
+/// and, if it has a password, the client must present it. Rooms that don't exist yet
+/// are open and get created by the first joiner.
+pub async fn admit(rooms: &Rooms, auth: &Auth, params: JoinParams) -> Result<Admission, Refusal> {
+    let room = params.room.unwrap_or_else(|| DEFAULT_ROOM.to_string());
+    if !is_valid_room_name(&room) {
+        return Err(ApiError::bad_request("invalid room name").into());
+    }
+
+    if let Some(token) = params.token {
+        let claims = auth.verify(&token)?;
+        if claims.room != room {
+            return Err(ApiError::forbidden("join token is for a different room").into());
+        }
+        return Ok(Admission { room, name: claims.name.or(params.name), role: claims.role, viewer: params.viewer });
+    }
+
+    let password_hash = {
+        match rooms.get(&room).as_deref() {
+            Some(existing) if existing.token_required => {
+                return Err(ApiError::unauthorized("this room requires a join token").into());
+            }
+            Some(existing) => existing.password_hash.clone(),
+            None => None,
+        }
+    };
+
+    if let Some(hash) = password_hash {
+        let password = params.password.filter(|password| !password.is_empty()).ok_or_else(|| {
+            Refusal::Password(ServerMessage::error("password-required", "This room requires a password."))
+        })?;
+        // Argon2 is deliberately slow; keep it off the async worker threads.
+        let matches = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        if !matches {
+            return Err(Refusal::Password(ServerMessage::error("wrong-password", "Wrong room password.")));
+        }
+    }
+
+    Ok(Admission { room, name: params.name, role: Role::Participant, viewer: params.viewer })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::Room;
+
+    fn auth() -> Auth {
+        Auth::new(Some("test secret"), None)
+    }
+
+    fn join(room: &str) -> JoinParams {
+        JoinParams { room: Some(room.to_string()), ..JoinParams::default() }
+    }
+
+    /// The HTTP status of a refusal answered before the upgrade.
+    fn status(refusal: Refusal) -> StatusCode {
+        match refusal {
+            Refusal::Http(e) => e.status,
+            Refusal::Password(msg) => panic!("expected an HTTP refusal, got {:?}", msg),
+        }
+    }
+
+    /// The code of the error a password refusal sends over the socket.
+    fn password_code(refusal: Refusal) -> String {
+        match refusal {
+            Refusal::Password(ServerMessage::Error(e)) => e.code,
+            other => panic!("expected a password refusal, got {:?}", other),
+        }
+    }
+
+    fn rooms_with(name: &str, room: Room) -> Rooms {
+        let rooms = Rooms::default();
+        rooms.insert(name.to_string(), room);
+        rooms
+    }
+
+    #[tokio::test]
+    async fn new_rooms_are_open() {
+        let admission = admit(&Rooms::default(), &auth(), join("standup")).await.unwrap();
+        assert_eq!(admission.room, "standup");
+        assert_eq!(admission.role, Role::Participant);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_malformed_token() {
+        let params = JoinParams { token: Some("not-a-token".to_string()), ..join("standup") };
+        let e = admit(&Rooms::default(), &auth(), params).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_token() {
+        let claims = Claims { room: "standup".to_string(), role: Role::Participant, name: None, exp: unix_now() - 3600 };
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"test secret"),
+        )
+        .unwrap();
+        let params = JoinParams { token: Some(token), ..join("standup") };
+        let e = admit(&Rooms::default(), &auth(), params).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_token_signed_with_another_secret() {
+        let (token, _) = Auth::new(Some("other secret"), None).mint("standup", Role::Participant, None, 60).unwrap();
+        let params = JoinParams { token: Some(token), ..join("standup") };
+        let e = admit(&Rooms::default(), &auth(), params).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_token_for_another_room() {
+        let auth = auth();
+        let (token, _) = auth.mint("standup", Role::Participant, None, 60).unwrap();
+        let params = JoinParams { token: Some(token), ..join("retro") };
+        let e = admit(&Rooms::default(), &auth, params).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn host_token_admits_as_host_with_its_name() {
+        let auth = auth();
+        let (token, _) = auth.mint("standup", Role::Host, Some("Ada".to_string()), 60).unwrap();
+        let params = JoinParams { token: Some(token), name: Some("Someone".to_string()), ..join("standup") };
+        let admission = admit(&Rooms::default(), &auth, params).await.unwrap();
+        assert_eq!(admission.role, Role::Host);
+        assert_eq!(admission.name.as_deref(), Some("Ada"));
+    }
+
+    #[tokio::test]
+    async fn token_rooms_refuse_joins_without_one() {
+        let rooms = rooms_with("standup", Room { token_required: true, ..Room::default() });
+        let e = admit(&rooms, &auth(), join("standup")).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn password_rooms_check_the_password() {
+        let hash = hash_password("hunter2").unwrap();
+        let rooms = rooms_with("standup", Room { password_hash: Some(hash), ..Room::default() });
+
+        let missing = admit(&rooms, &auth(), join("standup")).await.unwrap_err();
+        assert!(missing.is_failed_join());
+        assert_eq!(password_code(missing), "password-required");
+
+        let empty = JoinParams { password: Some(String::new()), ..join("standup") };
+        assert_eq!(password_code(admit(&rooms, &auth(), empty).await.unwrap_err()), "password-required");
+
+        let wrong = JoinParams { password: Some("hunter3".to_string()), ..join("standup") };
+        let wrong = admit(&rooms, &auth(), wrong).await.unwrap_err();
+        assert!(wrong.is_failed_join());
+        assert_eq!(password_code(wrong), "wrong-password");
+
+        let right = JoinParams { password: Some("hunter2".to_string()), ..join("standup") };
+        assert!(admit(&rooms, &auth(), right).await.is_ok());
+    }
+
+    #[test]
+    fn api_key_check_fails_closed() {
+        let e = auth().check_api_key(Some("Bearer anything")).unwrap_err();
+        assert_eq!(e.status, StatusCode::FORBIDDEN);
+
+        let auth = Auth::new(None, Some("k".to_string()));
+        assert_eq!(auth.check_api_key(None).unwrap_err().status, StatusCode::UNAUTHORIZED);
+        assert_eq!(auth.check_api_key(Some("Bearer x")).unwrap_err().status, StatusCode::UNAUTHORIZED);
+        assert!(auth.check_api_key(Some("Bearer k")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_room_names() {
+        let e = admit(&Rooms::default(), &auth(), join("no spaces/allowed")).await.unwrap_err();
+        assert_eq!(status(e), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::protocol::RosterEntry;
+
+/// Pub/sub channel the nodes of a deployment talk on unless configured otherwise.
+pub const DEFAULT_CHANNEL: &str = "videochat:signaling";
+
+/// What signaling nodes tell each other. Every node sees every event, including
+/// its own, and ignores the ones carrying its own node ID.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BackplaneEvent {
+    /// A signaling message from a client on `node`, for everyone in `room` on other nodes.
+    Relay { node: String, room: String, sender: String, payload: String },
+    /// The admitted participants of every room on `node`. Replaces what the node
+    /// announced before; sent on changes and as a heartbeat.
+    Presence { node: String, rooms: HashMap<String, Vec<RosterEntry>> },
+    /// `node` is shutting down; forget its participants right away.
+    Leave { node: String },
+}
+
+impl BackplaneEvent {
+    /// The node that sent the event.
+    pub fn node(&self) -> &str {
+        match self {
+            BackplaneEvent::Relay { node, .. } | BackplaneEvent::Presence { node, .. } | BackplaneEvent::Leave { node } => node,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BackplaneError(pub String);
+
+impl fmt::Display for BackplaneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "backplane error: {}", self.0)
+    }
+}
+
+impl std::error::Error for BackplaneError {}
+
+/// Carries [`BackplaneEvent`]s between the signaling nodes of a deployment.
+///
+/// Delivery is best effort: events published while a subscription is down are lost,
+/// which presence heartbeats make up for.
+pub trait Backplane: Send + Sync {
+    /// Sends an event to every subscribed node.
+    fn publish(&self, event: BackplaneEvent) -> BoxFuture<'_, Result<(), BackplaneError>>;
+
+    /// Starts receiving events. The stream ends when the connection is lost; the
+    /// caller subscribes again.
+    fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, BackplaneEvent>, BackplaneError>>;
+}
+
+pub type SharedBackplane = Arc<dyn Backplane>;
+
+/// Backplane within a single process. The default for a lone node, and a stand-in
+/// for Redis when several nodes run in one process, e.g. in tests: clone it and
+/// hand each node a copy.
+#[derive(Clone)]
+pub struct MemoryBackplane {
+    tx: broadcast::Sender<BackplaneEvent>,
+}
+
+impl MemoryBackplane {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1024);
+        MemoryBackplane { tx }
+    }
+}
+
+impl Default for MemoryBackplane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backplane for MemoryBackplane {
+    fn publish(&self, event: BackplaneEvent) -> BoxFuture<'_, Result<(), BackplaneError>> {
+        // Nobody listening is fine; there just are no other nodes.
+        let _ = self.tx.send(event);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, BackplaneEvent>, BackplaneError>> {
+        let rx = self.tx.subscribe();
+        Box::pin(async move {
+            let events = stream::unfold(rx, |mut rx| async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(event) => return Some((event, rx)),
+                        Err(RecvError::Lagged(missed)) => eprintln!("Backplane subscriber missed {} events", missed),
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            });
+            Ok(Box::pin(events) as BoxStream<'static, BackplaneEvent>)
+        })
+    }
+}
+
+/// Backplane on Redis pub/sub, for running several nodes behind a load balancer.
+#[cfg(feature = "redis-backplane")]
+pub struct RedisBackplane {
+    client: redis::Client,
+    /// Reconnects on its own when Redis goes away.
+    publisher: redis::aio::ConnectionManager,
+    channel: String,
+}
+
+#[cfg(feature = "redis-backplane")]
+impl RedisBackplane {
+    pub async fn connect(url: &str, channel: &str) -> Result<Self, BackplaneError> {
+        let client = redis::Client::open(url).map_err(|e| BackplaneError(e.to_string()))?;
+        let publisher = redis::aio::ConnectionManager::new(client.clone())
+            .await
+            .map_err(|e| BackplaneError(e.to_string()))?;
+        Ok(RedisBackplane { client, publisher, channel: channel.to_string() })
+    }
+}
+
+#[cfg(feature = "redis-backplane")]
+impl Backplane for RedisBackplane {
+    fn publish(&self, event: BackplaneEvent) -> BoxFuture<'_, Result<(), BackplaneError>> {
+        let mut publisher = self.publisher.clone();
+        Box::pin(async move {
+            let payload = serde_json::to_string(&event).expect("backplane events always serialize");
+            redis::AsyncCommands::publish::<_, _, ()>(&mut publisher, &self.channel, payload)
+                .await
+                .map_err(|e| BackplaneError(e.to_string()))
+        })
+    }
+
+    fn subscribe(&self) -> BoxFuture<'_, Result<BoxStream<'static, BackplaneEvent>, BackplaneError>> {
+        use futures::StreamExt;
+        Box::pin(async move {
+            let mut pubsub = self.client.get_async_pubsub().await.map_err(|e| BackplaneError(e.to_string()))?;
+            pubsub.subscribe(&self.channel).await.map_err(|e| BackplaneError(e.to_string()))?;
+            let events = pubsub.into_on_message().filter_map(|msg| async move {
+                let payload: String = msg.get_payload().ok()?;
+                serde_json::from_str(&payload)
+                    .map_err(|e| eprintln!("Ignoring malformed backplane event: {}", e))
+                    .ok()
+            });
+            Ok(Box::pin(events) as BoxStream<'static, BackplaneEvent>)
+        })
+    }
+}
+
+/// Picks the backplane for `url`: in-memory without one or for `memory`, Redis for
+/// `redis://` and `rediss://` URLs when built with the `redis-backplane` feature.
+#[cfg_attr(not(feature = "redis-backplane"), allow(unused_variables))]
+pub async fn connect(url: Option<&str>, channel: &str) -> Result<SharedBackplane, BackplaneError> {
+    match url {
+        None | Some("memory") => Ok(Arc::new(MemoryBackplane::new())),
+        #[cfg(feature = "redis-backplane")]
+        Some(url) if url.starts_with("redis://") || url.starts_with("rediss://") => {
+            Ok(Arc::new(RedisBackplane::connect(url, channel).await?))
+        }
+        Some(url) => Err(BackplaneError(format!(
+            "unsupported backplane URL {:?} (Redis needs the `redis-backplane` feature)",
+            url
+        ))),
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::{unix_now, SharedAuth};
+use crate::errors::ApiError;
+use crate::storage::{CallFilter, CallParticipant, CallRecord, SharedStore};
+use crate::time::{self, SECS_PER_DAY};
+
+/// Query string of `GET /api/calls`.
+#[derive(Deserialize, Debug)]
+pub struct CallQuery {
+    pub room: Option<String>,
+    /// Unix time or `YYYY-MM-DD` (UTC); calls that started at or after it.
+    pub from: Option<String>,
+    /// Unix time or `YYYY-MM-DD` (UTC, the whole day included); calls that started before it.
+    pub to: Option<String>,
+    /// `json` (the default) or `csv`.
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CallInfo<'a> {
+    pub id: &'a str,
+    pub room: &'a str,
+    pub started_at: u64,
+    pub ended_at: Option<u64>,
+    /// Up to now for calls that are still going on.
+    pub duration_secs: u64,
+    pub participants: Vec<CallParticipantInfo<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CallParticipantInfo<'a> {
+    #[serde(flatten)]
+    pub participant: &'a CallParticipant,
+    pub duration_secs: u64,
+}
+
+impl<'a> CallInfo<'a> {
+    fn new(call: &'a CallRecord, now: u64) -> Self {
+        CallInfo {
+            id: &call.id,
+            room: &call.room,
+            started_at: call.started_at,
+            ended_at: call.ended_at,
+            duration_secs: call.duration_secs(now),
+            participants: call
+                .participants
+                .iter()
+                .map(|participant| CallParticipantInfo { participant, duration_secs: participant.duration_secs(now) })
+                .collect(),
+        }
+    }
+}
+
+/// Call history under `/api/calls`, always needs the API key:
+///
+/// * `GET /api/calls?room=standup&from=2024-05-01&to=2024-05-31` lists the calls that
+///   started in the given room and period, earliest first, with who was in them and for
+///   how long. All parameters are optional.
+/// * Adding `format=csv` returns the same calls as a spreadsheet with one row per
+///   participant.
+pub fn routes(auth: SharedAuth, store: SharedStore) -> BoxedFilter<(Response,)> {
+    warp::path!("api" / "calls")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<CallQuery>())
+        .and(warp::any().map(move || auth.clone()))
+        .and(warp::any().map(move || store.clone()))
+        .and_then(list_calls)
+        .boxed()
+}
+
+async fn list_calls(
+    authorization: Option<String>,
+    query: CallQuery,
+    auth: SharedAuth,
+    store: SharedStore,
+) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    let filter = CallFilter {
+        room: query.room,
+        from: query.from.as_deref().map(|from| parse_time(from, false)).transpose()?,
+        to: query.to.as_deref().map(|to| parse_time(to, true)).transpose()?,
+    };
+    let calls = store.calls(filter).await.map_err(|e| {
+        eprintln!("Failed to load calls: {}", e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "failed to load calls")
+    })?;
+
+    let now = unix_now();
+    match query.format.as_deref() {
+        None | Some("json") => {
+            let calls: Vec<CallInfo> = calls.iter().map(|call| CallInfo::new(call, now)).collect();
+            Ok(warp::reply::json(&json!({ "calls": calls })).into_response())
+        }
+        Some("csv") => {
+            let reply = warp::reply::with_header(to_csv(&calls, now), "Content-Type", "text/csv; charset=utf-8");
+            Ok(warp::reply::with_header(reply, "Content-Disposition", "attachment; filename=\"calls.csv\"").into_response())
+        }
+        Some(_) => Err(ApiError::bad_request("format must be json or csv").into()),
+    }
+}
+
+/// Reads a Unix time or a `YYYY-MM-DD` date. As an upper bound (`end`), a date means
+/// the end of that day.
+fn parse_time(value: &str, end: bool) -> Result<u64, ApiError> {
+    if let Ok(secs) = value.parse() {
+        return Ok(secs);
+    }
+    let invalid = || ApiError::bad_request(format!("invalid time {:?}, expected Unix seconds or YYYY-MM-DD", value));
+    let mut parts = value.splitn(3, '-').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) {
+        return Err(invalid());
+    }
+    if !(1..=time::days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+    let days = time::days_from_civil(year, month, day);
+    Ok((days + u64::from(end)) * SECS_PER_DAY)
+}
+
+/// One row per participant and call; calls nobody was recorded in get a row of their own.
+fn to_csv(calls: &[CallRecord], now: u64) -> String {
+    let mut csv = String::from(
+        "call_id,room,call_started_at,call_ended_at,call_duration_secs,client_id,name,joined_at,left_at,duration_secs\r\n",
+    );
+    for call in calls {
+        let call_columns = [
+            call.id.clone(),
+            call.room.clone(),
+            time::iso8601(call.started_at),
+            call.ended_at.map(time::iso8601).unwrap_or_default(),
+            call.duration_secs(now).to_string(),
+        ];
+        let mut rows: Vec<[String; 5]> = call
+            .participants
+            .iter()
+            .map(|participant| {
+                [
+                    participant.client_id.clone(),
+                    participant.name.clone().unwrap_or_default(),
+                    time::iso8601(participant.joined_at),
+                    participant.left_at.map(time::iso8601).unwrap_or_default(),
+                    participant.duration_secs(now).to_string(),
+                ]
+            })
+            .collect();
+        if rows.is_empty() {
+            rows.push(Default::default());
+        }
+        for row in rows {
+            let fields: Vec<String> = call_columns.iter().chain(row.iter()).map(|field| csv_field(field)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push_str("\r\n");
+        }
+    }
+    csv
+}
+
+/// Quotes a CSV field when needed. Fields starting with a formula character get a
+/// leading quote so spreadsheets don't evaluate display names.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(participants: Vec<CallParticipant>) -> CallRecord {
+        CallRecord { id: "c1".to_string(), room: "standup".to_string(), started_at: 0, ended_at: Some(90), participants }
+    }
+
+    #[test]
+    fn parses_unix_seconds() {
+        assert_eq!(parse_time("1717232400", false).unwrap(), 1_717_232_400);
+        assert_eq!(parse_time("1717232400", true).unwrap(), 1_717_232_400);
+    }
+
+    #[test]
+    fn dates_cover_the_whole_day() {
+        let start = parse_time("2024-05-31", false).unwrap();
+        let end = parse_time("2024-05-31", true).unwrap();
+        assert_eq!(time::iso8601(start), "2024-05-31T00:00:00Z");
+        assert_eq!(time::iso8601(end), "2024-06-01T00:00:00Z");
+        assert_eq!(parse_time("2024-12-31", true).unwrap(), parse_time("2025-01-01", false).unwrap());
+        assert_eq!(parse_time("1970-01-01", false).unwrap(), 0);
+    }
+
+    #[test]
+    fn accepts_leap_days_only_in_leap_years() {
+        assert_eq!(time::iso8601(parse_time("2024-02-29", false).unwrap()), "2024-02-29T00:00:00Z");
+        assert_eq!(time::iso8601(parse_time("2000-02-29", false).unwrap()), "2000-02-29T00:00:00Z");
+        assert!(parse_time("2023-02-29", false).is_err());
+        assert!(parse_time("1900-02-29", false).is_err());
+    }
+
+    #[test]
+    fn rejects_impossible_dates() {
+        for value in ["2024-02-31", "2024-04-31", "2024-13-01", "2024-00-10", "2024-05-00", "1969-12-31", "yesterday", "2024-05"] {
+            let e = parse_time(value, false).unwrap_err();
+            assert_eq!(e.status, StatusCode::BAD_REQUEST, "{}", value);
+        }
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("Doe, Jane"), "\"Doe, Jane\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("=SUM(A1)"), "'=SUM(A1)");
+        assert_eq!(csv_field("-1,5"), "\"'-1,5\"");
+    }
+
+    #[test]
+    fn calls_without_participants_get_a_row() {
+        let csv = to_csv(&[call(Vec::new())], 100);
+        let rows: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], "c1,standup,1970-01-01T00:00:00Z,1970-01-01T00:01:30Z,90,,,,,");
+        assert_eq!(rows[2], "");
+    }
+
+    #[test]
+    fn one_row_per_participant() {
+        let participants = vec![
+            CallParticipant { client_id: "1".to_string(), name: Some("Ann".to_string()), joined_at: 0, left_at: Some(60) },
+            CallParticipant { client_id: "2".to_string(), name: None, joined_at: 30, left_at: None },
+        ];
+        let csv = to_csv(&[call(participants)], 100);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].ends_with(",1,Ann,1970-01-01T00:00:00Z,1970-01-01T00:01:00Z,60"));
+        assert!(rows[2].ends_with(",2,,1970-01-01T00:00:30Z,,70"));
+    }
+}
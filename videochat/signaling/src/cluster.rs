@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use warp::ws::Message;
+
+use crate::backplane::{BackplaneEvent, SharedBackplane};
+use crate::limits::SharedLimits;
+use crate::protocol::RosterEntry;
+use crate::rooms::{in_join_order, ClientId, Rooms};
+
+/// How often every node announces its participants.
+const HEARTBEAT: Duration = Duration::from_secs(5);
+/// Nodes not heard from for this long are considered gone.
+const NODE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Events queued for publishing before new ones are dropped.
+const OUTBOX_SIZE: usize = 1024;
+/// Pause before subscribing again after the backplane connection dropped.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// What we know about another node.
+struct RemoteNode {
+    rooms: HashMap<String, Vec<RosterEntry>>,
+    last_seen: Instant,
+}
+
+/// This node's view of the deployment: relays room messages to the other nodes
+/// through the backplane, and keeps track of the participants connected to them so
+/// rosters show everyone in a room, wherever they are connected.
+///
+/// Rooms, limits, hosts and moderation are still per node: a host can only mute or
+/// kick participants on its own node.
+pub struct Cluster {
+    node: String,
+    backplane: SharedBackplane,
+    /// Relayed messages are delivered under the same slow-consumer policy as local ones.
+    limits: SharedLimits,
+    outbox: mpsc::Sender<BackplaneEvent>,
+    nodes: Mutex<HashMap<String, RemoteNode>>,
+    /// Set once we said goodbye; nothing is published after that.
+    left: AtomicBool,
+}
+
+pub type SharedCluster = Arc<Cluster>;
+
+impl Cluster {
+    /// Joins the deployment as `node` and starts relaying events for `rooms`.
+    pub fn start(node: String, backplane: SharedBackplane, limits: SharedLimits, rooms: Rooms) -> SharedCluster {
+        println!("Signaling node {} joining the cluster", node);
+        let (outbox, outbox_rx) = mpsc::channel(OUTBOX_SIZE);
+        let cluster = Arc::new(Cluster {
+            node,
+            backplane,
+            limits,
+            outbox,
+            nodes: Mutex::new(HashMap::new()),
+            left: AtomicBool::new(false),
+        });
+        tokio::spawn(publish(cluster.clone(), outbox_rx));
+        tokio::spawn(subscribe(cluster.clone(), rooms.clone()));
+        tokio::spawn(heartbeat(cluster.clone(), rooms));
+        cluster
+    }
+
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Hands a signaling message from one of our clients to the other nodes.
+    pub fn relay(&self, room: &str, sender: ClientId, msg: &Message) {
+        let Ok(payload) = msg.to_str() else {
+            return;
+        };
+        self.queue(BackplaneEvent::Relay {
+            node: self.node.clone(),
+            room: room.to_string(),
+            sender: sender.to_string(),
+            payload: payload.to_string(),
+        });
+    }
+
+    /// Tells the other nodes who is connected here, after someone joined or left.
+    pub fn presence_changed(&self, rooms: &Rooms) {
+        self.queue(self.presence(rooms));
+    }
+
+    /// Participants of `room` connected to other nodes.
+    pub fn remote_participants(&self, room: &str) -> Vec<RosterEntry> {
+        let nodes = self.nodes.lock().unwrap();
+        let mut node_ids: Vec<&String> = nodes.keys().collect();
+        node_ids.sort();
+        node_ids
+            .into_iter()
+            .filter_map(|node| nodes[node].rooms.get(room))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Says goodbye so the other nodes drop our participants without waiting for
+    /// the heartbeat to time out.
+    pub async fn leave(&self) {
+        // Presence still queued would announce us again right after the goodbye.
+        self.left.store(true, Ordering::Relaxed);
+        if let Err(e) = self.backplane.publish(BackplaneEvent::Leave { node: self.node.clone() }).await {
+            eprintln!("Failed to leave the cluster: {}", e);
+        }
+    }
+
+    fn queue(&self, event: BackplaneEvent) {
+        if self.outbox.try_send(event).is_err() {
+            eprintln!("Backplane outbox full, dropping event");
+        }
+    }
+
+    fn presence(&self, rooms: &Rooms) -> BackplaneEvent {
+        let rooms = rooms
+            .iter()
+            .filter(|room| !room.participants.is_empty())
+            .map(|room| {
+                let entries = in_join_order(&room.participants).into_iter().map(RosterEntry::new).collect();
+                (room.key().clone(), entries)
+            })
+            .collect();
+        BackplaneEvent::Presence { node: self.node.clone(), rooms }
+    }
+
+    /// Handles an event from another node.
+    fn receive(&self, event: BackplaneEvent, rooms: &Rooms) {
+        match event {
+            BackplaneEvent::Relay { room, sender, payload, .. } => {
+                let Some(mut room_ref) = rooms.get_mut(&room) else {
+                    return;
+                };
+                let msg = Message::text(payload);
+                // Like local messages, those between two viewers are dropped.
+                let sender_is_viewer = room_ref.remote.iter().any(|entry| entry.id == sender && entry.viewer);
+                let slow: Vec<ClientId> = room_ref
+                    .participants
+                    .values()
+                    .filter(|participant| !(sender_is_viewer && participant.viewer))
+                    .filter(|participant| !self.limits.deliver(&participant.tx, msg.clone()))
+                    .map(|participant| participant.id)
+                    .collect();
+                if slow.is_empty() {
+                    return;
+                }
+                for id in slow {
+                    eprintln!("Disconnecting slow signaling client {}", id);
+                    if let Some(participant) = room_ref.remove_participant(id) {
+                        participant.evict();
+                    }
+                }
+                room_ref.ensure_host();
+                room_ref.send_roster();
+                // Presence looks at every room, so let go of this one first.
+                drop(room_ref);
+                self.presence_changed(rooms);
+            }
+            BackplaneEvent::Presence { node, rooms: remote_rooms } => {
+                let known = self
+                    .nodes
+                    .lock()
+                    .unwrap()
+                    .insert(node.clone(), RemoteNode { rooms: remote_rooms, last_seen: Instant::now() })
+                    .is_some();
+                if !known {
+                    println!("Signaling node {} joined the cluster", node);
+                    // Introduce ourselves instead of letting the new node wait for our heartbeat.
+                    self.presence_changed(rooms);
+                }
+                self.sync_rosters(rooms);
+            }
+            BackplaneEvent::Leave { node } => {
+                if self.nodes.lock().unwrap().remove(&node).is_some() {
+                    println!("Signaling node {} left the cluster", node);
+                    self.sync_rosters(rooms);
+                }
+            }
+        }
+    }
+
+    /// Forgets nodes that stopped sending heartbeats.
+    fn expire_nodes(&self, rooms: &Rooms) {
+        let expired = {
+            let mut nodes = self.nodes.lock().unwrap();
+            let before = nodes.len();
+            nodes.retain(|node, remote| {
+                let alive = remote.last_seen.elapsed() < NODE_TIMEOUT;
+                if !alive {
+                    eprintln!("Signaling node {} timed out", node);
+                }
+                alive
+            });
+            nodes.len() != before
+        };
+        if expired {
+            self.sync_rosters(rooms);
+        }
+    }
+
+    /// Updates the remote participants of our rooms and sends new rosters where they changed.
+    fn sync_rosters(&self, rooms: &Rooms) {
+        for mut room in rooms.iter_mut() {
+            let remote = self.remote_participants(room.key());
+            if room.remote != remote {
+                room.remote = remote;
+                room.send_roster();
+            }
+        }
+    }
+}
+
+/// Publishes queued events in order.
+async fn publish(cluster: SharedCluster, mut outbox: mpsc::Receiver<BackplaneEvent>) {
+    while let Some(event) = outbox.recv().await {
+        if cluster.left.load(Ordering::Relaxed) {
+            continue;
+        }
+        if let Err(e) = cluster.backplane.publish(event).await {
+            eprintln!("Failed to publish to the backplane: {}", e);
+        }
+    }
+}
+
+/// Receives events from the other nodes, subscribing again whenever the connection drops.
+async fn subscribe(cluster: SharedCluster, rooms: Rooms) {
+    loop {
+        match cluster.backplane.subscribe().await {
+            Ok(mut events) => {
+                while let Some(event) = events.next().await {
+                    if event.node() != cluster.node {
+                        cluster.receive(event, &rooms);
+                    }
+                }
+                eprintln!("Backplane subscription ended");
+            }
+            Err(e) => eprintln!("Failed to subscribe to the backplane: {}", e),
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+/// Announces our participants regularly, so nodes that missed an update or joined
+/// late catch up, and drops nodes that went quiet.
+async fn heartbeat(cluster: SharedCluster, rooms: Rooms) {
+    let mut interval = tokio::time::interval(HEARTBEAT);
+    loop {
+        interval.tick().await;
+        cluster.presence_changed(&rooms);
+        cluster.expire_nodes(&rooms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::Receiver;
+
+    use super::*;
+    use clap::Parser;
+
+    use crate::backplane::MemoryBackplane;
+    use crate::config::Cli;
+    use crate::limits::Limits;
+    use crate::rooms::{Participant, Role, Room};
+
+    /// A node with one participant in room `standup`.
+    fn node(name: &str, backplane: &MemoryBackplane, client: ClientId) -> (SharedCluster, Rooms, Receiver<Message>) {
+        let rooms = Rooms::default();
+        let (participant, rx) = Participant::connected(client, Role::Host);
+        let mut room = Room::default();
+        room.add_participant(participant);
+        rooms.insert("standup".to_string(), room);
+        let cluster = Cluster::start(name.to_string(), Arc::new(backplane.clone()), limits(&[]), rooms.clone());
+        (cluster, rooms, rx)
+    }
+
+    fn limits(args: &[&str]) -> SharedLimits {
+        let cli = Cli::parse_from(std::iter::once("signaling").chain(args.iter().copied()));
+        Arc::new(Limits::new(&cli.serve))
+    }
+
+    /// A relayed message from client 9 on node `b`.
+    fn relay_event(payload: &str) -> BackplaneEvent {
+        BackplaneEvent::Relay {
+            node: "b".to_string(),
+            room: "standup".to_string(),
+            sender: "9".to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    /// A node on its own backplane whose participant 1 never reads its queue.
+    fn stalled_node(policy: &str) -> (SharedCluster, Rooms, SharedLimits, Receiver<Message>) {
+        let rooms = Rooms::default();
+        let (participant, rx) = Participant::connected(1, Role::Host);
+        let mut room = Room::default();
+        room.add_participant(participant);
+        rooms.insert("standup".to_string(), room);
+        let limits = limits(&["--slow-consumer", policy]);
+        let cluster = Cluster::start("a".to_string(), Arc::new(MemoryBackplane::new()), limits.clone(), rooms.clone());
+        (cluster, rooms, limits, rx)
+    }
+
+    #[tokio::test]
+    async fn relays_disconnect_slow_consumers() {
+        let (cluster, rooms, limits, _rx) = stalled_node("disconnect");
+        for i in 0..16 {
+            cluster.receive(relay_event(&format!("message {}", i)), &rooms);
+        }
+        assert!(rooms.get("standup").unwrap().participants.contains_key(&1));
+        // The queue is full now.
+        cluster.receive(relay_event("one too many"), &rooms);
+        assert!(rooms.get("standup").unwrap().participants.is_empty());
+        assert_eq!(limits.counters.slow_consumer_disconnects.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn relays_drop_messages_for_slow_consumers_under_the_drop_policy() {
+        let (cluster, rooms, limits, _rx) = stalled_node("drop");
+        for i in 0..20 {
+            cluster.receive(relay_event(&format!("message {}", i)), &rooms);
+        }
+        assert!(rooms.get("standup").unwrap().participants.contains_key(&1));
+        assert_eq!(limits.counters.dropped_messages.load(Ordering::Relaxed), 4);
+    }
+
+    /// Waits for `condition` to hold, giving the cluster tasks time to exchange events.
+    async fn eventually(mut condition: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    fn remote_ids(rooms: &Rooms) -> Vec<String> {
+        rooms.get("standup").unwrap().remote.iter().map(|entry| entry.id.clone()).collect()
+    }
+
+    /// Texts queued for a client so far.
+    fn drain(rx: &mut Receiver<Message>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok()).filter_map(|msg| msg.to_str().ok().map(str::to_string)).collect()
+    }
+
+    #[tokio::test]
+    async fn nodes_share_rosters_and_relay_messages() {
+        let backplane = MemoryBackplane::new();
+        let (a, rooms_a, mut rx_a) = node("a", &backplane, 1);
+        let (b, rooms_b, mut rx_b) = node("b", &backplane, 2);
+        // Events sent before both nodes subscribed are lost, so keep announcing until they meet.
+        eventually(|| {
+            a.presence_changed(&rooms_a);
+            b.presence_changed(&rooms_b);
+            remote_ids(&rooms_a) == ["2"] && remote_ids(&rooms_b) == ["1"]
+        })
+        .await;
+
+        // Local participants come first, then those on other nodes.
+        let roster = drain(&mut rx_b).pop().unwrap();
+        let roster: serde_json::Value = serde_json::from_str(&roster).unwrap();
+        let ids: Vec<&str> = roster["data"]["participants"].as_array().unwrap().iter().map(|p| p["id"].as_str().unwrap()).collect();
+        assert_eq!(roster["type"], "Roster");
+        assert_eq!(ids, ["2", "1"]);
+        drain(&mut rx_a);
+
+        let offer = r#"{"type":"Offer","data":"v=0"}"#;
+        a.relay("standup", 1, &Message::text(offer));
+        eventually(|| rx_b.try_recv().is_ok_and(|msg| msg.to_str() == Ok(offer))).await;
+        // A node ignores its own events.
+        assert!(drain(&mut rx_a).is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaving_drops_the_nodes_participants() {
+        let backplane = MemoryBackplane::new();
+        let (a, rooms_a, _rx_a) = node("a", &backplane, 1);
+        let (b, rooms_b, mut rx_b) = node("b", &backplane, 2);
+        eventually(|| {
+            a.presence_changed(&rooms_a);
+            remote_ids(&rooms_b) == ["1"]
+        })
+        .await;
+        drain(&mut rx_b);
+
+        a.leave().await;
+        eventually(|| remote_ids(&rooms_b).is_empty()).await;
+        assert!(b.remote_participants("standup").is_empty());
+        // Whoever is left hears that the roster shrank.
+        let roster = drain(&mut rx_b).pop().unwrap();
+        assert!(roster.contains("\"Roster\"") && !roster.contains("\"id\":\"1\""));
+    }
+}
@@ -0,0 +1,216 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+
+use crate::limits::SlowConsumerPolicy;
+
+/// Command line interface of the signaling server.
+/// Running the binary without a subcommand starts the server.
+#[derive(Parser, Debug)]
+#[command(name = "signaling", about = "WebRTC signaling server for Rust Video Chat")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Generate a self-signed certificate and key for local HTTPS development.
+    GenDevCert(DevCertArgs),
+}
+
+/// Settings used when running the server.
+#[derive(Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// Address to bind the server to.
+    #[arg(long, env = "SIGNALING_HOST", default_value = "0.0.0.0")]
+    pub host: IpAddr,
+
+    /// Port to listen on.
+    #[arg(long, env = "SIGNALING_PORT", default_value_t = 3030)]
+    pub port: u16,
+
+    /// PEM encoded certificate chain. Enables TLS together with `--tls-key`.
+    #[arg(long, env = "SIGNALING_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM encoded private key matching `--tls-cert`.
+    #[arg(long, env = "SIGNALING_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// How often (in seconds) the certificate files are checked for changes.
+    #[arg(long, env = "SIGNALING_TLS_RELOAD_SECS", default_value_t = 5)]
+    pub tls_reload_secs: u64,
+
+    /// Origins allowed to open `/ws` and `/visitors` sockets, e.g. `https://chat.example.com`.
+    /// Can be repeated or given as a comma separated list. Empty allows every origin.
+    #[arg(long = "allowed-origin", env = "SIGNALING_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub allowed_origins: Vec<String>,
+
+    /// Secret used to sign and verify room join tokens (HS256). Tokens are disabled without it.
+    #[arg(long, env = "SIGNALING_TOKEN_SECRET", hide_env_values = true)]
+    pub token_secret: Option<String>,
+
+    /// Bearer key for the `/api` endpoints. Minting tokens is disabled without it.
+    #[arg(long, env = "SIGNALING_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+
+    /// Use the first `X-Forwarded-For` address as the client IP (when running behind a proxy).
+    #[arg(long, env = "SIGNALING_TRUST_PROXY")]
+    pub trust_proxy: bool,
+
+    /// Largest WebSocket message (and frame) accepted from a client, in bytes.
+    #[arg(long, env = "SIGNALING_MAX_MESSAGE_BYTES", default_value_t = 64 * 1024)]
+    pub max_message_bytes: usize,
+
+    /// Messages queued for a client before it counts as a slow consumer.
+    #[arg(long, env = "SIGNALING_OUTBOUND_QUEUE", default_value_t = 256)]
+    pub outbound_queue: usize,
+
+    /// What to do when a client's outbound queue is full.
+    #[arg(long, env = "SIGNALING_SLOW_CONSUMER", value_enum, default_value_t = SlowConsumerPolicy::Disconnect)]
+    pub slow_consumer: SlowConsumerPolicy,
+
+    /// Sustained messages per second a single connection may send.
+    #[arg(long, env = "SIGNALING_CLIENT_RATE", default_value_t = 20.0)]
+    pub client_rate: f64,
+
+    /// Burst of messages a single connection may send at once (ICE candidates arrive in bursts).
+    #[arg(long, env = "SIGNALING_CLIENT_BURST", default_value_t = 100.0)]
+    pub client_burst: f64,
+
+    /// Sustained messages per second shared by all connections from one IP.
+    #[arg(long, env = "SIGNALING_IP_RATE", default_value_t = 100.0)]
+    pub ip_rate: f64,
+
+    /// Burst of messages shared by all connections from one IP.
+    #[arg(long, env = "SIGNALING_IP_BURST", default_value_t = 300.0)]
+    pub ip_burst: f64,
+
+    /// Failed joins (wrong password or bad token) an IP may make per minute.
+    #[arg(long, env = "SIGNALING_JOIN_FAILURE_RATE", default_value_t = 6.0)]
+    pub join_failure_rate: f64,
+
+    /// Failed joins an IP may make in a row before it has to wait.
+    #[arg(long, env = "SIGNALING_JOIN_FAILURE_BURST", default_value_t = 10.0)]
+    pub join_failure_burst: f64,
+
+    /// Maximum number of participants in one room. Unlimited when not set.
+    #[arg(long, env = "SIGNALING_MAX_ROOM_PARTICIPANTS")]
+    pub max_room_participants: Option<usize>,
+
+    /// Maximum number of rooms that may exist at once. Unlimited when not set.
+    #[arg(long, env = "SIGNALING_MAX_ROOMS")]
+    pub max_rooms: Option<usize>,
+
+    /// Maximum number of open sockets (`/ws` and `/visitors`) on the server. Unlimited when not set.
+    #[arg(long, env = "SIGNALING_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of open sockets from a single IP. Unlimited when not set.
+    #[arg(long, env = "SIGNALING_MAX_CONNECTIONS_PER_IP")]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Seconds to let calls continue after SIGTERM/SIGINT before closing all sockets.
+    #[arg(long, env = "SIGNALING_DRAIN_SECS", default_value_t = 30)]
+    pub drain_secs: u64,
+
+    /// Delay clients are asked to wait before reconnecting after a shutdown notice.
+    #[arg(long, env = "SIGNALING_RECONNECT_HINT_MS", default_value_t = 5000)]
+    pub reconnect_hint_ms: u64,
+
+    /// Directory with the Trunk build output (`frontend/dist`) to serve alongside `/ws`.
+    #[arg(long, env = "SIGNALING_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+
+    /// `Cache-Control` max-age in seconds for static assets other than `index.html`.
+    #[arg(long, env = "SIGNALING_STATIC_MAX_AGE", default_value_t = 3600)]
+    pub static_max_age: u64,
+
+    /// Address users reach the app at, e.g. `https://chat.example.com`, for links to
+    /// scheduled meetings. The meetings API refuses to hand out links without it.
+    #[arg(long, env = "SIGNALING_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// JSON file visitor statistics are kept in across restarts. Pass an empty
+    /// value to keep them in memory only.
+    #[arg(long, env = "SIGNALING_STATS_FILE", default_value = "visitor-stats.json")]
+    pub stats_file: String,
+
+    /// SQLite database for rooms created through the API, scheduled meetings and call
+    /// history. Pass an empty value to keep them in memory only.
+    #[arg(long, env = "SIGNALING_DATABASE", default_value = "signaling.db")]
+    pub database: String,
+
+    /// Pub/sub backplane shared with the other signaling nodes of the deployment, e.g.
+    /// `redis://redis:6379` (needs the `redis-backplane` feature). Without it the node
+    /// runs alone.
+    #[arg(long, env = "SIGNALING_BACKPLANE_URL")]
+    pub backplane_url: Option<String>,
+
+    /// Backplane channel; nodes on different channels don't see each other.
+    #[arg(long, env = "SIGNALING_BACKPLANE_CHANNEL", default_value = crate::backplane::DEFAULT_CHANNEL)]
+    pub backplane_channel: String,
+
+    /// Name of this node in the cluster. Defaults to a random ID.
+    #[arg(long, env = "SIGNALING_NODE_ID")]
+    pub node_id: Option<String>,
+}
+
+impl ServeArgs {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    /// This node's name in the cluster: `--node-id`, or a random one.
+    pub fn node_id(&self) -> String {
+        self.node_id.clone().unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+    }
+
+    pub fn stats_file(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.stats_file)).filter(|path| !path.as_os_str().is_empty())
+    }
+
+    pub fn database(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.database)).filter(|path| !path.as_os_str().is_empty())
+    }
+
+    /// Returns the TLS settings if both a certificate and a key were given.
+    pub fn tls(&self) -> Option<TlsConfig> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+                reload_secs: self.tls_reload_secs,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Locations of the certificate files the TLS listener watches.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub reload_secs: u64,
+}
+
+/// Options for the `gen-dev-cert` subcommand.
+#[derive(Args, Debug)]
+pub struct DevCertArgs {
+    /// Where to write the certificate.
+    #[arg(long, default_value = "dev-cert.pem")]
+    pub cert_out: PathBuf,
+
+    /// Where to write the private key.
+    #[arg(long, default_value = "dev-key.pem")]
+    pub key_out: PathBuf,
+
+    /// Host names and IP addresses the certificate is valid for.
+    #[arg(long = "name", default_values = ["localhost", "127.0.0.1", "::1"])]
+    pub names: Vec<String>,
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use serde_json::json;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Rejection, Reply};
+
+/// Error returned from HTTP handlers and upgrade checks.
+/// Rendered as `{"error": "..."}` with the given status by [`handle_rejection`].
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError { status, message: message.into() }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl Reply for ApiError {
+    fn into_response(self) -> Response {
+        warp::reply::with_status(warp::reply::json(&json!({ "error": self.message })), self.status)
+            .into_response()
+    }
+}
+
+/// Turns an [`ApiError`] rejection into its response and passes every other rejection on,
+/// so routes further down the filter chain still get a chance to match.
+pub async fn handle_rejection(err: Rejection) -> Result<Response, Rejection> {
+    match err.find::<ApiError>() {
+        Some(api_error) => Ok(ApiError::new(api_error.status, api_error.message.clone()).into_response()),
+        None => Err(err),
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Instant;
+use serde::Serialize;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::cluster::SharedCluster;
+use crate::limits::SharedLimits;
+use crate::rooms::Rooms;
+use crate::shutdown::SharedShutdown;
+use crate::visitors::Visitors;
+
+/// Version and build details baked in by `build.rs`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub profile: &'static str,
+    pub target: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: env!("SIGNALING_GIT_COMMIT"),
+    profile: env!("SIGNALING_BUILD_PROFILE"),
+    target: env!("SIGNALING_BUILD_TARGET"),
+};
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Connections {
+    /// All open `/ws` and `/visitors` sockets.
+    pub open: usize,
+    pub signaling_clients: usize,
+    pub visitors: usize,
+    pub rooms: usize,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub status: &'static str,
+    /// Which node of the cluster answered.
+    pub node: String,
+    pub uptime_secs: u64,
+    pub draining: bool,
+    /// Why the server isn't ready; empty when it is.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<&'static str>,
+    pub connections: Connections,
+    pub build: &'static BuildInfo,
+}
+
+/// State the probes report on.
+#[derive(Clone)]
+pub struct HealthState {
+    pub started: Instant,
+    pub rooms: Rooms,
+    pub visitors: Visitors,
+    pub limits: SharedLimits,
+    pub shutdown: SharedShutdown,
+    pub cluster: SharedCluster,
+}
+
+impl HealthState {
+    fn report(&self, status: &'static str, reasons: Vec<&'static str>) -> HealthReport {
+        let signaling_clients = self.rooms.iter().map(|room| room.members().count()).sum();
+        HealthReport {
+            status,
+            node: self.cluster.node().to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            draining: self.shutdown.is_draining(),
+            reasons,
+            connections: Connections {
+                open: self.limits.open_connections(),
+                signaling_clients,
+                visitors: self.visitors.count(),
+                rooms: self.rooms.len(),
+            },
+            build: &BUILD_INFO,
+        }
+    }
+
+    /// Reasons the server shouldn't get new traffic right now.
+    fn unready_reasons(&self) -> Vec<&'static str> {
+        let mut reasons = Vec::new();
+        if self.shutdown.is_draining() {
+            reasons.push("draining");
+        }
+        if self.limits.at_connection_limit() {
+            reasons.push("connection-limit");
+        }
+        reasons
+    }
+}
+
+/// Probes for load balancers and orchestrators:
+///
+/// * `GET /healthz` answers `200` as long as the process serves requests.
+/// * `GET /readyz` answers `200` when new sockets are accepted and `503` while the
+///   server is draining or at its connection limit.
+///
+/// Both return the same JSON report with uptime, connection counts and build info.
+pub fn routes(state: HealthState) -> BoxedFilter<(Response,)> {
+    let healthz = {
+        let state = state.clone();
+        warp::path!("healthz")
+            .and(warp::get())
+            .map(move || warp::reply::json(&state.report("ok", Vec::new())).into_response())
+    };
+
+    let readyz = warp::path!("readyz").and(warp::get()).map(move || {
+        let reasons = state.unready_reasons();
+        let (status, code) = if reasons.is_empty() {
+            ("ready", StatusCode::OK)
+        } else {
+            ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+        };
+        let report = state.report(status, reasons);
+        warp::reply::with_status(warp::reply::json(&report), code).into_response()
+    });
+
+    healthz.or(readyz).unify().boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use std::sync::Arc;
+    use warp::hyper::body::Bytes;
+
+    use super::*;
+    use crate::backplane::MemoryBackplane;
+    use crate::cluster::Cluster;
+    use crate::config::Cli;
+    use crate::limits::Limits;
+    use crate::shutdown::Shutdown;
+    use crate::visitors::VisitorHub;
+
+    fn state(args: &[&str]) -> HealthState {
+        let cli = Cli::parse_from(["signaling"].iter().chain(args));
+        let limits = Arc::new(Limits::new(&cli.serve));
+        let rooms = Rooms::default();
+        HealthState {
+            started: Instant::now(),
+            rooms: rooms.clone(),
+            visitors: Arc::new(VisitorHub::new(16)),
+            limits: limits.clone(),
+            shutdown: Arc::new(Shutdown::default()),
+            cluster: Cluster::start("a".to_string(), Arc::new(MemoryBackplane::new()), limits, rooms),
+        }
+    }
+
+    async fn get(state: &HealthState, path: &str) -> warp::http::Response<Bytes> {
+        warp::test::request().path(path).reply(&routes(state.clone())).await
+    }
+
+    fn reasons(reply: &warp::http::Response<Bytes>) -> serde_json::Value {
+        serde_json::from_slice::<serde_json::Value>(reply.body()).unwrap()["reasons"].clone()
+    }
+
+    #[tokio::test]
+    async fn ready_when_idle() {
+        let state = state(&[]);
+        let reply = get(&state, "/readyz").await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_slice(reply.body()).unwrap();
+        assert_eq!(report["status"], "ready");
+        assert_eq!(report["node"], "a");
+        assert_eq!(get(&state, "/healthz").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unready_while_draining() {
+        let state = state(&[]);
+        state.shutdown.start_draining();
+        let reply = get(&state, "/readyz").await;
+        assert_eq!(reply.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(reasons(&reply), serde_json::json!(["draining"]));
+        assert_eq!(get(&state, "/healthz").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unready_at_the_connection_limit() {
+        let state = state(&["--max-connections", "1"]);
+        let guard = state.limits.acquire_connection(None).unwrap();
+        let reply = get(&state, "/readyz").await;
+        assert_eq!(reply.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(reasons(&reply), serde_json::json!(["connection-limit"]));
+        assert_eq!(get(&state, "/healthz").await.status(), StatusCode::OK);
+
+        drop(guard);
+        assert_eq!(get(&state, "/readyz").await.status(), StatusCode::OK);
+    }
+}
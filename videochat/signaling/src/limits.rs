@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use warp::ws::Message;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection};
+
+use crate::config::ServeArgs;
+use crate::errors::ApiError;
+
+/// Address of the TCP peer, stored as a request extension by servers that don't go
+/// through `warp::serve` (which is the only one `warp::addr::remote` knows about).
+#[derive(Clone, Copy, Debug)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Resolves the client IP of a request. With `trust_proxy` the first
+/// `X-Forwarded-For` entry wins, which is what ngrok and most proxies set.
+pub fn client_ip(trust_proxy: bool) -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<PeerAddr>())
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(move |remote: Option<SocketAddr>, peer: Option<PeerAddr>, forwarded: Option<String>| {
+            let forwarded_ip = forwarded
+                .filter(|_| trust_proxy)
+                .and_then(|value| value.split(',').next().and_then(|ip| ip.trim().parse().ok()));
+            forwarded_ip.or(remote.map(|addr| addr.ip())).or(peer.map(|peer| peer.0.ip()))
+        })
+}
+
+/// Reserves a connection slot for the request, rejecting it with `503`/`429`
+/// before the upgrade when the server or the client's IP is at its limit.
+pub fn connection_slot(
+    limits: SharedLimits,
+    trust_proxy: bool,
+) -> impl Filter<Extract = (ConnectionGuard,), Error = Rejection> + Clone {
+    client_ip(trust_proxy).and_then(move |ip: Option<IpAddr>| {
+        let limits = limits.clone();
+        async move {
+            limits.acquire_connection(ip).map_err(|e| {
+                eprintln!("Refused connection from {:?}: {}", ip, e);
+                warp::reject::custom(e)
+            })
+        }
+    })
+}
+
+/// Refills `rate` tokens per second up to `burst`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        TokenBucket { rate, burst, tokens: burst, last_refill: Instant::now() }
+    }
+
+    /// Takes one token, returning `false` if the bucket is empty.
+    pub fn try_take(&mut self) -> bool {
+        if self.has_token() {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a token is available, without taking it.
+    pub fn has_token(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+        self.tokens >= 1.0
+    }
+
+    fn is_full(&self) -> bool {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.tokens + elapsed * self.rate >= self.burst
+    }
+}
+
+/// What to do with a client whose outbound queue is full.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Drop the message for that client and keep the connection.
+    Drop,
+    /// Close the connection of the client.
+    Disconnect,
+}
+
+/// Counters for clients hitting the limits.
+#[derive(Default, Debug)]
+pub struct LimitCounters {
+    pub throttled_messages: AtomicU64,
+    pub throttled_clients: AtomicU64,
+    pub dropped_messages: AtomicU64,
+    pub slow_consumer_disconnects: AtomicU64,
+}
+
+/// Counts open sockets in total and per IP.
+#[derive(Default, Debug)]
+pub struct ConnectionTracker {
+    total: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Holds one connection slot; the slot is released when the guard is dropped.
+pub struct ConnectionGuard {
+    limits: SharedLimits,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionGuard {
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let tracker = &self.limits.connections;
+        tracker.total.fetch_sub(1, Ordering::Relaxed);
+        if let Some(ip) = self.ip {
+            let mut per_ip = tracker.per_ip.lock().unwrap();
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// Per-connection and per-IP limits shared by all socket handlers.
+pub struct Limits {
+    pub max_message_bytes: usize,
+    pub max_room_participants: Option<usize>,
+    pub max_rooms: Option<usize>,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    pub connections: ConnectionTracker,
+    pub outbound_queue: usize,
+    pub slow_consumer: SlowConsumerPolicy,
+    client_rate: f64,
+    client_burst: f64,
+    ip_rate: f64,
+    ip_burst: f64,
+    ip_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    /// Failed joins per second and in a row allowed per IP.
+    join_failure_rate: f64,
+    join_failure_burst: f64,
+    join_failures: Mutex<HashMap<IpAddr, TokenBucket>>,
+    pub counters: LimitCounters,
+}
+
+pub type SharedLimits = Arc<Limits>;
+
+impl Limits {
+    pub fn new(args: &ServeArgs) -> Self {
+        Limits {
+            max_message_bytes: args.max_message_bytes,
+            max_room_participants: args.max_room_participants,
+            max_rooms: args.max_rooms,
+            max_connections: args.max_connections,
+            max_connections_per_ip: args.max_connections_per_ip,
+            connections: ConnectionTracker::default(),
+            outbound_queue: args.outbound_queue.max(1),
+            slow_consumer: args.slow_consumer,
+            client_rate: args.client_rate,
+            client_burst: args.client_burst.max(1.0),
+            ip_rate: args.ip_rate,
+            ip_burst: args.ip_burst.max(1.0),
+            ip_buckets: Mutex::new(HashMap::new()),
+            join_failure_rate: args.join_failure_rate / 60.0,
+            join_failure_burst: args.join_failure_burst.max(1.0),
+            join_failures: Mutex::new(HashMap::new()),
+            counters: LimitCounters::default(),
+        }
+    }
+
+    /// Reserves a connection slot for a socket from `ip`, failing when the server
+    /// or that IP already has the maximum number of open sockets.
+    pub fn acquire_connection(self: &Arc<Self>, ip: Option<IpAddr>) -> Result<ConnectionGuard, ApiError> {
+        let tracker = &self.connections;
+        let total = tracker.total.fetch_add(1, Ordering::Relaxed);
+        // From here on the guard releases the slot again if we bail out.
+        let mut guard = ConnectionGuard { limits: self.clone(), ip: None };
+        if self.max_connections.is_some_and(|max| total >= max) {
+            return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "server is at its connection limit"));
+        }
+        if let Some(ip) = ip {
+            let mut per_ip = tracker.per_ip.lock().unwrap();
+            let count = per_ip.entry(ip).or_insert(0);
+            if self.max_connections_per_ip.is_some_and(|max| *count >= max) {
+                return Err(ApiError::new(StatusCode::TOO_MANY_REQUESTS, "too many connections from this address"));
+            }
+            *count += 1;
+            guard.ip = Some(ip);
+        }
+        Ok(guard)
+    }
+
+    /// Number of open `/ws` and `/visitors` sockets.
+    pub fn open_connections(&self) -> usize {
+        self.connections.total.load(Ordering::Relaxed)
+    }
+
+    /// Whether the server-wide connection limit is reached.
+    pub fn at_connection_limit(&self) -> bool {
+        self.max_connections.is_some_and(|max| self.open_connections() >= max)
+    }
+
+    /// Whether a room with `participants` members can take one more.
+    pub fn room_has_space(&self, participants: usize) -> bool {
+        self.max_room_participants.is_none_or(|max| participants < max)
+    }
+
+    /// Whether another room may be created when `rooms` already exist.
+    pub fn can_create_room(&self, rooms: usize) -> bool {
+        self.max_rooms.is_none_or(|max| rooms < max)
+    }
+
+    /// Bucket for a single connection.
+    pub fn client_bucket(&self) -> TokenBucket {
+        TokenBucket::new(self.client_rate, self.client_burst)
+    }
+
+    /// Checks the connection's own bucket and the shared bucket of its IP.
+    pub fn allow_message(&self, bucket: &mut TokenBucket, ip: Option<IpAddr>) -> bool {
+        if !bucket.try_take() {
+            return false;
+        }
+        match ip {
+            Some(ip) => {
+                let mut buckets = self.ip_buckets.lock().unwrap();
+                buckets
+                    .entry(ip)
+                    .or_insert_with(|| TokenBucket::new(self.ip_rate, self.ip_burst))
+                    .try_take()
+            }
+            None => true,
+        }
+    }
+
+    /// Whether `ip` may try to join a room; refused after too many recent failures,
+    /// so nobody can guess room passwords or keep the server busy hashing them.
+    pub fn may_attempt_join(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else {
+            return true;
+        };
+        let mut failures = self.join_failures.lock().unwrap();
+        failures.get_mut(&ip).is_none_or(|bucket| bucket.has_token())
+    }
+
+    /// Counts a join from `ip` that was refused for a wrong password or token.
+    pub fn record_failed_join(&self, ip: Option<IpAddr>) {
+        if let Some(ip) = ip {
+            let mut failures = self.join_failures.lock().unwrap();
+            failures
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(self.join_failure_rate, self.join_failure_burst))
+                .try_take();
+        }
+    }
+
+    /// Records a throttled message; `first_in_episode` marks the start of a throttling streak.
+    pub fn record_throttled(&self, first_in_episode: bool) {
+        self.counters.throttled_messages.fetch_add(1, Ordering::Relaxed);
+        if first_in_episode {
+            self.counters.throttled_clients.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Queues `msg` for a client. Returns `false` if the client should be disconnected,
+    /// either because it went away or because it is too slow under the `disconnect` policy.
+    pub fn deliver(&self, tx: &Sender<Message>, msg: Message) -> bool {
+        match tx.try_send(msg) {
+            Ok(()) => true,
+            Err(TrySendError::Closed(_)) => false,
+            Err(TrySendError::Full(_)) => match self.slow_consumer {
+                SlowConsumerPolicy::Drop => {
+                    self.counters.dropped_messages.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                SlowConsumerPolicy::Disconnect => {
+                    self.counters.slow_consumer_disconnects.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
+            },
+        }
+    }
+
+    /// Forgets IP buckets that have refilled, so the map doesn't grow without bound.
+    fn prune_ip_buckets(&self) {
+        self.ip_buckets.lock().unwrap().retain(|_, bucket| !bucket.is_full());
+        self.join_failures.lock().unwrap().retain(|_, bucket| !bucket.is_full());
+    }
+}
+
+/// Periodically prunes idle IP buckets and logs the counters when they changed.
+pub fn spawn_maintenance(limits: SharedLimits) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        let mut last_logged = [0u64; 4];
+        loop {
+            interval.tick().await;
+            limits.prune_ip_buckets();
+            let counters = &limits.counters;
+            let current = [
+                counters.throttled_messages.load(Ordering::Relaxed),
+                counters.throttled_clients.load(Ordering::Relaxed),
+                counters.dropped_messages.load(Ordering::Relaxed),
+                counters.slow_consumer_disconnects.load(Ordering::Relaxed),
+            ];
+            if current != last_logged {
+                println!(
+                    "Limits: {} throttled messages from {} clients, {} dropped for slow consumers, {} slow consumers disconnected",
+                    current[0], current[1], current[2], current[3]
+                );
+                last_logged = current;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Cli;
+
+    fn limits(args: &[&str]) -> SharedLimits {
+        let cli = Cli::parse_from(std::iter::once("signaling").chain(args.iter().copied()));
+        Arc::new(Limits::new(&cli.serve))
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([192, 0, 2, last]))
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills() {
+        let mut bucket = TokenBucket::new(100.0, 3.0);
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(bucket.try_take());
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_the_burst() {
+        let mut bucket = TokenBucket::new(1000.0, 2.0);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn allow_message_checks_the_shared_ip_bucket() {
+        let limits = limits(&["--client-burst", "10", "--ip-burst", "2", "--ip-rate", "0"]);
+        let mut first = limits.client_bucket();
+        let mut second = limits.client_bucket();
+        assert!(limits.allow_message(&mut first, ip(1)));
+        assert!(limits.allow_message(&mut second, ip(1)));
+        assert!(!limits.allow_message(&mut first, ip(1)));
+        assert!(limits.allow_message(&mut second, ip(2)));
+    }
+
+    #[test]
+    fn dropping_the_guard_releases_the_slot() {
+        let limits = limits(&["--max-connections", "1", "--max-connections-per-ip", "1"]);
+        let guard = limits.acquire_connection(ip(1)).unwrap();
+        assert_eq!(guard.ip(), ip(1));
+        assert_eq!(limits.open_connections(), 1);
+        assert!(limits.at_connection_limit());
+        drop(guard);
+        assert_eq!(limits.open_connections(), 0);
+        assert!(limits.connections.per_ip.lock().unwrap().is_empty());
+        assert!(limits.acquire_connection(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn refuses_connections_over_the_per_ip_cap() {
+        let limits = limits(&["--max-connections-per-ip", "2"]);
+        let _first = limits.acquire_connection(ip(1)).unwrap();
+        let _second = limits.acquire_connection(ip(1)).unwrap();
+        let e = limits.acquire_connection(ip(1)).err().unwrap();
+        assert_eq!(e.status, StatusCode::TOO_MANY_REQUESTS);
+        // The refused attempt doesn't hold on to a slot.
+        assert_eq!(limits.open_connections(), 2);
+        assert!(limits.acquire_connection(ip(2)).is_ok());
+        // Without a known address only the server-wide limit applies.
+        assert!(limits.acquire_connection(None).is_ok());
+    }
+
+    #[test]
+    fn refuses_connections_over_the_server_cap() {
+        let limits = limits(&["--max-connections", "1"]);
+        let _first = limits.acquire_connection(ip(1)).unwrap();
+        let e = limits.acquire_connection(ip(2)).err().unwrap();
+        assert_eq!(e.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(limits.open_connections(), 1);
+    }
+
+    #[test]
+    fn room_limits() {
+        let limits = limits(&["--max-room-participants", "2", "--max-rooms", "1"]);
+        assert!(limits.room_has_space(1));
+        assert!(!limits.room_has_space(2));
+        assert!(limits.can_create_room(0));
+        assert!(!limits.can_create_room(1));
+    }
+
+    #[test]
+    fn failed_joins_lock_out_the_ip_only() {
+        let limits = limits(&["--join-failure-burst", "3", "--join-failure-rate", "0"]);
+        for _ in 0..3 {
+            assert!(limits.may_attempt_join(ip(1)));
+            limits.record_failed_join(ip(1));
+        }
+        assert!(!limits.may_attempt_join(ip(1)));
+        assert!(limits.may_attempt_join(ip(2)));
+        assert!(limits.may_attempt_join(None));
+    }
+}
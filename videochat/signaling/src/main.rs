@@ -1,78 +1,466 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
 use warp::ws::{Message, WebSocket};
+use warp::http::StatusCode;
 use warp::Filter;
-use tokio::sync::mpsc;
-use serde_json::json;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
 use rand::random;
-//type Clients = Arc<Mutex<Vec<tokio::sync::mpsc::UnboundedSender<Message>>>>;
-type ClientId = usize;
-type Clients = Arc<Mutex<Vec<(ClientId, tokio::sync::mpsc::UnboundedSender<Message>)>>>;
-type Visitors = Arc<Mutex<Vec<tokio::sync::mpsc::UnboundedSender<Message>>>>;
+use dashmap::DashMap;
+use clap::Parser;
+
+mod admin;
+mod analytics;
+mod api;
+mod auth;
+mod calls;
+mod backplane;
+mod cluster;
+mod config;
+mod errors;
+mod health;
+mod limits;
+mod meetings;
+mod moderation;
+mod origin;
+mod protocol;
+mod rooms;
+mod shutdown;
+mod static_files;
+mod storage;
+mod time;
+mod tls;
+mod visitor_events;
+mod visitors;
+
+use analytics::{Analytics, SharedAnalytics};
+use auth::{Admission, Auth, JoinParams, Refusal, SharedAuth};
+use cluster::{Cluster, SharedCluster};
+use config::{Cli, Command, ServeArgs};
+use errors::ApiError;
+use health::HealthState;
+use limits::{ConnectionGuard, Limits, SharedLimits};
+use origin::OriginPolicy;
+use protocol::{ClientMessage, ServerMessage};
+use rooms::{ClientId, Participant, Room, Rooms};
+use shutdown::{SharedShutdown, Shutdown};
+use storage::{CallRecord, SharedStore};
+use visitors::{VisitorHub, Visitors};
+
+/// Shared state handed to every signaling connection.
+#[derive(Clone)]
+struct Signaling {
+    rooms: Rooms,
+    limits: SharedLimits,
+    visitors: Visitors,
+    analytics: SharedAnalytics,
+    cluster: SharedCluster,
+    store: SharedStore,
+}
+
 #[tokio::main]
 async fn main() {
-    // Shared state to track connected clients.
-    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
-    let clients_filter = warp::any().map(move || clients.clone());
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::GenDevCert(args)) => {
+            if let Err(e) = tls::generate_dev_cert(&args) {
+                eprintln!("Failed to generate development certificate: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => serve(cli.serve).await,
+    }
+}
+
+async fn serve(args: ServeArgs) {
+    let started = Instant::now();
+    // Shared state to track rooms and their connected clients.
+    let rooms: Rooms = Arc::new(DashMap::new());
+    // Rooms created through the API, meetings and call history.
+    let store = match storage::open(args.database().as_deref()) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed to open the database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match store.rooms().await {
+        Ok(records) => {
+            println!("Restored {} stored rooms", records.len());
+            for record in records {
+                rooms.insert(record.name.clone(), Room::from_record(record));
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to load stored rooms: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let rooms_filter = {
+        let rooms = rooms.clone();
+        warp::any().map(move || rooms.clone())
+    };
+    // Secrets for join tokens and the HTTP API.
+    let auth: SharedAuth = Arc::new(Auth::new(args.token_secret.as_deref(), args.api_key.clone()));
+    let auth_filter = {
+        let auth = auth.clone();
+        warp::any().map(move || auth.clone())
+    };
+    // Unique/peak visitors and calls, saved to the stats file now and then.
+    let analytics: SharedAnalytics = Arc::new(Analytics::load(args.stats_file()));
+    analytics::spawn_persistence(analytics.clone());
+    let analytics_filter = {
+        let analytics = analytics.clone();
+        warp::any().map(move || analytics.clone())
+    };
+    // Rate limits, message size caps and outbound queue sizes.
+    let limits: SharedLimits = Arc::new(Limits::new(&args));
+    limits::spawn_maintenance(limits.clone());
+    let limits_filter = {
+        let limits = limits.clone();
+        warp::any().map(move || limits.clone())
+    };
     // visitor count
-    // visitor connections: a list of sender channels for each visitor.
-    let visitors: Visitors = Arc::new(Mutex::new(Vec::new()));
-    let visitors_filter = warp::any().map(move || visitors.clone());
+    // visitor connections: everyone subscribed to the visitor stats broadcast.
+    let visitors: Visitors = Arc::new(VisitorHub::new(limits.outbound_queue));
+    let visitors_filter = {
+        let visitors = visitors.clone();
+        warp::any().map(move || visitors.clone())
+    };
+
+    // Other signaling nodes, reached through the backplane.
+    let backplane = match backplane::connect(args.backplane_url.as_deref(), &args.backplane_channel).await {
+        Ok(backplane) => backplane,
+        Err(e) => {
+            eprintln!("Failed to connect to the backplane: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let cluster = Cluster::start(args.node_id(), backplane, limits.clone(), rooms.clone());
+
+    // Everything a signaling connection works with.
+    let signaling_filter = {
+        let signaling = Signaling {
+            rooms: rooms.clone(),
+            limits: limits.clone(),
+            visitors: visitors.clone(),
+            analytics: analytics.clone(),
+            cluster: cluster.clone(),
+            store: store.clone(),
+        };
+        warp::any().map(move || signaling.clone())
+    };
+
+    // Flipped on SIGTERM/SIGINT so that no new sockets are accepted while draining.
+    let shutdown: SharedShutdown = Arc::new(Shutdown::default());
 
-    // WebSocket route at /ws
+    // Only pages from allowed origins may open sockets.
+    let origin_policy = OriginPolicy::new(&args.allowed_origins);
+
+    // WebSocket route at /ws. The connection slot is taken first, so nobody can have the
+    // server check passwords without limit; the room and credentials are checked before upgrading.
     let ws_route = warp::path("ws")
+        .and(shutdown::reject_when_draining(shutdown.clone()))
+        .and(origin::check(origin_policy.clone(), args.trust_proxy))
+        .and(limits::connection_slot(limits.clone(), args.trust_proxy))
+        .and(warp::query::<JoinParams>())
+        .and(signaling_filter)
+        .and(auth_filter)
+        .and_then(|slot: ConnectionGuard, params: JoinParams, signaling: Signaling, auth: SharedAuth| async move {
+            let ip = slot.ip();
+            if !signaling.limits.may_attempt_join(ip) {
+                eprintln!("Refused signaling client {:?}: too many failed joins", ip);
+                return Err(warp::reject::custom(ApiError::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "too many failed attempts to join, try again later",
+                )));
+            }
+            match auth::admit(&signaling.rooms, &auth, params).await {
+                Ok(admission) => Ok((Ok(admission), slot, signaling)),
+                Err(refusal) => {
+                    if refusal.is_failed_join() {
+                        signaling.limits.record_failed_join(ip);
+                    }
+                    match refusal {
+                        Refusal::Http(e) => {
+                            eprintln!("Rejected signaling client {:?}: {}", ip, e);
+                            Err(warp::Rejection::from(e))
+                        }
+                        Refusal::Password(refusal) => Ok((Err(refusal), slot, signaling)),
+                    }
+                }
+            }
+        })
+        .untuple_one()
         .and(warp::ws())
-        .and(clients_filter)
-        .map(|ws: warp::ws::Ws, clients| {
-            ws.on_upgrade(move |socket| handle_connection(socket, clients))
-        });
+        .map(|admission: Result<Admission, ServerMessage>, slot: ConnectionGuard, signaling: Signaling, ws: warp::ws::Ws| {
+            let max_message_bytes = signaling.limits.max_message_bytes;
+            ws.max_message_size(max_message_bytes)
+                .max_frame_size(max_message_bytes)
+                .on_upgrade(move |socket| async move {
+                    match admission {
+                        Ok(admission) => handle_connection(socket, signaling, admission, slot).await,
+                        Err(refusal) => refuse_connection(socket, refusal, slot).await,
+                    }
+                })
+        })
+        .recover(errors::handle_rejection);
     let visitors_route = warp::path("visitors")
+        .and(shutdown::reject_when_draining(shutdown.clone()))
+        .and(origin::check(origin_policy.clone(), args.trust_proxy))
         .and(warp::ws())
+        .and(limits::connection_slot(limits.clone(), args.trust_proxy))
+        .and(visitors_filter.clone())
+        .and(rooms_filter.clone())
+        .and(limits_filter)
+        .and(analytics_filter.clone())
+        .map(|ws: warp::ws::Ws, slot: ConnectionGuard, visitors: Visitors, rooms: Rooms, limits: SharedLimits, analytics: SharedAnalytics| {
+            ws.max_message_size(limits.max_message_bytes)
+                .max_frame_size(limits.max_message_bytes)
+                .on_upgrade(move |socket| handle_visitor_connection(socket, visitors, rooms, analytics, slot))
+        })
+        .recover(errors::handle_rejection);
+    // The same feed as server-sent events at /visitors/events, for proxies that mangle upgrades.
+    let visitor_events_route = warp::path!("visitors" / "events")
+        .and(warp::get())
+        .and(shutdown::reject_when_draining(shutdown.clone()))
+        .and(origin::check(origin_policy.clone(), args.trust_proxy))
+        .and(limits::connection_slot(limits.clone(), args.trust_proxy))
         .and(visitors_filter)
-        .map(|ws: warp::ws::Ws, visitors: Visitors| {
-            ws.on_upgrade(move |socket| handle_visitor_connection(socket, visitors))
-        });
+        .and(rooms_filter.clone())
+        .and(analytics_filter)
+        .and_then(visitor_events::subscribe)
+        .recover(errors::handle_rejection);
+    // Liveness and readiness probes for load balancers.
+    let health_route = health::routes(HealthState {
+        started,
+        rooms: rooms.clone(),
+        visitors: visitors.clone(),
+        limits: limits.clone(),
+        shutdown: shutdown.clone(),
+        cluster: cluster.clone(),
+    });
+    let admin_route = admin::routes(rooms.clone(), auth.clone(), store.clone()).recover(errors::handle_rejection);
+    let calls_route = calls::routes(auth.clone(), store.clone()).recover(errors::handle_rejection);
+    let meetings_route = meetings::routes(rooms.clone(), auth.clone(), limits.clone(), store.clone(), args.public_url.clone())
+        .recover(errors::handle_rejection);
+    let api_route = api::routes(rooms.clone(), visitors.clone(), auth, limits, analytics.clone(), store)
+        .recover(errors::handle_rejection);
+    // Pages on the allowed origins may call the API from the browser.
+    let api_routes = origin::api_path().and(calls_route.or(meetings_route).or(api_route).with(origin_policy.cors()));
     
 
-    println!("Signaling server running on 0.0.0.0:3030");
     //warp::serve(ws_route).run(([0, 0, 0, 0], 3030)).await;
-    let routes = ws_route.or(visitors_route);
-    println!("Signaling server running on 0.0.0.0:3030");
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+    let static_route = static_files::routes(args.static_dir.clone(), args.static_max_age);
+    let routes = ws_route
+        .or(visitor_events_route)
+        .or(visitors_route)
+        .or(health_route)
+        .or(admin_route)
+        .or(api_routes)
+        .or(static_route);
+    let addr = args.addr();
+    let tls_config = args.tls();
+    let server = async move {
+        match tls_config {
+            Some(tls_config) => {
+                println!("Signaling server running on https://{}", addr);
+                if let Err(e) = tls::serve(addr, tls_config, warp::service(routes)).await {
+                    eprintln!("TLS server failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                println!("Signaling server running on {}", addr);
+                warp::serve(routes).run(addr).await;
+            }
+        }
+    };
+
+    // Keep serving while draining so clients can still load the page; the
+    // listener goes away once the drain is complete and this function returns.
+    let drain_period = Duration::from_secs(args.drain_secs);
+    let reconnect_hint = Duration::from_millis(args.reconnect_hint_ms);
+    tokio::pin!(server);
+    tokio::select! {
+        _ = &mut server => {}
+        _ = shutdown::signal() => {
+            tokio::select! {
+                _ = &mut server => {}
+                _ = shutdown::drain(&shutdown, &rooms, &visitors, drain_period, reconnect_hint) => {}
+            }
+            cluster.leave().await;
+            println!("Signaling server stopped");
+        }
+    }
+    analytics.save().await;
+    storage::flush_updates().await;
+}
+
+/// How long a client gets to take the close frame when it is evicted.
+const EVICT_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Forwards queued messages to the socket until the queue closes or a close frame is sent.
+/// When the server drops the queue the client gets a close frame; when it evicts the client
+/// (e.g. for a slow consumer) the close frame skips whatever is still queued.
+fn spawn_forwarder(
+    mut ws_tx: SplitSink<WebSocket, Message>,
+    mut rx: mpsc::Receiver<Message>,
+    evicted: Arc<Notify>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let reason = loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                _ = evicted.notified() => break "too slow",
+            };
+            let Some(msg) = msg else {
+                break "disconnected by server";
+            };
+            let is_close = msg.is_close();
+            let sent = tokio::select! {
+                sent = ws_tx.send(msg) => sent.is_ok(),
+                _ = evicted.notified() => break "too slow",
+            };
+            if !sent || is_close {
+                return;
+            }
+        };
+        // A client that stopped reading may never take it, so don't wait forever.
+        let _ = tokio::time::timeout(EVICT_CLOSE_TIMEOUT, ws_tx.send(Message::close_with(1008u16, reason))).await;
+    })
+}
+
+/// Tells a client it can't join (e.g. for a wrong room password) and closes the socket.
+async fn refuse_connection(ws: WebSocket, refusal: ServerMessage, slot: ConnectionGuard) {
+    let (mut ws_tx, _) = ws.split();
+    eprintln!("Refused signaling client {:?}: {:?}", slot.ip(), refusal);
+    let _ = ws_tx.send(refusal.to_message()).await;
+    let _ = ws_tx.send(Message::close_with(1008u16, "room unavailable")).await;
 }
 
-async fn handle_connection(ws: WebSocket, clients: Clients) {
+async fn handle_connection(ws: WebSocket, signaling: Signaling, admission: Admission, slot: ConnectionGuard) {
+    let Signaling { rooms, limits, visitors, analytics, cluster, store } = signaling;
     // Split the socket into a sender (tx) and receiver (rx)
     let (mut ws_tx, mut ws_rx) = ws.split();
-    println!("New signaling connection established");
+    let ip = slot.ip();
+    println!("New signaling connection established from {:?}", ip);
 
 
-    // Create a channel to forward messages to this client.
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    // Create a bounded channel to forward messages to this client.
+    let (tx, rx) = mpsc::channel(limits.outbound_queue);
+    let evicted = Arc::new(Notify::new());
 
-    // Add the sender to the list of clients.
+    // Add the client to its room, creating the room if it doesn't exist yet.
     let client_id = random::<usize>(); // generate a random client ID
-    {
-        let mut clients_lock = clients.lock().unwrap();
-        clients_lock.push((client_id, tx));
-        println!("Added new signaling client with id {}. Total clients: {}", client_id, clients_lock.len());
+    let room_id = admission.room;
+    let refusal = {
+        // Counted before taking the room's entry, which locks its shard.
+        let room_count = rooms.len();
+        let existing = rooms.get(&room_id).map(|room| (room.participants.len() + room.waiting.len(), room.locked));
+        match existing {
+            None if !limits.can_create_room(room_count) => Some(ServerMessage::error(
+                "room-limit",
+                "The server can't open any more rooms right now. Please try again later.",
+            )),
+            Some((_, true)) => Some(ServerMessage::error("room-locked", "This room is locked.")),
+            Some((count, _)) if !limits.room_has_space(count) => {
+                Some(ServerMessage::error("room-full", "This room is full."))
+            }
+            _ => {
+                // A new room starts out knowing who is in it on other nodes.
+                let mut room = rooms
+                    .entry(room_id.clone())
+                    .or_insert_with(|| Room { remote: cluster.remote_participants(&room_id), ..Room::default() });
+                let participant = Participant {
+                    id: client_id,
+                    name: admission.name,
+                    role: admission.role,
+                    viewer: admission.viewer,
+                    ip,
+                    connected_at: auth::unix_now(),
+                    joined: rooms::next_join_sequence(),
+                    messages_sent: 0,
+                    stream: None,
+                    tx,
+                    evicted: evicted.clone(),
+                };
+                let name = participant.name.clone();
+                let viewer = participant.viewer;
+                if room.join(participant) {
+                    println!("Signaling client {} ({:?}) is waiting in the lobby of room {}", client_id, name, room_id);
+                } else {
+                    println!(
+                        "Added new signaling client with id {} ({:?}, {:?}{}) to room {}. Clients in room: {}",
+                        client_id,
+                        name,
+                        room.participants[&client_id].role,
+                        if viewer { ", viewer" } else { "" },
+                        room_id,
+                        room.participants.len()
+                    );
+                }
+                None
+            }
+        }
+    };
+
+    // Tell the client why it can't join before closing the socket.
+    if let Some(refusal) = refusal {
+        eprintln!("Refused signaling client {} for room {}: {:?}", client_id, room_id, refusal);
+        let _ = ws_tx.send(refusal.to_message()).await;
+        let _ = ws_tx.send(Message::close_with(1008u16, "room unavailable")).await;
+        return;
     }
 
+    // Room occupancy changed, so the visitor stats and our presence did too.
+    broadcast_visitor_count(&visitors, &rooms, &analytics);
+    cluster.presence_changed(&rooms);
+
     // Spawn a task to forward messages from the rx channel to the WebSocket.
-    let forward_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if ws_tx.send(msg).await.is_err() {
-                break;
-            }
-        }
-    });
+    let mut forward_task = spawn_forwarder(ws_tx, rx, evicted);
 
-    // Process incoming WebSocket messages and broadcast them.
-    while let Some(result) = ws_rx.next().await {
+    // Process incoming WebSocket messages and broadcast them to the room,
+    // until the client goes away or the server drops it.
+    let mut bucket = limits.client_bucket();
+    let mut throttled = false;
+    loop {
+        let result = tokio::select! {
+            next = ws_rx.next() => match next {
+                Some(result) => result,
+                None => break,
+            },
+            _ = &mut forward_task => break,
+        };
         match result {
             Ok(msg) => {
+                if !limits.allow_message(&mut bucket, ip) {
+                    if !throttled {
+                        eprintln!("Throttling signaling client {} ({:?})", client_id, ip);
+                    }
+                    limits.record_throttled(!throttled);
+                    throttled = true;
+                    continue;
+                }
+                throttled = false;
+                // Moderator commands are handled here instead of being relayed.
+                if let Some(ClientMessage::Moderate(command)) = ClientMessage::parse(&msg) {
+                    moderation::handle_command(command, &rooms, &limits, store.as_ref(), &room_id, client_id);
+                    continue;
+                }
                 println!("Received signaling message from client {}: {:?}", client_id, msg);
-                broadcast_message(msg, &clients, client_id).await;
+                // The roster says whose stream it is before the tracks reach anyone.
+                if let Some(stream) = protocol::media_stream(&msg) {
+                    announce_stream(&rooms, &cluster, &room_id, client_id, stream);
+                }
+                if let Some(call) = broadcast_message(msg, &rooms, &limits, &cluster, &room_id, client_id) {
+                    println!("Call {} started in room {}", call.id, room_id);
+                    storage::spawn_update(store.save_call(call));
+                    analytics.record_call_started();
+                    broadcast_visitor_count(&visitors, &rooms, &analytics);
+                }
             }
             Err(e) => {
                 eprintln!("WebSocket error in signaling connection: {}", e);
@@ -82,97 +470,261 @@ async fn handle_connection(ws: WebSocket, clients: Clients) {
     }
     
 
-    // Remove disconnected clients.
-    {
-        let mut clients_lock = clients.lock().unwrap();
-        //clients_lock.retain(|sender| !sender.is_closed());
-        clients_lock.retain(|(_client_id, tx)| !tx.is_closed());
-        println!("Cleaned up signaling clients. Remaining: {}", clients_lock.len());
+    // Remove the disconnected client. Dropping its sender ends the forward task.
+    if let Some(mut room) = rooms.get_mut(&room_id) {
+        if room.remove_waiting(client_id).is_some() {
+            room.send_lobby();
+        } else {
+            room.remove_participant(client_id);
+        }
+        println!("Cleaned up signaling client {}. Remaining in room {}: {}", client_id, room_id, room.participants.len());
+        // The call is over once everybody left.
+        if room.participants.is_empty() {
+            if let Some(call) = room.end_call() {
+                println!("Call {} in room {} ended after {}s", call.id, room_id, call.duration_secs(auth::unix_now()));
+                storage::spawn_update(store.save_call(call));
+            }
+        }
+        if !room.is_empty() || room.persistent {
+            room.ensure_host();
+            room.send_roster();
+            room.send_lobby();
+        }
     }
+    // Checked again under the shard lock, in case someone joined in the meantime.
+    rooms.remove_if(&room_id, |_, room| room.is_empty() && !room.persistent);
 
-    forward_task.await.unwrap();
+    broadcast_visitor_count(&visitors, &rooms, &analytics);
+    cluster.presence_changed(&rooms);
+
+    // The forward task may already have finished (and been polled) in the loop above.
+    if !forward_task.is_finished() {
+        let _ = forward_task.await;
+    }
 }
 
-async fn broadcast_message(msg: Message, clients: &Clients, sender_id: ClientId) {
-    let clients_lock = clients.lock().unwrap();
-    println!("Broadcasting message from sender {} to {} clients", sender_id, clients_lock.len());
-    for (client_id, tx) in clients_lock.iter() {
-        if *client_id != sender_id {
-            // Wrap the message text in a JSON structure
-            if let Ok(text) = msg.to_str() {
-                let _ = tx.send(msg.clone());
-                println!("Sent message to client id {}", client_id);
-            }
+/// Puts the media stream `client_id` sends in on the room's roster, here and on the other
+/// nodes, so receivers can tell whose tracks they get.
+fn announce_stream(rooms: &Rooms, cluster: &Cluster, room_id: &str, client_id: ClientId, stream: String) {
+    let Some(mut room) = rooms.get_mut(room_id) else {
+        return;
+    };
+    if !room.set_stream(client_id, stream) {
+        return;
+    }
+    room.send_roster();
+    drop(room);
+    cluster.presence_changed(rooms);
+}
+
+/// Relays `msg` to everyone else in the room, on this node and the others. Returns the new
+/// call if the message started one, i.e. it is the first answer that reached anyone since
+/// the room was last empty.
+fn broadcast_message(
+    msg: Message,
+    rooms: &Rooms,
+    limits: &Limits,
+    cluster: &Cluster,
+    room_id: &str,
+    sender_id: ClientId,
+) -> Option<CallRecord> {
+    let mut room = rooms.get_mut(room_id)?;
+    // Clients waiting in the lobby can't talk to the room yet.
+    let Some(sender) = room.participant_mut(sender_id) else {
+        println!("Dropping message from client {} waiting in the lobby of room {}", sender_id, room_id);
+        return None;
+    };
+    sender.messages_sent += 1;
+    let sender_is_viewer = sender.viewer;
+    // Viewers only watch and listen; don't let them set up a call that sends media.
+    if sender_is_viewer && protocol::sends_media(&msg) {
+        eprintln!("Dropping offer with media from viewer {} in room {}", sender_id, room_id);
+        sender.send(&ServerMessage::error("viewer-media", "Viewers can't send audio or video."));
+        return None;
+    }
+    // Only text frames carry signaling messages.
+    if msg.to_str().is_err() {
+        return None;
+    }
+    println!("Broadcasting message from sender {} to {} clients in room {}", sender_id, room.participants.len(), room_id);
+    // Clients that can't keep up are removed and their socket closed.
+    let mut slow = Vec::new();
+    let mut delivered = 0;
+    // Two viewers have nothing to send each other, so they don't negotiate a connection.
+    let recipients = room
+        .participants
+        .values()
+        .filter(|participant| participant.id != sender_id && !(sender_is_viewer && participant.viewer));
+    for participant in recipients {
+        if limits.deliver(&participant.tx, msg.clone()) {
+            println!("Sent message to client id {}", participant.id);
+            delivered += 1;
         } else {
-            println!("Skipping sender id {}", sender_id);
+            eprintln!("Disconnecting slow signaling client {}", participant.id);
+            slow.push(participant.id);
+        }
+    }
+    if !slow.is_empty() {
+        for id in slow {
+            if let Some(participant) = room.remove_participant(id) {
+                participant.evict();
+            }
         }
+        room.ensure_host();
+        room.send_roster();
+    }
+    cluster.relay(room_id, sender_id, &msg);
+    // The call starts once an offer was answered, not when someone offers to an empty room.
+    let answered = protocol::is_answer(&msg) && (delivered > 0 || !room.remote.is_empty());
+    if room.call.is_none() && answered {
+        Some(room.start_call(room_id))
+    } else {
+        None
     }
 }
 
 
 
-async fn handle_visitor_connection(ws: WebSocket, visitors: Visitors) {
+async fn handle_visitor_connection(
+    ws: WebSocket,
+    visitors: Visitors,
+    rooms: Rooms,
+    analytics: SharedAnalytics,
+    slot: ConnectionGuard,
+) {
     // Split the WebSocket into sender (ws_tx) and receiver (ws_rx)
     let (mut ws_tx, mut ws_rx) = ws.split();
     println!("New visitor connection established");
 
+    // Subscribe this visitor to the stats broadcast.
+    let (mut visitor, count) = visitors.join();
+    println!("Added new visitor. Total visitors: {}", count);
+    analytics.record_visit(slot.ip(), count);
 
-    // Create a channel for sending messages to this visitor.
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    // Immediately broadcast the updated visitor count to all visitors.
+    broadcast_visitor_count(&visitors, &rooms, &analytics);
 
-    // Add this visitor's sender to the global visitors list.
-    {
-        let mut visitors_lock = visitors.lock().unwrap();
-        visitors_lock.push(tx);
-        println!("Added new visitor. Total visitors: {}", visitors_lock.len());
+    // Forward updates to the socket and watch for the visitor leaving, until either ends.
+    loop {
+        tokio::select! {
+            next = ws_rx.next() => match next {
+                Some(Ok(_)) => {
+                    // In this example, we don't process visitor messages.
+                }
+                _ => break,
+            },
+            update = visitor.recv() => match update {
+                Some(msg) => {
+                    let is_close = msg.is_close();
+                    if ws_tx.send(msg).await.is_err() || is_close {
+                        break;
+                    }
+                }
+                None => break,
+            },
+        }
     }
 
-    // Immediately broadcast the updated visitor count to all visitors.
-    broadcast_visitor_count(&visitors).await;
+    // When the connection closes, unsubscribe and broadcast the updated count.
+    drop(visitor);
+    broadcast_visitor_count(&visitors, &rooms, &analytics);
+}
 
-    // Spawn a task to forward messages from the rx channel to the WebSocket.
-    let forward_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if ws_tx.send(msg).await.is_err() {
-                break;
-            }
-        }
-    });
+/// Sends the current visitor stats to every visitor.
+fn broadcast_visitor_count(visitors: &VisitorHub, rooms: &Rooms, analytics: &Analytics) {
+    let stats = analytics.snapshot(visitors.count(), rooms);
+    visitors.send(Message::text(serde_json::to_string(&stats).expect("stats always serialize")));
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::backplane::MemoryBackplane;
+    use crate::rooms::Role;
+
+    const OFFER: &str = r#"{"type":"Offer","data":"v=0"}"#;
+    const ANSWER: &str = r#"{"type":"Answer","data":"v=0"}"#;
 
-    // Process incoming messages (if any) until the connection closes.
-    while let Some(result) = ws_rx.next().await {
-        if result.is_err() {
-            break;
+    /// A node alone on its backplane with `clients` in room `standup`.
+    fn room_with(clients: &[ClientId]) -> (Rooms, SharedLimits, SharedCluster, Vec<mpsc::Receiver<Message>>) {
+        let rooms = Rooms::default();
+        let mut room = Room::default();
+        let mut receivers = Vec::new();
+        for &id in clients {
+            let (participant, rx) = Participant::connected(id, Role::Participant);
+            room.add_participant(participant);
+            receivers.push(rx);
         }
-        // In this example, we don't process visitor messages.
+        rooms.insert("standup".to_string(), room);
+        let limits = Arc::new(Limits::new(&Cli::parse_from(["signaling"]).serve));
+        let cluster = Cluster::start("test".to_string(), Arc::new(MemoryBackplane::new()), limits.clone(), rooms.clone());
+        (rooms, limits, cluster, receivers)
     }
 
-    // When the connection closes, remove this visitor's sender.
-    {
-        let mut visitors_lock = visitors.lock().unwrap();
-        visitors_lock.retain(|sender| !sender.is_closed());
+    #[tokio::test]
+    async fn an_offer_alone_starts_no_call() {
+        let (rooms, limits, cluster, _rx) = room_with(&[1]);
+        assert!(broadcast_message(Message::text(OFFER), &rooms, &limits, &cluster, "standup", 1).is_none());
+        // Nobody to answer it, even if the client sends one.
+        assert!(broadcast_message(Message::text(ANSWER), &rooms, &limits, &cluster, "standup", 1).is_none());
+        assert!(rooms.get("standup").unwrap().call.is_none());
     }
-    // After removal, broadcast the updated count.
-    broadcast_visitor_count(&visitors).await;
 
-    // Wait for the forward task to finish.
-    let _ = forward_task.await;
-}
+    #[tokio::test]
+    async fn an_unanswered_offer_starts_no_call() {
+        let (rooms, limits, cluster, _rx) = room_with(&[1, 2]);
+        assert!(broadcast_message(Message::text(OFFER), &rooms, &limits, &cluster, "standup", 1).is_none());
+        assert!(rooms.get("standup").unwrap().call.is_none());
+    }
 
-async fn broadcast_visitor_count(visitors: &Visitors) {
-    // Get the current count.
-    let count = {
-        let visitors_lock = visitors.lock().unwrap();
-        visitors_lock.len()
-    };
+    #[tokio::test]
+    async fn the_first_answer_starts_the_call() {
+        let (rooms, limits, cluster, _rx) = room_with(&[1, 2]);
+        broadcast_message(Message::text(OFFER), &rooms, &limits, &cluster, "standup", 1);
+        let call = broadcast_message(Message::text(ANSWER), &rooms, &limits, &cluster, "standup", 2).unwrap();
+        assert_eq!(call.room, "standup");
+        assert_eq!(call.participants.len(), 2);
+        // Later exchanges belong to the same call.
+        assert!(broadcast_message(Message::text(ANSWER), &rooms, &limits, &cluster, "standup", 1).is_none());
+        assert_eq!(rooms.get("standup").unwrap().call.as_ref().unwrap().id, call.id);
+    }
+
+    /// The `stream` of each roster entry in the last roster `rx` got.
+    fn streams_in_last_roster(rx: &mut mpsc::Receiver<Message>) -> Vec<Option<String>> {
+        let mut last = None;
+        while let Ok(msg) = rx.try_recv() {
+            let json: serde_json::Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+            if json["type"] == "Roster" {
+                last = Some(json["data"]["participants"].clone());
+            }
+        }
+        let participants = last.expect("no roster sent");
+        participants.as_array().unwrap().iter().map(|entry| entry["stream"].as_str().map(str::to_string)).collect()
+    }
+
+    #[tokio::test]
+    async fn announces_each_senders_stream_on_the_roster() {
+        let (rooms, _limits, cluster, mut rx) = room_with(&[1, 2]);
+        announce_stream(&rooms, &cluster, "standup", 1, "cam-a".to_string());
+        assert_eq!(streams_in_last_roster(&mut rx[1]), vec![Some("cam-a".to_string()), None]);
+        announce_stream(&rooms, &cluster, "standup", 2, "cam-b".to_string());
+        for rx in &mut rx {
+            assert_eq!(streams_in_last_roster(rx), vec![Some("cam-a".to_string()), Some("cam-b".to_string())]);
+        }
 
-    // Build the JSON message.
-    let msg = Message::text(serde_json::json!({ "visitorCount": count }).to_string());
+        // Renegotiating with the same stream doesn't resend the roster.
+        announce_stream(&rooms, &cluster, "standup", 1, "cam-a".to_string());
+        assert!(rx.iter_mut().all(|rx| rx.try_recv().is_err()));
+    }
 
-    // Send the message to every connected visitor.
-    let visitors_lock = visitors.lock().unwrap();
-    for sender in visitors_lock.iter() {
-        let _ = sender.send(msg.clone());
+    #[tokio::test]
+    async fn viewers_have_no_stream() {
+        let (rooms, _limits, cluster, mut rx) = room_with(&[1, 2]);
+        rooms.get_mut("standup").unwrap().participant_mut(2).unwrap().viewer = true;
+        announce_stream(&rooms, &cluster, "standup", 2, "cam-b".to_string());
+        assert!(rx[0].try_recv().is_err());
+        assert_eq!(rooms.get("standup").unwrap().participants[&2].stream, None);
     }
 }
-
@@ -0,0 +1,457 @@
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::api::{add_stored_room, hash_room_password};
+use crate::auth::{unix_now, SharedAuth};
+use crate::errors::ApiError;
+use crate::limits::SharedLimits;
+use crate::rooms::{is_valid_room_name, Role, Rooms};
+use crate::storage::{MeetingRecord, RoomRecord, SharedStore};
+use crate::time;
+
+/// Largest JSON body accepted by the meetings API.
+const MAX_BODY_BYTES: u64 = 16 * 1024;
+/// Longest meeting title.
+const MAX_TITLE_CHARS: usize = 200;
+/// Length of a meeting when the caller doesn't say.
+const DEFAULT_DURATION_MINS: u32 = 60;
+/// Upper bound on meeting lengths.
+const MAX_DURATION_MINS: u32 = 24 * 60;
+/// How long after its end the host link of a meeting keeps working, in seconds.
+const HOST_LINK_GRACE_SECS: u64 = 60 * 60;
+/// How far ahead meetings can be scheduled, in seconds (about ten years).
+const MAX_SCHEDULE_AHEAD_SECS: u64 = 10 * 366 * 24 * 60 * 60;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleMeetingRequest {
+    pub title: String,
+    /// Unix time.
+    pub starts_at: u64,
+    pub duration_mins: Option<u32>,
+    pub password: Option<String>,
+    /// Hold joiners in a lobby until a host admits them.
+    #[serde(default)]
+    pub lobby: bool,
+    /// Name of the meeting's room; the meeting ID when missing.
+    pub room: Option<String>,
+    pub owner: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MeetingsQuery {
+    /// Unix time; meetings starting at or after it. Defaults to now.
+    pub from: Option<u64>,
+}
+
+/// A meeting with the links to hand out.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MeetingInfo {
+    #[serde(flatten)]
+    pub meeting: MeetingRecord,
+    /// Page that joins the meeting's room.
+    pub url: String,
+    /// Calendar invitation for the meeting.
+    pub ics_url: String,
+}
+
+impl MeetingInfo {
+    fn new(meeting: MeetingRecord, base_url: &str) -> Self {
+        MeetingInfo {
+            url: format!("{}/room/{}", base_url, meeting.room),
+            ics_url: format!("{}/api/meetings/{}.ics", base_url, meeting.id),
+            meeting,
+        }
+    }
+}
+
+/// Scheduled meetings under `/api/meetings`:
+///
+/// * `POST /api/meetings` with `{"title", "startsAt", "durationMins", "password", "lobby"}`
+///   opens a room for the meeting and returns its shareable URL, the URL of its
+///   calendar invitation and the invitation itself (`ics`). With join tokens configured
+///   it also returns `hostUrl`, which joins as host. Needs the API key, like creating rooms.
+/// * `GET /api/meetings?from=...` lists upcoming meetings. Always needs the API key.
+/// * `GET /api/meetings/{id}.ics` downloads the invitation. Public, so it can be
+///   linked in emails; it contains nothing the meeting URL doesn't give away.
+/// * `DELETE /api/meetings/{id}` cancels a meeting. Always needs the API key; the
+///   room stays until it is closed through the admin API.
+///
+/// Links point at `public_url` (`--public-url`). Without it nothing that hands out
+/// links works, since the request's `Host` header can't be trusted to name the server.
+pub fn routes(
+    rooms: Rooms,
+    auth: SharedAuth,
+    limits: SharedLimits,
+    store: SharedStore,
+    public_url: Option<String>,
+) -> BoxedFilter<(Response,)> {
+    let auth_filter = warp::any().map(move || auth.clone());
+    let store_filter = warp::any().map(move || store.clone());
+    let public_url: Option<Arc<str>> = public_url.map(|url| url.trim_end_matches('/').into());
+    let base_url = warp::any().map(move || public_url.clone());
+
+    let schedule = warp::path!("api" / "meetings")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(base_url.clone())
+        .and(warp::any().map(move || rooms.clone()))
+        .and(auth_filter.clone())
+        .and(warp::any().map(move || limits.clone()))
+        .and(store_filter.clone())
+        .and_then(schedule_meeting);
+
+    let list = warp::path!("api" / "meetings")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<MeetingsQuery>())
+        .and(base_url.clone())
+        .and(auth_filter.clone())
+        .and(store_filter.clone())
+        .and_then(list_meetings);
+
+    let invitation = warp::path!("api" / "meetings" / String)
+        .and(warp::get())
+        .and(base_url)
+        .and(store_filter.clone())
+        .and_then(invitation);
+
+    let cancel = warp::path!("api" / "meetings" / String)
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(auth_filter)
+        .and(store_filter)
+        .and_then(cancel_meeting);
+
+    schedule.or(list).unify().or(invitation).unify().or(cancel).unify().boxed()
+}
+
+fn meeting_not_found() -> Rejection {
+    warp::reject::custom(ApiError::new(StatusCode::NOT_FOUND, "meeting not found"))
+}
+
+/// The address links are built from, or an error when `--public-url` isn't set.
+fn links_base(base_url: &Option<Arc<str>>) -> Result<&str, ApiError> {
+    base_url
+        .as_deref()
+        .ok_or_else(|| ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "meeting links need --public-url to be configured"))
+}
+
+fn store_failed(e: impl std::fmt::Display) -> ApiError {
+    eprintln!("Meetings store failed: {}", e);
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "failed to access meetings")
+}
+
+async fn schedule_meeting(
+    authorization: Option<String>,
+    request: ScheduleMeetingRequest,
+    base_url: Option<Arc<str>>,
+    rooms: Rooms,
+    auth: SharedAuth,
+    limits: SharedLimits,
+    store: SharedStore,
+) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    let base_url = links_base(&base_url)?;
+    let title = request.title.trim().to_string();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        return Err(ApiError::bad_request(format!("title must be 1 to {} characters", MAX_TITLE_CHARS)).into());
+    }
+    let duration_mins = request.duration_mins.unwrap_or(DEFAULT_DURATION_MINS);
+    if duration_mins == 0 || duration_mins > MAX_DURATION_MINS {
+        return Err(ApiError::bad_request(format!("durationMins must be 1 to {}", MAX_DURATION_MINS)).into());
+    }
+    let now = unix_now();
+    let ends_at = request
+        .starts_at
+        .checked_add(u64::from(duration_mins) * 60)
+        .filter(|_| request.starts_at <= now.saturating_add(MAX_SCHEDULE_AHEAD_SECS))
+        .ok_or_else(|| ApiError::bad_request("startsAt is too far in the future"))?;
+    let id = format!("{:016x}", rand::random::<u64>());
+    let room = request.room.unwrap_or_else(|| id.clone());
+    if !is_valid_room_name(&room) {
+        return Err(ApiError::bad_request("invalid room name").into());
+    }
+
+    let record = RoomRecord {
+        name: room.clone(),
+        password_hash: hash_room_password(request.password).await?,
+        token_required: false,
+        lobby: request.lobby,
+        locked: false,
+        owner: request.owner,
+        created_at: now,
+    };
+    add_stored_room(&rooms, &limits, store.as_ref(), record).await?;
+    let meeting = MeetingRecord { id, room, title, starts_at: request.starts_at, duration_mins, created_at: now };
+    if let Err(e) = store.save_meeting(meeting.clone()).await {
+        // Don't leave a room behind that a retry would collide with.
+        rooms.remove(&meeting.room);
+        if let Err(e) = store.delete_room(meeting.room.clone()).await {
+            eprintln!("Failed to remove room {} of unscheduled meeting: {}", meeting.room, e);
+        }
+        return Err(store_failed(e).into());
+    }
+    println!("Scheduled meeting {} in room {} at {}", meeting.id, meeting.room, time::iso8601(meeting.starts_at));
+
+    // Lets whoever scheduled the meeting in as host, even into a lobby that someone
+    // else reached first. Only available when join tokens are configured.
+    let host_ttl = ends_at.saturating_sub(now) + HOST_LINK_GRACE_SECS;
+    let info = MeetingInfo::new(meeting, base_url);
+    let host_url = auth
+        .mint(&info.meeting.room, Role::Host, None, host_ttl)
+        .ok()
+        .map(|(token, _)| format!("{}?token={}", info.url, token));
+    let body = json!({ "meeting": &info, "ics": invitation_text(&info), "hostUrl": host_url });
+    Ok(warp::reply::with_status(warp::reply::json(&body), StatusCode::CREATED).into_response())
+}
+
+async fn list_meetings(
+    authorization: Option<String>,
+    query: MeetingsQuery,
+    base_url: Option<Arc<str>>,
+    auth: SharedAuth,
+    store: SharedStore,
+) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    let base_url = links_base(&base_url)?;
+    let meetings = store.meetings(query.from.unwrap_or_else(unix_now)).await.map_err(store_failed)?;
+    let meetings: Vec<MeetingInfo> = meetings.into_iter().map(|meeting| MeetingInfo::new(meeting, base_url)).collect();
+    Ok(warp::reply::json(&json!({ "meetings": meetings })).into_response())
+}
+
+async fn invitation(file: String, base_url: Option<Arc<str>>, store: SharedStore) -> Result<Response, Rejection> {
+    let base_url = links_base(&base_url)?;
+    let id = file.strip_suffix(".ics").ok_or_else(meeting_not_found)?;
+    let meeting = store.meeting(id.to_string()).await.map_err(store_failed)?.ok_or_else(meeting_not_found)?;
+    let info = MeetingInfo::new(meeting, base_url);
+    let reply = warp::reply::with_header(invitation_text(&info), "Content-Type", "text/calendar; charset=utf-8");
+    let disposition = format!("attachment; filename=\"meeting-{}.ics\"", info.meeting.id);
+    Ok(warp::reply::with_header(reply, "Content-Disposition", disposition).into_response())
+}
+
+async fn cancel_meeting(
+    id: String,
+    authorization: Option<String>,
+    auth: SharedAuth,
+    store: SharedStore,
+) -> Result<Response, Rejection> {
+    auth.check_api_key(authorization.as_deref())?;
+    store.meeting(id.clone()).await.map_err(store_failed)?.ok_or_else(meeting_not_found)?;
+    store.delete_meeting(id.clone()).await.map_err(store_failed)?;
+    println!("Cancelled meeting {}", id);
+    Ok(warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT).into_response())
+}
+
+/// The meeting as an iCalendar (RFC 5545) event.
+fn invitation_text(info: &MeetingInfo) -> String {
+    let meeting = &info.meeting;
+    let ends_at = meeting.starts_at.saturating_add(u64::from(meeting.duration_mins) * 60);
+    let lines = [
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Rust Video Chat//Signaling Server//EN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@videochat", meeting.id),
+        format!("DTSTAMP:{}", time::ical(meeting.created_at)),
+        format!("DTSTART:{}", time::ical(meeting.starts_at)),
+        format!("DTEND:{}", time::ical(ends_at)),
+        format!("SUMMARY:{}", ical_text(&meeting.title)),
+        format!("DESCRIPTION:{}", ical_text(&format!("Join the video call: {}", info.url))),
+        format!("LOCATION:{}", ical_text(&info.url)),
+        format!("URL:{}", info.url),
+        "END:VEVENT".to_string(),
+        "END:VCALENDAR".to_string(),
+    ];
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+/// Escapes a TEXT value.
+fn ical_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Ends a content line with CRLF, folding it so no line is longer than 75 bytes.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use futures::future;
+
+    use super::*;
+    use crate::auth::Auth;
+    use crate::config::Cli;
+    use crate::limits::Limits;
+    use crate::storage::{CallFilter, CallRecord, MemoryStore, Store, StoreError, StoreFuture};
+
+    /// Keeps rooms but fails to store meetings.
+    #[derive(Default)]
+    struct NoMeetings(MemoryStore);
+
+    impl Store for NoMeetings {
+        fn rooms(&self) -> StoreFuture<Vec<RoomRecord>> {
+            self.0.rooms()
+        }
+        fn save_room(&self, room: RoomRecord) -> StoreFuture<()> {
+            self.0.save_room(room)
+        }
+        fn set_room_locked(&self, name: String, locked: bool) -> StoreFuture<()> {
+            self.0.set_room_locked(name, locked)
+        }
+        fn delete_room(&self, name: String) -> StoreFuture<()> {
+            self.0.delete_room(name)
+        }
+        fn meetings(&self, from: u64) -> StoreFuture<Vec<MeetingRecord>> {
+            self.0.meetings(from)
+        }
+        fn meeting(&self, id: String) -> StoreFuture<Option<MeetingRecord>> {
+            self.0.meeting(id)
+        }
+        fn save_meeting(&self, _meeting: MeetingRecord) -> StoreFuture<()> {
+            Box::pin(future::ready(Err(StoreError("disk full".to_string()))))
+        }
+        fn delete_meeting(&self, id: String) -> StoreFuture<()> {
+            self.0.delete_meeting(id)
+        }
+        fn calls(&self, filter: CallFilter) -> StoreFuture<Vec<CallRecord>> {
+            self.0.calls(filter)
+        }
+        fn save_call(&self, call: CallRecord) -> StoreFuture<()> {
+            self.0.save_call(call)
+        }
+    }
+
+    fn api(rooms: &Rooms, store: SharedStore, public_url: Option<&str>) -> BoxedFilter<(Response,)> {
+        let limits = Arc::new(Limits::new(&Cli::parse_from(["signaling"]).serve));
+        let auth = Arc::new(Auth::new(None, Some("key".to_string())));
+        routes(rooms.clone(), auth, limits, store, public_url.map(str::to_string))
+            .recover(crate::errors::handle_rejection)
+            .map(Reply::into_response)
+            .boxed()
+    }
+
+    async fn schedule(
+        api: &BoxedFilter<(Response,)>,
+        body: serde_json::Value,
+    ) -> warp::http::Response<warp::hyper::body::Bytes> {
+        warp::test::request()
+            .method("POST")
+            .path("/api/meetings")
+            .header("authorization", "Bearer key")
+            .header("host", "evil.example")
+            .json(&body)
+            .reply(api)
+            .await
+    }
+
+    #[tokio::test]
+    async fn links_use_the_public_url() {
+        let rooms = Rooms::default();
+        let api = api(&rooms, Arc::new(MemoryStore::default()), Some("https://chat.example.com/"));
+        let body = json!({ "title": "Sync", "startsAt": unix_now() + 3600, "room": "sync" });
+        let response = schedule(&api, body).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["meeting"]["url"], "https://chat.example.com/room/sync");
+        assert!(!body["ics"].as_str().unwrap().contains("evil.example"));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_make_links_without_a_public_url() {
+        let rooms = Rooms::default();
+        let api = api(&rooms, Arc::new(MemoryStore::default()), None);
+        let response = schedule(&api, json!({ "title": "Sync", "startsAt": unix_now() + 3600 })).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_starts_out_of_range() {
+        let rooms = Rooms::default();
+        let api = api(&rooms, Arc::new(MemoryStore::default()), Some("https://chat.example.com"));
+        for starts_at in [u64::MAX, u64::MAX - 60, unix_now() + MAX_SCHEDULE_AHEAD_SECS + 3600] {
+            let response = schedule(&api, json!({ "title": "Sync", "startsAt": starts_at })).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "startsAt {}", starts_at);
+        }
+        assert!(rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn removes_the_room_when_the_meeting_cant_be_stored() {
+        let rooms = Rooms::default();
+        let store = Arc::new(NoMeetings::default());
+        let api = api(&rooms, store.clone(), Some("https://chat.example.com"));
+        let body = json!({ "title": "Sync", "startsAt": unix_now() + 3600, "room": "sync" });
+
+        assert_eq!(schedule(&api, body.clone()).await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(rooms.is_empty());
+        assert!(store.rooms().await.unwrap().is_empty());
+        // A retry isn't told the room already exists.
+        assert_eq!(schedule(&api, body).await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn escapes_text_values() {
+        assert_eq!(ical_text(r"a;b,c\d"), r"a\;b\,c\\d");
+        assert_eq!(ical_text("one\r\ntwo\nthree\rfour"), r"one\ntwo\nthree\nfour");
+        // Escaping the backslash first keeps the added ones intact.
+        assert_eq!(ical_text(r"\;"), r"\\\;");
+    }
+
+    #[test]
+    fn keeps_short_lines_whole() {
+        let line = "x".repeat(75);
+        assert_eq!(fold_line(&line), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn folds_long_lines_at_75_octets() {
+        let line = "x".repeat(76);
+        assert_eq!(fold_line(&line), format!("{}\r\n x\r\n", "x".repeat(75)));
+        let long = "y".repeat(200);
+        for folded in fold_line(&long).split("\r\n") {
+            assert!(folded.len() <= 75);
+        }
+    }
+
+    #[test]
+    fn never_splits_a_character() {
+        // 74 ASCII octets leave one octet, too little for the two-octet "é".
+        let line = format!("{}é", "x".repeat(74));
+        let folded = fold_line(&line);
+        assert_eq!(folded, format!("{}\r\n é\r\n", "x".repeat(74)));
+        for part in folded.split("\r\n") {
+            assert!(part.len() <= 75);
+        }
+        // Unfolding gives the line back.
+        assert_eq!(folded.replace("\r\n ", "").trim_end(), line);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::limits::Limits;
+use crate::protocol::{ModeratorCommand, MuteRequest, RosterEntry, ServerMessage};
+use crate::rooms::{ClientId, Role, Rooms};
+use crate::storage::{self, Store};
+
+/// Carries out a moderator command from `sender_id` if the sender is a host of the room.
+/// Clients waiting in the lobby aren't participants, so their commands are ignored.
+/// The sender gets an `Error` message when it isn't allowed or the target is unknown.
+pub fn handle_command(
+    command: ModeratorCommand,
+    rooms: &Rooms,
+    limits: &Limits,
+    store: &dyn Store,
+    room_id: &str,
+    sender_id: ClientId,
+) {
+    let Some(mut room) = rooms.get_mut(room_id) else {
+        return;
+    };
+    let Some(sender) = room.participant_mut(sender_id) else {
+        return;
+    };
+    if sender.role != Role::Host {
+        eprintln!("Refused {:?} from non-host client {} in room {}", command, sender_id, room_id);
+        sender.send(&ServerMessage::error("not-allowed", "Only hosts can do that."));
+        return;
+    }
+    let sender_name = sender.name.clone();
+    let sender_tx = sender.tx.clone();
+    let unknown_target = || {
+        let _ = sender_tx.try_send(
+            ServerMessage::error("unknown-participant", "That participant is no longer in the room.").to_message(),
+        );
+    };
+    println!("Host {} in room {} sent {:?}", sender_id, room_id, command);
+
+    match command {
+        ModeratorCommand::MuteRequest { target } => match parse_target(&target).and_then(|id| room.participant_mut(id)) {
+            Some(participant) => participant.send(&ServerMessage::MuteRequest(MuteRequest { by: sender_name })),
+            None => unknown_target(),
+        },
+        ModeratorCommand::Kick { target } => match parse_target(&target).and_then(|id| room.remove_participant(id)) {
+            Some(participant) => {
+                participant.disconnect("kicked", "You were removed from the room by the host.");
+                // Hosts may kick each other, or themselves.
+                room.ensure_host();
+                room.send_roster();
+                room.send_lobby();
+            }
+            None => unknown_target(),
+        },
+        ModeratorCommand::LockRoom { locked } => {
+            room.locked = locked;
+            room.send_roster();
+            // Stored rooms come back locked after a restart, too.
+            if room.persistent {
+                storage::spawn_update(store.set_room_locked(room_id.to_string(), locked));
+            }
+        }
+        ModeratorCommand::Promote { target } => match parse_target(&target).and_then(|id| room.participant_mut(id)) {
+            Some(participant) => {
+                participant.role = Role::Host;
+                room.send_roster();
+            }
+            None => unknown_target(),
+        },
+        ModeratorCommand::Admit { target } => {
+            if !limits.room_has_space(room.participants.len()) {
+                let _ = sender_tx.try_send(ServerMessage::error("room-full", "This room is full.").to_message());
+                return;
+            }
+            match parse_target(&target).and_then(|id| room.remove_waiting(id)) {
+                Some(admitted) => {
+                    admitted.send(&ServerMessage::Welcome(RosterEntry::new(&admitted)));
+                    room.add_participant(admitted);
+                    room.send_roster();
+                    room.send_lobby();
+                }
+                None => unknown_target(),
+            }
+        }
+        ModeratorCommand::Deny { target } => match parse_target(&target).and_then(|id| room.remove_waiting(id)) {
+            Some(denied) => {
+                denied.disconnect("lobby-denied", "The host didn't let you in.");
+                room.send_lobby();
+            }
+            None => unknown_target(),
+        },
+    }
+}
+
+fn parse_target(target: &str) -> Option<ClientId> {
+    target.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Cli;
+    use crate::rooms::{Participant, Room};
+    use crate::storage::MemoryStore;
+
+    fn limits() -> Limits {
+        Limits::new(&Cli::parse_from(["signaling"]).serve)
+    }
+
+    fn kick(target: ClientId) -> ModeratorCommand {
+        ModeratorCommand::Kick { target: target.to_string() }
+    }
+
+    #[test]
+    fn kicking_the_only_host_promotes_someone() {
+        let rooms = Rooms::default();
+        let mut room = Room::default();
+        let (first_host, _first_rx) = Participant::connected(1, Role::Host);
+        let (second_host, _second_rx) = Participant::connected(2, Role::Host);
+        let (guest, _guest_rx) = Participant::connected(3, Role::Participant);
+        room.add_participant(first_host);
+        room.add_participant(second_host);
+        room.add_participant(guest);
+        rooms.insert("standup".to_string(), room);
+
+        handle_command(kick(2), &rooms, &limits(), &MemoryStore::default(), "standup", 1);
+        handle_command(kick(1), &rooms, &limits(), &MemoryStore::default(), "standup", 1);
+
+        let room = rooms.get("standup").unwrap();
+        assert_eq!(room.participants.len(), 1);
+        assert_eq!(room.participants[&3].role, Role::Host);
+    }
+
+    #[test]
+    fn participants_cant_kick() {
+        let rooms = Rooms::default();
+        let mut room = Room::default();
+        let (host, _host_rx) = Participant::connected(1, Role::Host);
+        let (guest, mut guest_rx) = Participant::connected(2, Role::Participant);
+        room.add_participant(host);
+        room.add_participant(guest);
+        rooms.insert("standup".to_string(), room);
+
+        handle_command(kick(1), &rooms, &limits(), &MemoryStore::default(), "standup", 2);
+
+        assert_eq!(rooms.get("standup").unwrap().participants.len(), 2);
+        let refusal = guest_rx.try_recv().unwrap();
+        assert!(refusal.to_str().unwrap().contains("not-allowed"));
+    }
+}
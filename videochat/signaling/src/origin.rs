@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+use warp::http::uri::{Authority, Scheme};
+use warp::path::{FullPath, Peek};
+use warp::{Filter, Rejection};
+
+use crate::errors::ApiError;
+use crate::limits;
+
+/// Which `Origin`s may open a WebSocket to the server.
+/// An empty allow-list keeps the old behaviour of accepting every origin.
+#[derive(Clone, Debug)]
+pub struct OriginPolicy {
+    allowed: Option<Arc<HashSet<String>>>,
+}
+
+impl OriginPolicy {
+    pub fn new(origins: &[String]) -> Self {
+        if origins.is_empty() {
+            println!("No allowed origins configured; WebSocket upgrades are accepted from any origin");
+            return OriginPolicy { allowed: None };
+        }
+        let allowed: HashSet<String> = origins.iter().map(|o| normalize(o)).collect();
+        println!("Accepting WebSocket upgrades from origins: {:?}", allowed);
+        OriginPolicy { allowed: Some(Arc::new(allowed)) }
+    }
+
+    /// Requests without an `Origin` header don't come from a browser page and are let through;
+    /// the header only protects against other sites driving a visitor's browser.
+    pub fn is_allowed(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed, origin) {
+            (None, _) | (_, None) => true,
+            (Some(allowed), Some(origin)) => allowed.contains(&normalize(origin)),
+        }
+    }
+
+    /// CORS for the HTTP API, allowing the same origins as the WebSocket check.
+    pub fn cors(&self) -> warp::cors::Builder {
+        let cors = warp::cors()
+            .allow_methods(["GET", "POST", "PUT", "DELETE"])
+            .allow_headers(["authorization", "content-type"]);
+        match &self.allowed {
+            None => cors.allow_any_origin(),
+            Some(allowed) => {
+                // warp panics on origins it can't parse, so leave those out of CORS.
+                let valid = allowed.iter().filter(|origin| {
+                    let valid = is_valid_origin(origin);
+                    if !valid {
+                        eprintln!("Allowed origin {:?} is not scheme://host[:port]; not used for CORS", origin);
+                    }
+                    valid
+                });
+                cors.allow_origins(valid.map(String::as_str))
+            }
+        }
+    }
+}
+
+fn is_valid_origin(origin: &str) -> bool {
+    match origin.split_once("://") {
+        Some((scheme, host)) => scheme.parse::<Scheme>().is_ok() && host.parse::<Authority>().is_ok(),
+        None => false,
+    }
+}
+
+fn normalize(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+/// Rejects requests whose `Origin` header is not on the allow-list.
+pub fn check(policy: OriginPolicy, trust_proxy: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("origin")
+        .and(warp::path::full())
+        .and(limits::client_ip(trust_proxy))
+        .and_then(move |origin: Option<String>, path: FullPath, ip: Option<IpAddr>| {
+            let policy = policy.clone();
+            async move {
+                if policy.is_allowed(origin.as_deref()) {
+                    Ok(())
+                } else {
+                    eprintln!("Rejected upgrade to {} from origin {:?} (client {:?})", path.as_str(), origin, ip);
+                    Err(warp::reject::custom(ApiError::forbidden("origin not allowed")))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Matches requests under `/api`, leaving the path for the routes to match.
+pub fn api_path() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::peek()
+        .and_then(|peek: Peek| async move {
+            if peek.segments().next() == Some("api") {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_allow_list_accepts_everything() {
+        let policy = OriginPolicy::new(&[]);
+        assert!(policy.is_allowed(Some("https://anywhere.example")));
+        assert!(policy.is_allowed(None));
+    }
+
+    #[test]
+    fn allow_list_is_normalized() {
+        let policy = OriginPolicy::new(&["https://Chat.Example.com/".to_string()]);
+        assert!(policy.is_allowed(Some("https://chat.example.com")));
+        assert!(!policy.is_allowed(Some("https://evil.example")));
+        assert!(policy.is_allowed(None));
+    }
+
+    #[test]
+    fn invalid_origins_are_left_out_of_cors() {
+        assert!(is_valid_origin("https://chat.example.com:8443"));
+        assert!(!is_valid_origin("chat.example.com"));
+        assert!(!is_valid_origin("https://"));
+    }
+
+    #[tokio::test]
+    async fn cors_answers_preflights_for_allowed_origins_only() {
+        let policy = OriginPolicy::new(&["https://chat.example.com".to_string()]);
+        let route = api_path().and(warp::path!("api" / "rooms")).map(warp::reply).with(policy.cors());
+        let allowed = warp::test::request()
+            .method("OPTIONS")
+            .path("/api/rooms")
+            .header("origin", "https://chat.example.com")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "authorization, content-type")
+            .reply(&route)
+            .await;
+        assert_eq!(allowed.status(), 200);
+        assert_eq!(allowed.headers()["access-control-allow-origin"], "https://chat.example.com");
+        let refused = warp::test::request()
+            .method("OPTIONS")
+            .path("/api/rooms")
+            .header("origin", "https://evil.example")
+            .header("access-control-request-method", "POST")
+            .reply(&route)
+            .await;
+        assert_eq!(refused.status(), 403);
+    }
+}
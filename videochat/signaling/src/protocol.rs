@@ -0,0 +1,273 @@
+use serde::{Deserialize, Serialize};
+use warp::ws::Message;
+
+use crate::rooms::{Participant, Role};
+
+/// Messages the server itself sends to signaling clients. They use the same
+/// `{"type": ..., "data": ...}` envelope as the messages clients relay to each other.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", content = "data")]
+pub enum ServerMessage {
+    Error(ServerError),
+    /// The server is draining; clients should reconnect after the given delay.
+    #[serde(rename = "server-shutting-down")]
+    ServerShuttingDown(ShutdownNotice),
+    /// A message from the operators, shown to everyone it is sent to.
+    Announcement(Announcement),
+    /// Sent once after joining: the client's own ID and role.
+    Welcome(RosterEntry),
+    /// Everyone in the room, sent whenever someone joins, leaves or changes role.
+    Roster(Roster),
+    /// A host asks the client to mute its microphone.
+    MuteRequest(MuteRequest),
+    /// Sent instead of `Welcome` when the client is held in the lobby.
+    Waiting(RosterEntry),
+    /// Clients waiting in the lobby, sent to hosts whenever it changes.
+    Lobby(LobbyUpdate),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ServerError {
+    /// Machine readable reason, e.g. `room-full`.
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownNotice {
+    pub reconnect_after_ms: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Announcement {
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RosterEntry {
+    /// Client IDs don't fit into a JavaScript number, so they are sent as strings.
+    pub id: String,
+    pub name: Option<String>,
+    pub role: Role,
+    /// Watches and listens only; other viewers don't need to negotiate with it.
+    #[serde(default)]
+    pub viewer: bool,
+    /// ID of the media stream the client sends its audio and video in, once it offered or
+    /// answered with media. Receivers match it against the streams of incoming tracks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Roster {
+    pub locked: bool,
+    pub participants: Vec<RosterEntry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LobbyUpdate {
+    pub waiting: Vec<RosterEntry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MuteRequest {
+    /// Name of the host asking.
+    pub by: Option<String>,
+}
+
+impl RosterEntry {
+    pub fn new(participant: &Participant) -> Self {
+        RosterEntry {
+            id: participant.id.to_string(),
+            name: participant.name.clone(),
+            role: participant.role,
+            viewer: participant.viewer,
+            stream: participant.stream.clone(),
+        }
+    }
+}
+
+/// Messages clients send to the server itself rather than to the room.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", content = "data")]
+pub enum ClientMessage {
+    Moderate(ModeratorCommand),
+}
+
+/// Commands only hosts may send, e.g.
+/// `{"type": "Moderate", "data": {"command": "kick", "target": "123"}}`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ModeratorCommand {
+    MuteRequest { target: String },
+    Kick { target: String },
+    LockRoom { locked: bool },
+    Promote { target: String },
+    /// Lets a client waiting in the lobby into the room.
+    Admit { target: String },
+    /// Turns a client waiting in the lobby away.
+    Deny { target: String },
+}
+
+impl ClientMessage {
+    /// Parses a message meant for the server; anything else is relayed to the room.
+    pub fn parse(msg: &Message) -> Option<Self> {
+        serde_json::from_str(msg.to_str().ok()?).ok()
+    }
+}
+
+/// Whether a relayed message is an SDP answer, i.e. completes an offer/answer exchange.
+pub fn is_answer(msg: &Message) -> bool {
+    #[derive(Deserialize)]
+    struct Envelope {
+        #[serde(rename = "type")]
+        kind: String,
+    }
+    msg.to_str()
+        .ok()
+        .and_then(|text| serde_json::from_str::<Envelope>(text).ok())
+        .is_some_and(|envelope| envelope.kind == "Answer")
+}
+
+/// The SDP of a relayed offer or answer.
+fn session_description(msg: &Message) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Description {
+        #[serde(rename = "type")]
+        kind: String,
+        data: Option<String>,
+    }
+    let description = serde_json::from_str::<Description>(msg.to_str().ok()?).ok()?;
+    description.data.filter(|_| description.kind == "Offer" || description.kind == "Answer")
+}
+
+/// Whether a relayed offer or answer has the sender send audio or video, i.e. has a media
+/// section that is `sendrecv` or `sendonly`, the default when no direction is given.
+pub fn sends_media(msg: &Message) -> bool {
+    let Some(sdp) = session_description(msg) else {
+        return false;
+    };
+    let direction = |section: &str| {
+        section.lines().map(str::trim).rev().find_map(|line| match line {
+            "a=sendrecv" | "a=sendonly" | "a=recvonly" | "a=inactive" => Some(line.to_string()),
+            _ => None,
+        })
+    };
+    let mut sections = sdp.split("\nm=");
+    // Attributes before the first media section apply to all of them.
+    let session_direction = sections.next().and_then(direction);
+    sections
+        .filter(|section| section.starts_with("audio") || section.starts_with("video"))
+        .any(|section| {
+            let direction = direction(section).or(session_direction.clone());
+            matches!(direction.as_deref(), None | Some("a=sendrecv") | Some("a=sendonly"))
+        })
+}
+
+/// The media stream the sender's tracks belong to in a relayed offer or answer, from the
+/// first `a=msid:<stream> <track>` line that names one.
+pub fn media_stream(msg: &Message) -> Option<String> {
+    let sdp = session_description(msg)?;
+    sdp.lines()
+        .filter_map(|line| line.trim().strip_prefix("a=msid:"))
+        .filter_map(|msid| msid.split_whitespace().next())
+        .find(|stream| *stream != "-")
+        .map(str::to_string)
+}
+
+impl ServerMessage {
+    pub fn error(code: &str, message: impl Into<String>) -> Self {
+        ServerMessage::Error(ServerError { code: code.to_string(), message: message.into() })
+    }
+
+    pub fn shutting_down(reconnect_after_ms: u64) -> Self {
+        ServerMessage::ServerShuttingDown(ShutdownNotice { reconnect_after_ms })
+    }
+
+    pub fn announcement(message: impl Into<String>) -> Self {
+        ServerMessage::Announcement(Announcement { message: message.into() })
+    }
+
+    pub fn to_message(&self) -> Message {
+        Message::text(serde_json::to_string(self).expect("server messages always serialize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_notice_is_kebab_case() {
+        let json: serde_json::Value = serde_json::from_str(ServerMessage::shutting_down(5000).to_message().to_str().unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "server-shutting-down", "data": { "reconnectAfterMs": 5000 } }));
+    }
+
+    fn offer(sdp: &str) -> Message {
+        Message::text(serde_json::json!({ "type": "Offer", "data": sdp }).to_string())
+    }
+
+    const SESSION: &str = "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n";
+
+    #[test]
+    fn receive_only_offers_send_no_media() {
+        let sdp = format!(
+            "{}m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=mid:0\r\na=recvonly\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\na=mid:1\r\na=recvonly\r\n",
+            SESSION
+        );
+        assert!(!sends_media(&offer(&sdp)));
+        let data_only = format!("{}m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\na=mid:0\r\n", SESSION);
+        assert!(!sends_media(&offer(&data_only)));
+    }
+
+    #[test]
+    fn sending_sections_send_media() {
+        let sdp = format!(
+            "{}m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=recvonly\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\na=sendonly\r\n",
+            SESSION
+        );
+        assert!(sends_media(&offer(&sdp)));
+        let sendrecv = format!("{}m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=sendrecv\r\n", SESSION);
+        assert!(sends_media(&offer(&sendrecv)));
+        let answer = Message::text(serde_json::json!({ "type": "Answer", "data": sendrecv }).to_string());
+        assert!(sends_media(&answer));
+    }
+
+    #[test]
+    fn direction_defaults_to_sendrecv() {
+        let sdp = format!("{}m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=mid:0\r\n", SESSION);
+        assert!(sends_media(&offer(&sdp)));
+        let session_recvonly = format!("{}a=recvonly\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=mid:0\r\n", SESSION);
+        assert!(!sends_media(&offer(&session_recvonly)));
+    }
+
+    #[test]
+    fn other_messages_send_no_media() {
+        assert!(!sends_media(&Message::text(r#"{"type":"IceCandidate","data":{"candidate":"a=sendrecv"}}"#)));
+        assert!(!sends_media(&Message::text("not json")));
+    }
+
+    #[test]
+    fn reads_the_stream_the_tracks_are_sent_in() {
+        let sdp = format!(
+            "{}m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=mid:0\r\na=msid:- 0f1e\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\na=mid:1\r\na=msid:cam-7a2b 9c3d\r\n",
+            SESSION
+        );
+        assert_eq!(media_stream(&offer(&sdp)).as_deref(), Some("cam-7a2b"));
+        let answer = Message::text(serde_json::json!({ "type": "Answer", "data": sdp }).to_string());
+        assert_eq!(media_stream(&answer).as_deref(), Some("cam-7a2b"));
+
+        let receive_only = format!("{}m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=mid:0\r\na=recvonly\r\n", SESSION);
+        assert_eq!(media_stream(&offer(&receive_only)), None);
+        assert_eq!(media_stream(&Message::text(r#"{"type":"IceCandidate","data":{"candidate":"a=msid:x y"}}"#)), None);
+    }
+
+    #[test]
+    fn answers_complete_the_exchange() {
+        assert!(is_answer(&Message::text(r#"{"type":"Answer","data":"v=0"}"#)));
+        assert!(!is_answer(&Message::text(r#"{"type":"Offer","data":"v=0"}"#)));
+        assert!(!is_answer(&Message::text(r#"{"type":"IceCandidate","data":{}}"#)));
+        assert!(!is_answer(&Message::text("not json")));
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
+use warp::ws::Message;
+
+use crate::protocol::{LobbyUpdate, Roster, RosterEntry, ServerMessage};
+use crate::auth::unix_now;
+use crate::storage::{CallParticipant, CallRecord, RoomRecord};
+
+pub type ClientId = usize;
+
+/// Room used by clients that don't ask for a specific one.
+pub const DEFAULT_ROOM: &str = "default";
+
+/// What a participant is allowed to do in a room.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Host,
+    #[default]
+    Participant,
+}
+
+/// A connected signaling client.
+pub struct Participant {
+    pub id: ClientId,
+    pub name: Option<String>,
+    pub role: Role,
+    /// Joined to watch and listen only, without sending media.
+    pub viewer: bool,
+    pub ip: Option<IpAddr>,
+    /// Unix time the client joined at.
+    pub connected_at: u64,
+    /// Position in the order clients connected in, which rosters are sorted by.
+    pub joined: u64,
+    /// Messages relayed from this client to the room.
+    pub messages_sent: u64,
+    /// ID of the media stream the client sends, as announced in its SDP.
+    pub stream: Option<String>,
+    pub tx: Sender<Message>,
+    /// Tells the client's forwarder to close the socket right away, bypassing the queue.
+    pub evicted: Arc<Notify>,
+}
+
+/// A room and the clients currently in it.
+#[derive(Default)]
+pub struct Room {
+    /// Argon2 PHC string of the room password, if one is required.
+    pub password_hash: Option<String>,
+    /// Only clients presenting a valid join token may enter.
+    pub token_required: bool,
+    /// Rooms created through the API stay around when they empty out;
+    /// rooms created implicitly by the first joiner are dropped.
+    pub persistent: bool,
+    /// Locked rooms don't take new participants.
+    pub locked: bool,
+    /// Joiners wait in the lobby until a host admits them.
+    pub lobby: bool,
+    /// Whoever created the room through the API, if they said.
+    pub owner: Option<String>,
+    /// The call going on in the room, from the first answered offer until the room
+    /// is empty again.
+    pub call: Option<CallRecord>,
+    pub participants: HashMap<ClientId, Participant>,
+    /// Clients held in the lobby. They get no offers or roster events.
+    pub waiting: HashMap<ClientId, Participant>,
+    /// Participants of this room connected to other signaling nodes.
+    pub remote: Vec<RosterEntry>,
+}
+
+static JOIN_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// The next value for [`Participant::joined`].
+pub fn next_join_sequence() -> u64 {
+    JOIN_SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Participants sorted by when they connected.
+pub fn in_join_order(clients: &HashMap<ClientId, Participant>) -> Vec<&Participant> {
+    let mut sorted: Vec<&Participant> = clients.values().collect();
+    sorted.sort_by_key(|participant| participant.joined);
+    sorted
+}
+
+impl Participant {
+    /// Queues a server message for the client, dropping it if the queue is full.
+    pub fn send(&self, msg: &ServerMessage) {
+        let _ = self.tx.try_send(msg.to_message());
+    }
+
+    /// Tells the client why it is being removed and closes its socket.
+    /// The client's handler cleans up once the close frame went out.
+    pub fn disconnect(&self, code: &str, message: &str) {
+        self.send(&ServerMessage::error(code, message));
+        let _ = self.tx.try_send(Message::close_with(1008u16, code.to_string()));
+    }
+
+    /// Closes the socket of a client that stopped reading, whose queue has no room
+    /// left for a close frame.
+    pub fn evict(&self) {
+        self.evicted.notify_one();
+    }
+}
+
+impl Room {
+    /// A room created through the API before the last restart.
+    pub fn from_record(record: RoomRecord) -> Self {
+        Room {
+            password_hash: record.password_hash,
+            token_required: record.token_required,
+            persistent: true,
+            locked: record.locked,
+            lobby: record.lobby,
+            owner: record.owner,
+            ..Room::default()
+        }
+    }
+
+    pub fn participant_mut(&mut self, id: ClientId) -> Option<&mut Participant> {
+        self.participants.get_mut(&id)
+    }
+
+    /// Everyone connected to the room, admitted or waiting.
+    pub fn members(&self) -> impl Iterator<Item = &Participant> {
+        self.participants.values().chain(self.waiting.values())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.participants.is_empty() && self.waiting.is_empty()
+    }
+
+    /// Takes a client out of the lobby.
+    pub fn remove_waiting(&mut self, id: ClientId) -> Option<Participant> {
+        self.waiting.remove(&id)
+    }
+
+    /// Admits a participant, adding it to the call if one is going on.
+    pub fn add_participant(&mut self, participant: Participant) {
+        if let Some(call) = &mut self.call {
+            call.participants.push(CallParticipant::new(&participant, unix_now()));
+        }
+        self.participants.insert(participant.id, participant);
+    }
+
+    /// Lets a new client in and tells it and the room. The first one into an empty room
+    /// becomes host, so lobby rooms always have someone to admit people; as does anyone with
+    /// a host token. Everyone else joining a lobby room waits there. Returns whether the
+    /// client was held in the lobby.
+    pub fn join(&mut self, mut participant: Participant) -> bool {
+        if self.participants.is_empty() {
+            participant.role = Role::Host;
+        }
+        if self.lobby && participant.role != Role::Host {
+            // Held back until a host lets them in; only the hosts learn about them.
+            participant.send(&ServerMessage::Waiting(RosterEntry::new(&participant)));
+            self.waiting.insert(participant.id, participant);
+            self.send_lobby();
+            return true;
+        }
+        participant.send(&ServerMessage::Welcome(RosterEntry::new(&participant)));
+        self.add_participant(participant);
+        self.send_roster();
+        self.send_lobby();
+        false
+    }
+
+    /// Removes a participant, handing it back so the caller can say goodbye.
+    pub fn remove_participant(&mut self, id: ClientId) -> Option<Participant> {
+        let participant = self.participants.remove(&id)?;
+        if let Some(call) = &mut self.call {
+            call.left(&id.to_string(), unix_now());
+        }
+        Some(participant)
+    }
+
+    /// Starts recording a call with everyone in the room, handing back a copy to store.
+    pub fn start_call(&mut self, name: &str) -> CallRecord {
+        let now = unix_now();
+        let call = CallRecord {
+            id: format!("{:016x}", rand::random::<u64>()),
+            room: name.to_string(),
+            started_at: now,
+            ended_at: None,
+            participants: in_join_order(&self.participants)
+                .into_iter()
+                .map(|participant| CallParticipant::new(participant, now))
+                .collect(),
+        };
+        self.call = Some(call.clone());
+        call
+    }
+
+    /// Ends the call going on in the room, if any, handing it back to store.
+    pub fn end_call(&mut self) -> Option<CallRecord> {
+        let mut call = self.call.take()?;
+        let now = unix_now();
+        for participant in in_join_order(&self.participants) {
+            call.left(&participant.id.to_string(), now);
+        }
+        call.ended_at = Some(now);
+        Some(call)
+    }
+
+    /// Makes the longest-present participant host when the last host left.
+    pub fn ensure_host(&mut self) {
+        if !self.participants.values().any(|participant| participant.role == Role::Host) {
+            if let Some(first) = self.participants.values_mut().min_by_key(|participant| participant.joined) {
+                first.role = Role::Host;
+                println!("Promoted client {} to host", first.id);
+            }
+        }
+    }
+
+    /// Records the media stream `id` sends in; returns whether it changed. Viewers and
+    /// clients waiting in the lobby send no media.
+    pub fn set_stream(&mut self, id: ClientId, stream: String) -> bool {
+        match self.participants.get_mut(&id) {
+            Some(participant) if !participant.viewer && participant.stream.as_ref() != Some(&stream) => {
+                participant.stream = Some(stream);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sends the current roster to everyone in the room.
+    pub fn send_roster(&self) {
+        let roster = ServerMessage::Roster(Roster {
+            locked: self.locked,
+            participants: in_join_order(&self.participants)
+                .into_iter()
+                .map(RosterEntry::new)
+                .chain(self.remote.iter().cloned())
+                .collect(),
+        });
+        for participant in self.participants.values() {
+            participant.send(&roster);
+        }
+    }
+
+    /// Sends the list of waiting clients to the hosts.
+    pub fn send_lobby(&self) {
+        let lobby = ServerMessage::Lobby(LobbyUpdate {
+            waiting: in_join_order(&self.waiting).into_iter().map(RosterEntry::new).collect(),
+        });
+        for host in self.participants.values().filter(|participant| participant.role == Role::Host) {
+            host.send(&lobby);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Participant {
+    /// A client that just connected, with the receiving end of its queue.
+    pub fn connected(id: ClientId, role: Role) -> (Self, tokio::sync::mpsc::Receiver<Message>) {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let participant = Participant {
+            id,
+            name: None,
+            role,
+            viewer: false,
+            ip: None,
+            connected_at: unix_now(),
+            joined: next_join_sequence(),
+            messages_sent: 0,
+            stream: None,
+            tx,
+            evicted: Arc::new(Notify::new()),
+        };
+        (participant, rx)
+    }
+}
+
+/// All rooms by name. Each room is locked on its own shard, so traffic in one room
+/// doesn't wait for another. Never hold a room across an `.await`, and never look
+/// at other rooms (`len`, `iter`) while holding one: that deadlocks on a shared shard.
+pub type Rooms = Arc<DashMap<String, Room>>;
+
+/// Room names end up in URLs and logs, so keep them short and boring.
+pub fn is_valid_room_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `type` of every message queued for a client so far.
+    fn received(rx: &mut tokio::sync::mpsc::Receiver<Message>) -> Vec<String> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|msg| {
+                let json: serde_json::Value = serde_json::from_str(msg.to_str().unwrap()).unwrap();
+                json["type"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    fn lobby_room() -> Room {
+        Room::from_record(RoomRecord {
+            name: "standup".to_string(),
+            password_hash: None,
+            token_required: false,
+            lobby: true,
+            locked: false,
+            owner: None,
+            created_at: 0,
+        })
+    }
+
+    #[test]
+    fn first_into_an_open_room_is_host() {
+        let mut room = Room::default();
+        let (first, mut first_rx) = Participant::connected(1, Role::Participant);
+        let (second, _second_rx) = Participant::connected(2, Role::Participant);
+        assert!(!room.join(first));
+        assert!(!room.join(second));
+        assert_eq!(room.participants[&1].role, Role::Host);
+        assert_eq!(room.participants[&2].role, Role::Participant);
+        assert_eq!(received(&mut first_rx), ["Welcome", "Roster", "Lobby", "Roster", "Lobby"]);
+    }
+
+    #[test]
+    fn first_into_an_empty_lobby_room_is_host_and_admits_the_rest() {
+        let mut room = lobby_room();
+        let (first, mut first_rx) = Participant::connected(1, Role::Participant);
+        let (second, mut second_rx) = Participant::connected(2, Role::Participant);
+        assert!(!room.join(first));
+        assert_eq!(room.participants[&1].role, Role::Host);
+        assert_eq!(received(&mut first_rx), ["Welcome", "Roster", "Lobby"]);
+
+        assert!(room.join(second));
+        assert!(room.waiting.contains_key(&2));
+        assert_eq!(received(&mut second_rx), ["Waiting"]);
+        // The host hears about them and can let them in.
+        assert_eq!(received(&mut first_rx), ["Lobby"]);
+    }
+
+    #[test]
+    fn host_tokens_skip_the_lobby() {
+        let mut room = lobby_room();
+        let (first, _first_rx) = Participant::connected(1, Role::Participant);
+        let (host, mut host_rx) = Participant::connected(2, Role::Host);
+        room.join(first);
+        assert!(!room.join(host));
+        assert_eq!(received(&mut host_rx), ["Welcome", "Roster", "Lobby"]);
+    }
+
+    #[test]
+    fn ensure_host_promotes_the_longest_present() {
+        let mut room = Room::default();
+        let (first, _first_rx) = Participant::connected(1, Role::Participant);
+        let (second, _second_rx) = Participant::connected(2, Role::Participant);
+        room.add_participant(second);
+        room.add_participant(first);
+        room.ensure_host();
+        assert_eq!(room.participants[&1].role, Role::Host);
+        assert_eq!(room.participants[&2].role, Role::Participant);
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use warp::http::StatusCode;
+use warp::ws::Message;
+use warp::{Filter, Rejection};
+
+use crate::errors::ApiError;
+use crate::protocol::ServerMessage;
+use crate::rooms::Rooms;
+use crate::visitors::VisitorHub;
+
+/// Close code sent to clients when the server goes away (RFC 6455 "going away").
+const CLOSE_GOING_AWAY: u16 = 1001;
+/// Time given to the final close frames to reach the clients.
+const CLOSE_FLUSH: Duration = Duration::from_secs(1);
+
+/// Set once the server starts draining; new sockets are refused from then on.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    draining: AtomicBool,
+}
+
+pub type SharedShutdown = Arc<Shutdown>;
+
+impl Shutdown {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting new sockets.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Rejects WebSocket upgrades with `503` while the server is draining.
+pub fn reject_when_draining(shutdown: SharedShutdown) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let shutdown = shutdown.clone();
+            async move {
+                if shutdown.is_draining() {
+                    Err(warp::reject::custom(ApiError::new(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "server is shutting down",
+                    )))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => println!("Received SIGINT"),
+        _ = terminate => println!("Received SIGTERM"),
+    }
+}
+
+/// Drains the server: refuses new sockets, tells every client to reconnect after
+/// `reconnect_after`, waits up to `drain` for calls to end and finally closes all
+/// remaining sockets with "going away".
+pub async fn drain(
+    shutdown: &Shutdown,
+    rooms: &Rooms,
+    visitors: &VisitorHub,
+    drain: Duration,
+    reconnect_after: Duration,
+) {
+    shutdown.start_draining();
+    let reconnect_after_ms = reconnect_after.as_millis() as u64;
+    println!("Draining signaling server for up to {:?}", drain);
+
+    // Warn everyone first so the frontends can tell their users.
+    let notice = ServerMessage::shutting_down(reconnect_after_ms).to_message();
+    for_each_client(rooms, |tx| {
+        let _ = tx.try_send(notice.clone());
+    });
+    visitors.send(Message::text(
+        serde_json::json!({ "serverShuttingDown": true, "reconnectAfterMs": reconnect_after_ms }).to_string(),
+    ));
+
+    // Give ongoing calls time to finish, but stop early once nobody is left.
+    let deadline = Instant::now() + drain;
+    while Instant::now() < deadline && signaling_clients(rooms) > 0 {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    println!("Closing {} remaining signaling clients", signaling_clients(rooms));
+    for_each_client(rooms, |tx| {
+        let _ = tx.try_send(Message::close_with(CLOSE_GOING_AWAY, "server shutting down"));
+    });
+    visitors.send(Message::close_with(CLOSE_GOING_AWAY, "server shutting down"));
+    tokio::time::sleep(CLOSE_FLUSH).await;
+}
+
+fn signaling_clients(rooms: &Rooms) -> usize {
+    rooms.iter().map(|room| room.members().count()).sum()
+}
+
+fn for_each_client(rooms: &Rooms, mut f: impl FnMut(&tokio::sync::mpsc::Sender<Message>)) {
+    for room in rooms.iter() {
+        for participant in room.members() {
+            f(&participant.tx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::StatusCode;
+
+    use super::*;
+    use crate::rooms::{ClientId, Participant, Role, Room};
+
+    fn room_with(ids: &[ClientId]) -> (Rooms, Vec<tokio::sync::mpsc::Receiver<Message>>) {
+        let rooms = Rooms::default();
+        let mut room = Room::default();
+        let mut clients = Vec::new();
+        for &id in ids {
+            let (participant, rx) = Participant::connected(id, Role::Participant);
+            room.add_participant(participant);
+            clients.push(rx);
+        }
+        rooms.insert("standup".to_string(), room);
+        (rooms, clients)
+    }
+
+    #[tokio::test]
+    async fn refuses_new_sockets_while_draining() {
+        let shutdown = SharedShutdown::default();
+        let filter = reject_when_draining(shutdown.clone()).map(warp::reply);
+        assert!(warp::test::request().filter(&filter).await.is_ok());
+
+        shutdown.start_draining();
+        let reply = warp::test::request()
+            .reply(&filter.recover(crate::errors::handle_rejection))
+            .await;
+        assert_eq!(reply.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn warns_clients_then_closes_them() {
+        let shutdown = Shutdown::default();
+        let (rooms, mut clients) = room_with(&[1, 2]);
+        let visitors = VisitorHub::new(4);
+        let (mut visitor, _) = visitors.join();
+
+        drain(&shutdown, &rooms, &visitors, Duration::ZERO, Duration::from_secs(5)).await;
+        assert!(shutdown.is_draining());
+
+        for rx in &mut clients {
+            assert_eq!(rx.recv().await.unwrap(), ServerMessage::shutting_down(5000).to_message());
+            let close = rx.recv().await.unwrap();
+            assert_eq!(close.close_frame().unwrap().0, CLOSE_GOING_AWAY);
+        }
+        let notice: serde_json::Value = serde_json::from_str(visitor.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(notice, serde_json::json!({ "serverShuttingDown": true, "reconnectAfterMs": 5000 }));
+        let close = visitor.recv().await.unwrap();
+        assert_eq!(close.close_frame().unwrap().0, CLOSE_GOING_AWAY);
+    }
+
+    #[tokio::test]
+    async fn stops_waiting_once_everyone_has_left() {
+        let shutdown = Shutdown::default();
+        let (rooms, mut clients) = room_with(&[1]);
+        let visitors = VisitorHub::new(4);
+        let leaving = rooms.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            leaving.remove("standup");
+        });
+
+        let started = Instant::now();
+        drain(&shutdown, &rooms, &visitors, Duration::from_secs(30), Duration::ZERO).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The client left before the deadline, so it only got the notice.
+        assert!(clients[0].recv().await.unwrap().is_text());
+        assert!(clients[0].recv().await.is_none());
+    }
+}
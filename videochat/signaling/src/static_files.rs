@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderValue, CACHE_CONTROL};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+/// Copy of the Trunk build output baked into the binary (`--features embed-frontend`).
+#[cfg(feature = "embed-frontend")]
+static EMBEDDED_DIST: include_dir::Dir<'static> =
+    include_dir::include_dir!("$CARGO_MANIFEST_DIR/../frontend/dist");
+
+/// Builds the route serving the frontend bundle.
+///
+/// Files come from `static_dir` when it is set, otherwise from the embedded copy if
+/// the binary was built with it. Unknown paths that don't look like files fall back
+/// to `index.html` so client-side routes survive a reload.
+pub fn routes(static_dir: Option<PathBuf>, max_age: u64) -> BoxedFilter<(Response,)> {
+    match static_dir {
+        Some(dir) => {
+            println!("Serving frontend from {}", dir.display());
+            disk_routes(dir, max_age)
+        }
+        None => embedded_routes(max_age),
+    }
+}
+
+fn disk_routes(dir: PathBuf, max_age: u64) -> BoxedFilter<(Response,)> {
+    let files = warp::fs::dir(dir.clone());
+    let spa_fallback = warp::path::full()
+        .and_then(|path: FullPath| async move {
+            if is_client_route(path.as_str()) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::file(dir.join("index.html")));
+
+    warp::get()
+        .and(files.or(spa_fallback).unify())
+        .map(move |file: warp::fs::File| {
+            let name = file_name(file.path()).to_string();
+            with_cache_headers(file.into_response(), &name, max_age)
+        })
+        .boxed()
+}
+
+#[cfg(feature = "embed-frontend")]
+fn embedded_routes(max_age: u64) -> BoxedFilter<(Response,)> {
+    println!("Serving embedded frontend");
+    warp::get()
+        .and(warp::path::full())
+        .and_then(move |path: FullPath| async move {
+            let requested = path.as_str().trim_start_matches('/');
+            let file = match EMBEDDED_DIST.get_file(requested) {
+                Some(file) => file,
+                None if is_client_route(path.as_str()) => {
+                    EMBEDDED_DIST.get_file("index.html").ok_or_else(warp::reject::not_found)?
+                }
+                None => return Err(warp::reject::not_found()),
+            };
+            let mime = mime_guess::from_path(file.path()).first_or_octet_stream();
+            let reply = warp::reply::with_header(
+                file.contents(),
+                warp::http::header::CONTENT_TYPE,
+                mime.as_ref(),
+            );
+            Ok(with_cache_headers(reply.into_response(), file_name(file.path()), max_age))
+        })
+        .boxed()
+}
+
+#[cfg(not(feature = "embed-frontend"))]
+fn embedded_routes(_max_age: u64) -> BoxedFilter<(Response,)> {
+    warp::any()
+        .and_then(|| async { Err::<Response, _>(warp::reject::not_found()) })
+        .boxed()
+}
+
+/// Top-level path segments owned by the server; unknown paths below them are 404s
+/// rather than the app.
+const SERVER_PREFIXES: &[&str] = &["api", "admin", "ws", "visitors"];
+
+/// Paths without an extension in their last segment are treated as SPA routes,
+/// except below the server's own prefixes.
+fn is_client_route(path: &str) -> bool {
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    let last = path.rsplit('/').next().unwrap_or("");
+    !SERVER_PREFIXES.contains(&first) && !last.contains('.')
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|name| name.to_str()).unwrap_or("")
+}
+
+/// `index.html` must always be revalidated so new builds are picked up; the other
+/// assets can be cached for `max_age` seconds.
+fn with_cache_headers(mut response: Response, file_name: &str, max_age: u64) -> Response {
+    let value = if file_name == "index.html" {
+        HeaderValue::from_static("no-cache")
+    } else {
+        HeaderValue::from_str(&format!("public, max-age={}", max_age))
+            .unwrap_or_else(|_| HeaderValue::from_static("no-cache"))
+    };
+    response.headers_mut().insert(CACHE_CONTROL, value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::header::CONTENT_TYPE;
+    use warp::http::StatusCode;
+
+    use super::*;
+
+    /// A dist directory with an index, a hashed bundle and its wasm.
+    fn dist() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("signaling-dist-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join("frontend-3f2a.js"), "export {}").unwrap();
+        std::fs::write(dir.join("frontend-3f2a_bg.wasm"), b"\0asm").unwrap();
+        dir
+    }
+
+    async fn get(dir: &Path, path: &str) -> warp::http::Response<warp::hyper::body::Bytes> {
+        let routes = disk_routes(dir.to_path_buf(), 3600).recover(crate::errors::handle_rejection);
+        warp::test::request().path(path).reply(&routes).await
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_app_for_client_routes_only() {
+        let dir = dist();
+        let reply = get(&dir, "/room/x").await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(reply.body().as_ref(), b"<html></html>");
+
+        assert_eq!(get(&dir, "/api/nope").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(get(&dir, "/admin/rooms/x").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(get(&dir, "/frontend-0000.js").await.status(), StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_index_is_revalidated() {
+        let dir = dist();
+        for path in ["/", "/index.html", "/room/x"] {
+            assert_eq!(get(&dir, path).await.headers()[CACHE_CONTROL], "no-cache", "{}", path);
+        }
+        let reply = get(&dir, "/frontend-3f2a.js").await;
+        assert_eq!(reply.headers()[CACHE_CONTROL], "public, max-age=3600");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_wasm_with_its_mime_type() {
+        let dir = dist();
+        let reply = get(&dir, "/frontend-3f2a_bg.wasm").await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(reply.headers()[CONTENT_TYPE], "application/wasm");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use futures::future;
+
+use super::{CallFilter, CallRecord, MeetingRecord, RoomRecord, Store, StoreFuture};
+
+/// Keeps everything in memory. Used when no database is configured, and handy as a
+/// stand-in for SQLite in tests.
+#[derive(Default, Clone)]
+pub struct MemoryStore {
+    rooms: Arc<Mutex<BTreeMap<String, RoomRecord>>>,
+    meetings: Arc<Mutex<BTreeMap<String, MeetingRecord>>>,
+    calls: Arc<Mutex<BTreeMap<String, CallRecord>>>,
+}
+
+fn ready<T: Send + 'static>(value: T) -> StoreFuture<T> {
+    Box::pin(future::ready(Ok(value)))
+}
+
+impl Store for MemoryStore {
+    fn rooms(&self) -> StoreFuture<Vec<RoomRecord>> {
+        ready(self.rooms.lock().unwrap().values().cloned().collect())
+    }
+
+    fn save_room(&self, room: RoomRecord) -> StoreFuture<()> {
+        self.rooms.lock().unwrap().insert(room.name.clone(), room);
+        ready(())
+    }
+
+    fn set_room_locked(&self, name: String, locked: bool) -> StoreFuture<()> {
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(&name) {
+            room.locked = locked;
+        }
+        ready(())
+    }
+
+    fn delete_room(&self, name: String) -> StoreFuture<()> {
+        self.rooms.lock().unwrap().remove(&name);
+        ready(())
+    }
+
+    fn meetings(&self, from: u64) -> StoreFuture<Vec<MeetingRecord>> {
+        let mut meetings: Vec<MeetingRecord> = self
+            .meetings
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|meeting| meeting.starts_at >= from)
+            .cloned()
+            .collect();
+        meetings.sort_by_key(|meeting| meeting.starts_at);
+        ready(meetings)
+    }
+
+    fn meeting(&self, id: String) -> StoreFuture<Option<MeetingRecord>> {
+        ready(self.meetings.lock().unwrap().get(&id).cloned())
+    }
+
+    fn save_meeting(&self, meeting: MeetingRecord) -> StoreFuture<()> {
+        self.meetings.lock().unwrap().insert(meeting.id.clone(), meeting);
+        ready(())
+    }
+
+    fn delete_meeting(&self, id: String) -> StoreFuture<()> {
+        self.meetings.lock().unwrap().remove(&id);
+        ready(())
+    }
+
+    fn calls(&self, filter: CallFilter) -> StoreFuture<Vec<CallRecord>> {
+        let mut calls: Vec<CallRecord> =
+            self.calls.lock().unwrap().values().filter(|call| filter.matches(call)).cloned().collect();
+        calls.sort_by_key(|call| call.started_at);
+        ready(calls)
+    }
+
+    fn save_call(&self, call: CallRecord) -> StoreFuture<()> {
+        self.calls.lock().unwrap().insert(call.id.clone(), call);
+        ready(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Persistent state: rooms created through the API, scheduled meetings and call history.
+//!
+//! [`Store`] has two backends: [`SqliteStore`] for real deployments and [`MemoryStore`]
+//! for running without a database file (everything is gone after a restart).
+
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::rooms::Participant;
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// A room created through the API.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomRecord {
+    pub name: String,
+    /// Argon2 PHC string of the room password.
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub token_required: bool,
+    pub lobby: bool,
+    pub locked: bool,
+    /// Whoever created the room, as given by the API caller.
+    pub owner: Option<String>,
+    /// Unix time.
+    pub created_at: u64,
+}
+
+/// A meeting scheduled in a room.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MeetingRecord {
+    pub id: String,
+    pub room: String,
+    pub title: String,
+    /// Unix time.
+    pub starts_at: u64,
+    pub duration_mins: u32,
+    pub created_at: u64,
+}
+
+/// A call in a room, from the first answered offer until the room emptied.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRecord {
+    pub id: String,
+    pub room: String,
+    /// Unix time.
+    pub started_at: u64,
+    /// `None` while the call is going on.
+    pub ended_at: Option<u64>,
+    pub participants: Vec<CallParticipant>,
+}
+
+impl CallRecord {
+    /// How long the call lasted, or has lasted until `now` if it is still going on.
+    pub fn duration_secs(&self, now: u64) -> u64 {
+        self.ended_at.unwrap_or(now).saturating_sub(self.started_at)
+    }
+
+    /// Notes that client `client_id` left at `at`.
+    pub fn left(&mut self, client_id: &str, at: u64) {
+        let open = self
+            .participants
+            .iter_mut()
+            .find(|participant| participant.client_id == client_id && participant.left_at.is_none());
+        if let Some(participant) = open {
+            participant.left_at = Some(at);
+        }
+    }
+}
+
+/// Someone who was in a call.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallParticipant {
+    pub client_id: String,
+    pub name: Option<String>,
+    /// Unix time they joined the call, or the call start if they were there before.
+    pub joined_at: u64,
+    pub left_at: Option<u64>,
+}
+
+impl CallParticipant {
+    pub fn new(participant: &Participant, joined_at: u64) -> Self {
+        CallParticipant { client_id: participant.id.to_string(), name: participant.name.clone(), joined_at, left_at: None }
+    }
+
+    /// How long they were in the call, or have been until `now` if they still are.
+    pub fn duration_secs(&self, now: u64) -> u64 {
+        self.left_at.unwrap_or(now).saturating_sub(self.joined_at)
+    }
+}
+
+/// Which calls to list. Times are Unix times; a call matches when it started in `[from, to)`.
+#[derive(Debug, Clone, Default)]
+pub struct CallFilter {
+    pub room: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl CallFilter {
+    pub fn matches(&self, call: &CallRecord) -> bool {
+        self.room.as_ref().is_none_or(|room| *room == call.room)
+            && self.from.is_none_or(|from| call.started_at >= from)
+            && self.to.is_none_or(|to| call.started_at < to)
+    }
+}
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "storage error: {}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Result of a store operation. The futures don't borrow the store, so updates can
+/// be spawned from code that can't wait for them.
+pub type StoreFuture<T> = BoxFuture<'static, Result<T, StoreError>>;
+
+/// Where rooms, meetings and calls are kept.
+pub trait Store: Send + Sync {
+    /// All stored rooms, by name.
+    fn rooms(&self) -> StoreFuture<Vec<RoomRecord>>;
+    /// Adds a room or replaces the one with the same name.
+    fn save_room(&self, room: RoomRecord) -> StoreFuture<()>;
+    fn set_room_locked(&self, name: String, locked: bool) -> StoreFuture<()>;
+    fn delete_room(&self, name: String) -> StoreFuture<()>;
+
+    /// Meetings starting at or after `from`, earliest first.
+    fn meetings(&self, from: u64) -> StoreFuture<Vec<MeetingRecord>>;
+    fn meeting(&self, id: String) -> StoreFuture<Option<MeetingRecord>>;
+    /// Adds a meeting or replaces the one with the same ID.
+    fn save_meeting(&self, meeting: MeetingRecord) -> StoreFuture<()>;
+    fn delete_meeting(&self, id: String) -> StoreFuture<()>;
+
+    /// Calls matching `filter`, earliest first.
+    fn calls(&self, filter: CallFilter) -> StoreFuture<Vec<CallRecord>>;
+    /// Adds a call or replaces the one with the same ID, participants included.
+    fn save_call(&self, call: CallRecord) -> StoreFuture<()>;
+}
+
+pub type SharedStore = Arc<dyn Store>;
+
+/// Opens the SQLite database at `path`, creating and migrating it as needed, or
+/// keeps everything in memory without a path.
+pub fn open(path: Option<&Path>) -> Result<SharedStore, StoreError> {
+    match path {
+        Some(path) => {
+            println!("Keeping rooms, meetings and calls in {}", path.display());
+            Ok(Arc::new(SqliteStore::open(path)?))
+        }
+        None => {
+            println!("No database configured; rooms, meetings and calls are kept in memory only");
+            Ok(Arc::new(MemoryStore::default()))
+        }
+    }
+}
+
+/// Updates handed to [`spawn_update`], applied one after the other.
+static UPDATES: OnceLock<mpsc::UnboundedSender<StoreFuture<()>>> = OnceLock::new();
+
+/// Runs a store update in the background from code that can't wait for it,
+/// logging failures. Updates are applied in the order they were spawned, so e.g.
+/// the end of a short call can't be overwritten by its start.
+pub fn spawn_update(update: StoreFuture<()>) {
+    let updates = UPDATES.get_or_init(|| {
+        let (tx, mut rx) = mpsc::unbounded_channel::<StoreFuture<()>>();
+        tokio::spawn(async move {
+            while let Some(update) = rx.recv().await {
+                if let Err(e) = update.await {
+                    eprintln!("Failed to update the store: {}", e);
+                }
+            }
+        });
+        tx
+    });
+    let _ = updates.send(update);
+}
+
+/// Waits until the updates spawned so far have been applied.
+pub async fn flush_updates() {
+    let (done, applied) = tokio::sync::oneshot::channel();
+    spawn_update(Box::pin(async move {
+        let _ = done.send(());
+        Ok(())
+    }));
+    let _ = applied.await;
+}
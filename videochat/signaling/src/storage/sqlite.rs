@@ -0,0 +1,328 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{CallFilter, CallParticipant, CallRecord, MeetingRecord, RoomRecord, Store, StoreError, StoreFuture};
+
+/// Schema changes, applied in order. `PRAGMA user_version` remembers how many ran.
+/// Only ever append to this list; shipped migrations must not change.
+const MIGRATIONS: &[&str] = &[include_str!("../../migrations/0001_initial.sql")];
+
+/// SQLite database file. Queries run on the blocking thread pool.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError(e.to_string())
+    }
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and brings its schema up to date.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+        Ok(SqliteStore { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool.
+    fn run<T, F>(&self, f: F) -> StoreFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap()))
+                .await
+                .map_err(|e| StoreError(e.to_string()))?
+                .map_err(StoreError::from)
+        })
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), StoreError> {
+    let applied: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if applied > MIGRATIONS.len() {
+        return Err(StoreError(format!(
+            "database schema version {} is newer than this server ({})",
+            applied,
+            MIGRATIONS.len()
+        )));
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+        println!("Applied database migration {}", version + 1);
+    }
+    Ok(())
+}
+
+fn room_from_row(row: &Row) -> rusqlite::Result<RoomRecord> {
+    Ok(RoomRecord {
+        name: row.get("name")?,
+        password_hash: row.get("password_hash")?,
+        token_required: row.get("token_required")?,
+        lobby: row.get("lobby")?,
+        locked: row.get("locked")?,
+        owner: row.get("owner")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+fn meeting_from_row(row: &Row) -> rusqlite::Result<MeetingRecord> {
+    Ok(MeetingRecord {
+        id: row.get("id")?,
+        room: row.get("room")?,
+        title: row.get("title")?,
+        starts_at: row.get("starts_at")?,
+        duration_mins: row.get("duration_mins")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+impl Store for SqliteStore {
+    fn rooms(&self) -> StoreFuture<Vec<RoomRecord>> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare("SELECT * FROM rooms ORDER BY name")?;
+            let rooms = stmt.query_map([], room_from_row)?.collect();
+            rooms
+        })
+    }
+
+    fn save_room(&self, room: RoomRecord) -> StoreFuture<()> {
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO rooms (name, password_hash, token_required, lobby, locked, owner, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    room.name,
+                    room.password_hash,
+                    room.token_required,
+                    room.lobby,
+                    room.locked,
+                    room.owner,
+                    room.created_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn set_room_locked(&self, name: String, locked: bool) -> StoreFuture<()> {
+        self.run(move |conn| {
+            conn.execute("UPDATE rooms SET locked = ?2 WHERE name = ?1", params![name, locked])?;
+            Ok(())
+        })
+    }
+
+    fn delete_room(&self, name: String) -> StoreFuture<()> {
+        self.run(move |conn| {
+            conn.execute("DELETE FROM rooms WHERE name = ?1", params![name])?;
+            Ok(())
+        })
+    }
+
+    fn meetings(&self, from: u64) -> StoreFuture<Vec<MeetingRecord>> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare("SELECT * FROM meetings WHERE starts_at >= ?1 ORDER BY starts_at")?;
+            let meetings = stmt.query_map(params![from], meeting_from_row)?.collect();
+            meetings
+        })
+    }
+
+    fn meeting(&self, id: String) -> StoreFuture<Option<MeetingRecord>> {
+        self.run(move |conn| {
+            conn.query_row("SELECT * FROM meetings WHERE id = ?1", params![id], meeting_from_row)
+                .optional()
+        })
+    }
+
+    fn save_meeting(&self, meeting: MeetingRecord) -> StoreFuture<()> {
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO meetings (id, room, title, starts_at, duration_mins, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    meeting.id,
+                    meeting.room,
+                    meeting.title,
+                    meeting.starts_at,
+                    meeting.duration_mins,
+                    meeting.created_at
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete_meeting(&self, id: String) -> StoreFuture<()> {
+        self.run(move |conn| {
+            conn.execute("DELETE FROM meetings WHERE id = ?1", params![id])?;
+            Ok(())
+        })
+    }
+
+    fn calls(&self, filter: CallFilter) -> StoreFuture<Vec<CallRecord>> {
+        self.run(move |conn| {
+            let mut calls: Vec<CallRecord> = {
+                let mut stmt = conn.prepare(
+                    "SELECT id, room, started_at, ended_at FROM calls
+                     WHERE (?1 IS NULL OR room = ?1)
+                       AND (?2 IS NULL OR started_at >= ?2)
+                       AND (?3 IS NULL OR started_at < ?3)
+                     ORDER BY started_at",
+                )?;
+                let rows = stmt.query_map(params![filter.room, filter.from, filter.to], |row| {
+                    Ok(CallRecord {
+                        id: row.get(0)?,
+                        room: row.get(1)?,
+                        started_at: row.get(2)?,
+                        ended_at: row.get(3)?,
+                        participants: Vec::new(),
+                    })
+                })?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            let mut stmt = conn.prepare(
+                "SELECT client_id, name, joined_at, left_at FROM call_participants
+                 WHERE call_id = ?1 ORDER BY joined_at, client_id",
+            )?;
+            for call in calls.iter_mut() {
+                call.participants = stmt
+                    .query_map(params![call.id], |row| {
+                        Ok(CallParticipant {
+                            client_id: row.get(0)?,
+                            name: row.get(1)?,
+                            joined_at: row.get(2)?,
+                            left_at: row.get(3)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<_>>()?;
+            }
+            Ok(calls)
+        })
+    }
+
+    fn save_call(&self, call: CallRecord) -> StoreFuture<()> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT OR REPLACE INTO calls (id, room, started_at, ended_at) VALUES (?1, ?2, ?3, ?4)",
+                params![call.id, call.room, call.started_at, call.ended_at],
+            )?;
+            tx.execute("DELETE FROM call_participants WHERE call_id = ?1", params![call.id])?;
+            for participant in &call.participants {
+                tx.execute(
+                    "INSERT INTO call_participants (call_id, client_id, name, joined_at, left_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![call.id, participant.client_id, participant.name, participant.joined_at, participant.left_at],
+                )?;
+            }
+            tx.commit()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{flush_updates, spawn_update};
+
+    fn room(name: &str) -> RoomRecord {
+        RoomRecord {
+            name: name.to_string(),
+            password_hash: Some("$argon2id$hash".to_string()),
+            token_required: false,
+            lobby: true,
+            locked: false,
+            owner: Some("ops".to_string()),
+            created_at: 100,
+        }
+    }
+
+    fn participant(client_id: &str, joined_at: u64, left_at: Option<u64>) -> CallParticipant {
+        CallParticipant { client_id: client_id.to_string(), name: Some(format!("client {}", client_id)), joined_at, left_at }
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let tables: usize = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name IN ('rooms', 'meetings', 'calls', 'call_participants')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 4);
+    }
+
+    #[test]
+    fn refuses_newer_schemas() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+
+    // The update queue runs on the runtime of whoever used it first, so this is the
+    // only test that goes through it.
+    #[tokio::test]
+    async fn round_trips_through_the_update_queue() {
+        let store = SqliteStore::open(Path::new(":memory:")).unwrap();
+
+        spawn_update(store.save_room(room("standup")));
+        spawn_update(store.save_room(room("retro")));
+        spawn_update(store.set_room_locked("standup".to_string(), true));
+        spawn_update(store.delete_room("retro".to_string()));
+        let meeting = MeetingRecord {
+            id: "m1".to_string(),
+            room: "standup".to_string(),
+            title: "Weekly sync".to_string(),
+            starts_at: 1_000,
+            duration_mins: 30,
+            created_at: 100,
+        };
+        spawn_update(store.save_meeting(meeting.clone()));
+        // A call saved when it starts and again when it ends; the end must win.
+        let mut call = CallRecord {
+            id: "c1".to_string(),
+            room: "standup".to_string(),
+            started_at: 1_000,
+            ended_at: None,
+            participants: vec![participant("1", 1_000, None)],
+        };
+        spawn_update(store.save_call(call.clone()));
+        call.participants.push(participant("2", 1_010, Some(1_050)));
+        call.participants[0].left_at = Some(1_060);
+        call.ended_at = Some(1_060);
+        spawn_update(store.save_call(call.clone()));
+        flush_updates().await;
+
+        let rooms = store.rooms().await.unwrap();
+        assert_eq!(rooms, [RoomRecord { locked: true, ..room("standup") }]);
+        assert_eq!(store.meeting("m1".to_string()).await.unwrap(), Some(meeting.clone()));
+        assert_eq!(store.meetings(1_000).await.unwrap(), [meeting]);
+        assert!(store.meetings(1_001).await.unwrap().is_empty());
+        assert_eq!(store.calls(CallFilter::default()).await.unwrap(), [call.clone()]);
+        let filter = CallFilter { room: Some("standup".to_string()), from: Some(1_000), to: Some(1_001) };
+        assert_eq!(store.calls(filter).await.unwrap(), [call]);
+        let filter = CallFilter { room: Some("retro".to_string()), ..CallFilter::default() };
+        assert!(store.calls(filter).await.unwrap().is_empty());
+
+        spawn_update(store.delete_meeting("m1".to_string()));
+        flush_updates().await;
+        assert_eq!(store.meeting("m1".to_string()).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use hyper::server::conn::Http;
+use hyper::service::Service;
+use hyper::{Body, Request, Response};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::{DevCertArgs, TlsConfig};
+use crate::limits::PeerAddr;
+
+/// Hands out the current certificate to every TLS handshake.
+/// The certificate is swapped in place when the files on disk change,
+/// so existing connections keep working and new ones pick up the new key.
+#[derive(Debug)]
+struct ReloadableCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    fn new(key: CertifiedKey) -> Self {
+        ReloadableCert { current: RwLock::new(Arc::new(key)) }
+    }
+
+    fn replace(&self, key: CertifiedKey) {
+        *self.current.write().unwrap() = Arc::new(key);
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Reads the PEM certificate chain and private key from disk.
+fn load_certified_key(config: &TlsConfig) -> io::Result<CertifiedKey> {
+    let mut cert_reader = BufReader::new(File::open(&config.cert_path)?);
+    let certs = rustls_pemfile::certs(&mut cert_reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!("no certificates found in {}", config.cert_path.display())));
+    }
+
+    let mut key_reader = BufReader::new(File::open(&config.key_path)?);
+    let key = rustls_pemfile::private_key(&mut key_reader)?
+        .ok_or_else(|| invalid_data(format!("no private key found in {}", config.key_path.display())))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| invalid_data(format!("unsupported private key: {}", e)))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Polls the certificate and key files and reloads them when either changes.
+/// A broken file is logged and the previous certificate stays in use.
+fn watch_for_changes(resolver: Arc<ReloadableCert>, config: TlsConfig) {
+    // Taken before spawning so that changes made right after the first load aren't missed.
+    let mut last_seen = (modified(&config.cert_path), modified(&config.key_path));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.reload_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let current = (modified(&config.cert_path), modified(&config.key_path));
+            if current == last_seen {
+                continue;
+            }
+            last_seen = current;
+            match load_certified_key(&config) {
+                Ok(key) => {
+                    resolver.replace(key);
+                    println!("Reloaded TLS certificate from {}", config.cert_path.display());
+                }
+                Err(e) => eprintln!("Failed to reload TLS certificate, keeping the old one: {}", e),
+            }
+        }
+    });
+}
+
+/// Serves `service` over HTTPS on `addr`, terminating TLS in-process.
+pub async fn serve<S>(addr: SocketAddr, config: TlsConfig, service: S) -> io::Result<()>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let resolver = Arc::new(ReloadableCert::new(load_certified_key(&config)?));
+    watch_for_changes(resolver.clone(), config.clone());
+
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    // WebSocket upgrades need HTTP/1.1.
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Failed to accept TCP connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = service.clone();
+        tokio::spawn(async move {
+            let tls_stream = match acceptor.accept(stream).await {
+                Ok(tls_stream) => tls_stream,
+                Err(e) => {
+                    eprintln!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            // Let the filters see who is connected, like `warp::addr::remote` does for plain HTTP.
+            let service = hyper::service::service_fn(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(PeerAddr(peer));
+                service.clone().call(req)
+            });
+            let conn = Http::new()
+                .http1_only(true)
+                .serve_connection(tls_stream, service)
+                .with_upgrades();
+            if let Err(e) = conn.await {
+                eprintln!("HTTPS connection with {} ended with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Writes a self-signed certificate and key for running the stack locally over HTTPS.
+pub fn generate_dev_cert(args: &DevCertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let cert = rcgen::generate_simple_self_signed(args.names.clone())?;
+    std::fs::write(&args.cert_out, cert.serialize_pem()?)?;
+    std::fs::write(&args.key_out, cert.serialize_private_key_pem())?;
+    println!(
+        "Wrote self-signed certificate for {:?} to {} and key to {}",
+        args.names,
+        args.cert_out.display(),
+        args.key_out.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cert and key paths in a fresh temp directory.
+    fn dev_cert_args(names: &[&str]) -> DevCertArgs {
+        let dir = std::env::temp_dir().join(format!("signaling-tls-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        DevCertArgs {
+            cert_out: dir.join("cert.pem"),
+            key_out: dir.join("key.pem"),
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn tls_config(args: &DevCertArgs) -> TlsConfig {
+        TlsConfig { cert_path: args.cert_out.clone(), key_path: args.key_out.clone(), reload_secs: 1 }
+    }
+
+    fn first_cert(path: &Path) -> Vec<u8> {
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        let cert = rustls_pemfile::certs(&mut reader).next().unwrap().unwrap();
+        cert.to_vec()
+    }
+
+    fn served_cert(resolver: &ReloadableCert) -> Vec<u8> {
+        resolver.current.read().unwrap().cert[0].to_vec()
+    }
+
+    #[test]
+    fn loads_the_generated_dev_cert() {
+        let args = dev_cert_args(&["localhost"]);
+        generate_dev_cert(&args).unwrap();
+        let key = load_certified_key(&tls_config(&args)).unwrap();
+        assert_eq!(key.cert.len(), 1);
+        assert_eq!(key.cert[0].to_vec(), first_cert(&args.cert_out));
+        std::fs::remove_dir_all(args.cert_out.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_files_without_a_cert_or_key() {
+        let args = dev_cert_args(&["localhost"]);
+        generate_dev_cert(&args).unwrap();
+        std::fs::write(&args.key_out, "").unwrap();
+        assert_eq!(load_certified_key(&tls_config(&args)).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        std::fs::write(&args.cert_out, "").unwrap();
+        assert_eq!(load_certified_key(&tls_config(&args)).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(args.cert_out.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_the_new_cert_after_the_files_change() {
+        let args = dev_cert_args(&["localhost"]);
+        generate_dev_cert(&args).unwrap();
+        let config = tls_config(&args);
+        let resolver = Arc::new(ReloadableCert::new(load_certified_key(&config).unwrap()));
+        let old = served_cert(&resolver);
+        watch_for_changes(resolver.clone(), config);
+
+        let renewed = DevCertArgs { names: vec!["example.test".to_string()], ..args };
+        generate_dev_cert(&renewed).unwrap();
+        // Make sure the change is seen even on file systems with coarse timestamps.
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options().write(true).open(&renewed.cert_out).unwrap().set_modified(later).unwrap();
+        let new = first_cert(&renewed.cert_out);
+        assert_ne!(new, old);
+
+        for _ in 0..50 {
+            if served_cert(&resolver) == new {
+                std::fs::remove_dir_all(renewed.cert_out.parent().unwrap()).unwrap();
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the renewed certificate was never served");
+    }
+}
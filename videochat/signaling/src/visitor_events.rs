@@ -0,0 +1,128 @@
+use std::convert::Infallible;
+use futures::{stream, Stream};
+use warp::sse::Event;
+use warp::Reply;
+
+use crate::analytics::SharedAnalytics;
+use crate::limits::ConnectionGuard;
+use crate::rooms::Rooms;
+use crate::visitors::{VisitorHandle, Visitors};
+
+/// An SSE visitor. Dropped when the client goes away (hyper drops the response
+/// body) or the server closes the stream, which unsubscribes the visitor and
+/// releases its connection slot.
+struct Subscription {
+    /// Always `Some` until dropped; taken in `drop` so the count excludes us.
+    visitor: Option<VisitorHandle>,
+    visitors: Visitors,
+    rooms: Rooms,
+    analytics: SharedAnalytics,
+    _slot: ConnectionGuard,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        drop(self.visitor.take());
+        println!("SSE visitor disconnected");
+        crate::broadcast_visitor_count(&self.visitors, &self.rooms, &self.analytics);
+    }
+}
+
+/// `GET /visitors/events`: the `/visitors` feed as server-sent events, for networks
+/// whose proxies break WebSocket upgrades. SSE visitors subscribe to the same hub as
+/// the WebSocket ones, so `broadcast_visitor_count` and the shutdown notice reach both.
+pub async fn subscribe(
+    slot: ConnectionGuard,
+    visitors: Visitors,
+    rooms: Rooms,
+    analytics: SharedAnalytics,
+) -> Result<impl Reply, Infallible> {
+    let (visitor, count) = visitors.join();
+    println!("Added new SSE visitor. Total visitors: {}", count);
+    analytics.record_visit(slot.ip(), count);
+    // The first update lands in our own queue, so the stream starts with the stats.
+    crate::broadcast_visitor_count(&visitors, &rooms, &analytics);
+
+    let subscription = Subscription { visitor: Some(visitor), visitors, rooms, analytics, _slot: slot };
+    let events = warp::sse::keep_alive().stream(events(subscription));
+    // Ask nginx and friends not to buffer the stream.
+    Ok(warp::reply::with_header(warp::sse::reply(events), "X-Accel-Buffering", "no"))
+}
+
+/// Turns the visitor updates into SSE events until a close message ends the stream.
+fn events(subscription: Subscription) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(subscription, |mut subscription| async move {
+        let msg = subscription.visitor.as_mut()?.recv().await?;
+        let text = msg.to_str().ok()?.to_string();
+        Some((Ok(Event::default().data(text)), subscription))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use std::sync::Arc;
+    use warp::hyper::body::HttpBody;
+
+    use super::*;
+    use crate::analytics::Analytics;
+    use crate::config::Cli;
+    use crate::limits::{Limits, SharedLimits};
+    use crate::visitors::VisitorHub;
+
+    struct Fixture {
+        limits: SharedLimits,
+        visitors: Visitors,
+        rooms: Rooms,
+        analytics: SharedAnalytics,
+    }
+
+    fn fixture() -> Fixture {
+        Fixture {
+            limits: Arc::new(Limits::new(&Cli::parse_from(["signaling"]).serve)),
+            visitors: Arc::new(VisitorHub::new(16)),
+            rooms: Rooms::default(),
+            analytics: Arc::new(Analytics::load(None)),
+        }
+    }
+
+    async fn subscribe_to(fixture: &Fixture) -> warp::reply::Response {
+        let slot = fixture.limits.acquire_connection(None).unwrap();
+        let reply = subscribe(slot, fixture.visitors.clone(), fixture.rooms.clone(), fixture.analytics.clone());
+        reply.await.unwrap().into_response()
+    }
+
+    fn visitor_count(msg: &str) -> u64 {
+        let stats: serde_json::Value = serde_json::from_str(msg).unwrap();
+        stats["visitorCount"].as_u64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn starts_with_the_current_stats() {
+        let fixture = fixture();
+        let (_ws_visitor, _) = fixture.visitors.join();
+        let mut response = subscribe_to(&fixture).await;
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        let chunk = response.body_mut().data().await.unwrap().unwrap();
+        let event = std::str::from_utf8(&chunk).unwrap();
+        let data = event.lines().find_map(|line| line.strip_prefix("data:")).unwrap();
+        assert_eq!(visitor_count(data), 2);
+    }
+
+    #[tokio::test]
+    async fn leaving_updates_the_other_visitors() {
+        let fixture = fixture();
+        let (mut ws_visitor, _) = fixture.visitors.join();
+        let response = subscribe_to(&fixture).await;
+        let joined = ws_visitor.recv().await.unwrap();
+        assert_eq!(visitor_count(joined.to_str().unwrap()), 2);
+        assert_eq!(fixture.limits.open_connections(), 1);
+
+        drop(response);
+        let left = ws_visitor.recv().await.unwrap();
+        assert_eq!(visitor_count(left.to_str().unwrap()), 1);
+        assert_eq!(fixture.visitors.count(), 1);
+        assert_eq!(fixture.limits.open_connections(), 0);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::ws::Message;
+
+/// Everyone watching the visitor counter, on `/visitors` or `/visitors/events`.
+///
+/// Updates go out over a broadcast channel, so sending is a single push no matter
+/// how many visitors there are, and joining or leaving never touches a shared list.
+/// The visitor count is the number of live receivers.
+pub struct VisitorHub {
+    tx: broadcast::Sender<Message>,
+}
+
+pub type Visitors = Arc<VisitorHub>;
+
+impl VisitorHub {
+    /// `capacity` updates are buffered per visitor before the slowest ones skip ahead.
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        VisitorHub { tx }
+    }
+
+    /// Adds a visitor, returning its handle and the new visitor count.
+    /// The visitor is removed again when the handle is dropped.
+    pub fn join(&self) -> (VisitorHandle, usize) {
+        let rx = self.tx.subscribe();
+        (VisitorHandle { rx }, self.tx.receiver_count())
+    }
+
+    pub fn count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    /// Queues `msg` for every visitor.
+    pub fn send(&self, msg: Message) {
+        // Only fails when nobody is listening.
+        let _ = self.tx.send(msg);
+    }
+}
+
+/// One visitor's end of the hub.
+pub struct VisitorHandle {
+    rx: broadcast::Receiver<Message>,
+}
+
+impl VisitorHandle {
+    /// Waits for the next update. A visitor that fell behind skips the updates it
+    /// missed, since every stats message supersedes the ones before it.
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            match self.rx.recv().await {
+                Ok(msg) => return Some(msg),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn every_visitor_gets_each_update() {
+        let hub = VisitorHub::new(4);
+        let mut handles: Vec<_> = (1..=3)
+            .map(|expected| {
+                let (handle, count) = hub.join();
+                assert_eq!(count, expected);
+                handle
+            })
+            .collect();
+        hub.send(Message::text("2 online"));
+        for handle in &mut handles {
+            assert_eq!(handle.recv().await, Some(Message::text("2 online")));
+        }
+        drop(handles.pop());
+        assert_eq!(hub.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_lagging_visitor_skips_to_the_latest_updates() {
+        let hub = VisitorHub::new(2);
+        let (mut slow, _) = hub.join();
+        for i in 0..5 {
+            hub.send(Message::text(i.to_string()));
+        }
+        // Only the last `capacity` updates are still buffered.
+        assert_eq!(slow.recv().await, Some(Message::text("3")));
+        assert_eq!(slow.recv().await, Some(Message::text("4")));
+        // Falling behind doesn't cost the visitor its place.
+        assert_eq!(hub.count(), 1);
+        hub.send(Message::text("5"));
+        assert_eq!(slow.recv().await, Some(Message::text("5")));
+    }
+
+    #[tokio::test]
+    async fn visitors_see_the_end_when_the_hub_goes_away() {
+        let hub = VisitorHub::new(2);
+        let (mut handle, _) = hub.join();
+        drop(hub);
+        assert_eq!(handle.recv().await, None);
+    }
+}